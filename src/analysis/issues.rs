@@ -0,0 +1,267 @@
+//! Checks for common problems in a recording: runs of clipped samples, digital
+//! silence and DC offset.
+
+use super::Progress;
+use crate::audio_file::AudioFile;
+
+/// Samples at or above this level are taken to be at full scale
+pub const CLIP_LEVEL: f32 = 0.999;
+
+/// A DC offset larger than this is reported, about -60 dBFS
+pub const DC_OFFSET_THRESHOLD: f64 = 0.001;
+
+// Shortest run of full scale samples reported as clipping
+const MIN_CLIPPED_RUN: usize = 3;
+
+// Shortest gap of silence in all channels reported, in seconds
+const MIN_SILENCE_SECONDS: f64 = 0.05;
+
+// Stop recording issues after this many, so a badly clipped file doesn't flood the view
+const MAX_ISSUES: usize = 10000;
+
+// Number of frames read at a time
+const CHUNK_FRAMES: usize = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    /// consecutive full scale samples in a channel
+    Clipping(usize),
+    /// digital silence in every channel
+    Silence,
+}
+
+/// A problem found between samples `start` and `end`
+#[derive(Debug, Clone, Copy)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct IssueReport {
+    /// issues in order of their start
+    pub issues: Vec<Issue>,
+    /// mean of each channel
+    pub dc_offsets: Vec<f64>,
+    /// true if there were more issues than were recorded
+    pub truncated: bool,
+}
+
+// Record an issue, or only note that there were more once `MAX_ISSUES` are recorded. The
+// scan carries on either way, so that the DC offsets cover the whole region
+fn record(issues: &mut Vec<Issue>, truncated: &mut bool, issue: Issue) {
+    if issues.len() < MAX_ISSUES {
+        issues.push(issue);
+    } else {
+        *truncated = true;
+    }
+}
+
+/// check the samples from `start` to `end`. Returns `None` if cancelled
+pub fn analyse(file: &AudioFile, start: usize, end: usize, progress: &Progress) -> Option<IssueReport> {
+    let end = end.min(file.num_samples);
+    let start = start.min(end);
+    let num_channels = file.num_channels;
+    let min_silence = (file.sample_rate * MIN_SILENCE_SECONDS).ceil() as usize;
+
+    let mut issues = Vec::new();
+    let mut truncated = false;
+    let mut sums = vec![0.0f64; num_channels];
+    // Start of the current run of full scale samples in each channel, and of silence
+    let mut clipped_from: Vec<Option<usize>> = vec![None; num_channels];
+    let mut silent_from: Option<usize> = None;
+
+    let mut buffer = vec![0.0; CHUNK_FRAMES * num_channels];
+    let mut position = start;
+    while position < end {
+        if !progress.update((position - start) as f32 / (end - start) as f32) {
+            return None;
+        }
+
+        let len = CHUNK_FRAMES.min(end - position);
+        let num_frames = file.read_frames(position, &mut buffer[..len * num_channels]);
+        if num_frames == 0 {
+            break;
+        }
+
+        for (idx, frame) in buffer[..num_frames * num_channels].chunks(num_channels).enumerate() {
+            let sample_idx = position + idx;
+            let mut silent = true;
+
+            for (channel, sample) in frame.iter().enumerate() {
+                sums[channel] += *sample as f64;
+                silent &= *sample == 0.0;
+
+                if sample.abs() >= CLIP_LEVEL {
+                    clipped_from[channel].get_or_insert(sample_idx);
+                } else if let Some(run_start) = clipped_from[channel].take() {
+                    if sample_idx - run_start >= MIN_CLIPPED_RUN {
+                        record(
+                            &mut issues,
+                            &mut truncated,
+                            Issue {
+                                kind: IssueKind::Clipping(channel),
+                                start: run_start,
+                                end: sample_idx,
+                            },
+                        );
+                    }
+                }
+            }
+
+            if silent {
+                silent_from.get_or_insert(sample_idx);
+            } else if let Some(gap_start) = silent_from.take() {
+                if sample_idx - gap_start >= min_silence {
+                    record(
+                        &mut issues,
+                        &mut truncated,
+                        Issue {
+                            kind: IssueKind::Silence,
+                            start: gap_start,
+                            end: sample_idx,
+                        },
+                    );
+                }
+            }
+        }
+
+        position += num_frames;
+    }
+
+    // Runs still open at the end of the region
+    for (channel, run_start) in clipped_from.iter().enumerate() {
+        if let Some(run_start) = run_start {
+            if position - run_start >= MIN_CLIPPED_RUN {
+                record(
+                    &mut issues,
+                    &mut truncated,
+                    Issue {
+                        kind: IssueKind::Clipping(channel),
+                        start: *run_start,
+                        end: position,
+                    },
+                );
+            }
+        }
+    }
+    if let Some(gap_start) = silent_from {
+        if position - gap_start >= min_silence {
+            record(
+                &mut issues,
+                &mut truncated,
+                Issue {
+                    kind: IssueKind::Silence,
+                    start: gap_start,
+                    end: position,
+                },
+            );
+        }
+    }
+
+    let truncated = truncated || position < end;
+    issues.sort_by_key(|issue| issue.start);
+
+    let num_samples = (position - start).max(1) as f64;
+    progress.update(1.0);
+
+    Some(IssueReport {
+        issues,
+        dc_offsets: sums.iter().map(|sum| sum / num_samples).collect(),
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::open_wav;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Write a float file with a channel for each of `channels`, and check all of it
+    fn check(name: &str, channels: &[Vec<f32>]) -> IssueReport {
+        let samples: Vec<f32> = (0..channels[0].len())
+            .flat_map(|idx| channels.iter().map(move |channel| channel[idx]))
+            .collect();
+        let file = open_wav(&format!("issues_{}.wav", name), channels.len(), SAMPLE_RATE, &samples);
+        analyse(&file, 0, file.num_samples, &Progress::new()).unwrap()
+    }
+
+    /// A quiet signal with no silence, clipping or DC offset
+    fn signal(len: usize) -> Vec<f32> {
+        (0..len).map(|idx| if idx % 2 == 0 { 0.25 } else { -0.25 }).collect()
+    }
+
+    fn spans(report: &IssueReport) -> Vec<(IssueKind, usize, usize)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.kind, issue.start, issue.end))
+            .collect()
+    }
+
+    #[test]
+    fn clipping_from_three_samples() {
+        let mut samples = signal(1000);
+        samples[100..102].iter_mut().for_each(|sample| *sample = 1.0);
+        samples[200..203].iter_mut().for_each(|sample| *sample = -1.0);
+        samples[300..310].iter_mut().for_each(|sample| *sample = CLIP_LEVEL);
+        // Runs still going at the end are counted too
+        samples[997..].iter_mut().for_each(|sample| *sample = 1.0);
+        let report = check("clipping", &[samples]);
+        assert_eq!(
+            spans(&report),
+            [
+                (IssueKind::Clipping(0), 200, 203),
+                (IssueKind::Clipping(0), 300, 310),
+                (IssueKind::Clipping(0), 997, 1000)
+            ]
+        );
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn silence_in_every_channel() {
+        let min_silence = (SAMPLE_RATE as f64 * MIN_SILENCE_SECONDS) as usize;
+        let (mut left, mut right) = (signal(20000), signal(20000));
+        // Silence in one channel only isn't reported
+        left[1000..5000].iter_mut().for_each(|sample| *sample = 0.0);
+        // A gap just too short, and one just long enough
+        for channel in [&mut left, &mut right].iter_mut() {
+            channel[6000..6000 + min_silence - 1]
+                .iter_mut()
+                .for_each(|sample| *sample = 0.0);
+            channel[10000..10000 + min_silence]
+                .iter_mut()
+                .for_each(|sample| *sample = 0.0);
+        }
+        let report = check("silence", &[left, right]);
+        assert_eq!(spans(&report), [(IssueKind::Silence, 10000, 10000 + min_silence)]);
+    }
+
+    #[test]
+    fn dc_offset_sign() {
+        let up = signal(1000).iter().map(|sample| sample + 0.01).collect();
+        let down = signal(1000).iter().map(|sample| sample - 0.002).collect();
+        let report = check("dc", &[up, down, signal(1000)]);
+        assert!((report.dc_offsets[0] - 0.01).abs() < 1e-6, "{:?}", report.dc_offsets);
+        assert!((report.dc_offsets[1] + 0.002).abs() < 1e-6, "{:?}", report.dc_offsets);
+        assert!(report.dc_offsets[2].abs() < 1e-6, "{:?}", report.dc_offsets);
+    }
+
+    #[test]
+    fn issues_are_capped() {
+        // Runs of three clipped samples and one below, more of them than are kept
+        // followed by as long again of a negative offset
+        let runs_len = (MAX_ISSUES + 100) * 4;
+        let mut samples: Vec<f32> = (0..runs_len).map(|idx| if idx % 4 == 3 { 0.5 } else { 1.0 }).collect();
+        samples.resize(runs_len * 2, -0.875);
+        let report = check("capped", &[samples]);
+        assert_eq!(report.issues.len(), MAX_ISSUES);
+        assert!(report.truncated);
+        assert!(report.issues.windows(2).all(|pair| pair[0].start < pair[1].start));
+        // The offset is still measured over the whole file
+        assert!(report.dc_offsets[0].abs() < 1e-6, "{:?}", report.dc_offsets);
+    }
+}
@@ -0,0 +1,398 @@
+//! Loudness and peak levels following ITU-R BS.1770-4, EBU R128 and EBU Tech 3342.
+//!
+//! The samples are K-weighted and their mean square is summed over the channels,
+//! weighted by channel position, in 100 ms steps. Momentary loudness covers the
+//! last 400 ms, short-term loudness the last 3 s. The integrated loudness is the
+//! gated mean of the momentary blocks, and the loudness range the spread of the
+//! gated short-term values. The true peak is measured on a signal oversampled 4x by
+//! the interpolating filter given in BS.1770-4.
+
+use super::Progress;
+use crate::audio_file::AudioFile;
+use crate::utils::channel_label;
+
+/// Time between successive momentary and short-term measurements
+pub const STEP_SECONDS: f64 = 0.1;
+
+// Number of steps in a momentary and a short-term window
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+
+// Gates for the integrated loudness and the loudness range, in LUFS and LU
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+// Percentiles of the gated short-term loudness that bound the loudness range
+const RANGE_LOW_PERCENTILE: f64 = 0.10;
+const RANGE_HIGH_PERCENTILE: f64 = 0.95;
+
+// The interpolating filter used for the true peak has this many phases, each with this many taps
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+
+// The 48-tap interpolating filter of BS.1770-4 Annex 2, split into its four phases. The
+// values are those given there, which f32 holds exactly
+#[rustfmt::skip]
+#[allow(clippy::excessive_precision)]
+const TRUE_PEAK_PHASES: [[f32; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING] = [
+    [
+        0.001708984375, 0.010986328125, -0.0196533203125, 0.033203125, -0.0594482421875, 0.1373291015625,
+        0.97216796875, -0.102294921875, 0.047607421875, -0.026611328125, 0.014892578125, -0.00830078125,
+    ],
+    [
+        -0.0291748046875, 0.029296875, -0.0517578125, 0.089111328125, -0.16650390625, 0.465087890625,
+        0.77978515625, -0.2003173828125, 0.1015625, -0.0582275390625, 0.0330810546875, -0.0189208984375,
+    ],
+    [
+        -0.0189208984375, 0.0330810546875, -0.0582275390625, 0.1015625, -0.2003173828125, 0.77978515625,
+        0.465087890625, -0.16650390625, 0.089111328125, -0.0517578125, 0.029296875, -0.0291748046875,
+    ],
+    [
+        -0.00830078125, 0.014892578125, -0.026611328125, 0.047607421875, -0.102294921875, 0.97216796875,
+        0.1373291015625, -0.0594482421875, 0.033203125, -0.0196533203125, 0.010986328125, 0.001708984375,
+    ],
+];
+
+// Number of samples read from each channel at a time
+const CHUNK_SAMPLES: usize = 65536;
+
+/// The loudness and peaks of a region of a file. Levels are `None` when the region
+/// is too short or too quiet for them to be measured
+pub struct LoudnessReport {
+    pub start: usize,
+    pub end: usize,
+    /// integrated loudness in LUFS
+    pub integrated: Option<f64>,
+    /// loudness range in LU
+    pub range: Option<f64>,
+    /// highest momentary and short-term loudness in LUFS
+    pub max_momentary: Option<f64>,
+    pub max_short_term: Option<f64>,
+    /// highest sample and true peak levels in dBFS and dBTP
+    pub sample_peak: f64,
+    pub true_peak: f64,
+    /// short-term loudness in LUFS at the end of each step from `start`, `step` samples
+    /// apart. The first values cover less than the full 3 s window
+    pub short_term: Vec<f32>,
+    pub step: usize,
+}
+
+/// measure the loudness of the samples from `start` to `end`. Returns `None` if cancelled
+pub fn analyse(file: &AudioFile, start: usize, end: usize, progress: &Progress) -> Option<LoudnessReport> {
+    let end = end.min(file.num_samples);
+    let start = start.min(end);
+    let len = end - start;
+
+    let step = (file.sample_rate * STEP_SECONDS).round().max(1.0) as usize;
+    let num_steps = len / step;
+    let mut step_energy = vec![0.0f64; num_steps];
+
+    let mut sample_peak = 0.0f32;
+    let mut true_peak = 0.0f32;
+
+    let mut buffer = vec![0.0; CHUNK_SAMPLES];
+    for channel in 0..file.num_channels {
+        let weight = channel_weight(channel, file.num_channels);
+        let mut filter = KWeighting::new(file.sample_rate);
+        let mut oversampler = TruePeak::new();
+
+        let mut position = 0;
+        while position < len {
+            let done = (channel * len + position) as f32 / (file.num_channels * len) as f32;
+            if !progress.update(done) {
+                return None;
+            }
+
+            let num_samples = file.read(channel, start + position, &mut buffer[..CHUNK_SAMPLES.min(len - position)]);
+            if num_samples == 0 {
+                break;
+            }
+
+            for (idx, sample) in buffer[..num_samples].iter().enumerate() {
+                sample_peak = sample_peak.max(sample.abs());
+                true_peak = true_peak.max(oversampler.push(*sample));
+
+                let weighted = filter.process(*sample as f64);
+                if let Some(energy) = step_energy.get_mut((position + idx) / step) {
+                    *energy += weight * weighted * weighted;
+                }
+            }
+            position += num_samples;
+        }
+
+        true_peak = true_peak.max(oversampler.flush());
+    }
+
+    // Loudness of the windows ending at each step
+    let window_loudness = |steps: usize| -> Vec<f64> {
+        (steps..=num_steps)
+            .map(|last| {
+                let energy: f64 = step_energy[last - steps..last].iter().sum();
+                loudness(energy / (steps * step) as f64)
+            })
+            .collect()
+    };
+    let momentary = window_loudness(MOMENTARY_STEPS);
+    let short_term = window_loudness(SHORT_TERM_STEPS);
+
+    // The start of the curve uses what there is of the window
+    let mut curve = Vec::with_capacity(num_steps);
+    for last in 1..=num_steps {
+        let first = last.saturating_sub(SHORT_TERM_STEPS);
+        let energy: f64 = step_energy[first..last].iter().sum();
+        curve.push(loudness(energy / ((last - first) * step) as f64) as f32);
+    }
+
+    progress.update(1.0);
+
+    Some(LoudnessReport {
+        start,
+        end,
+        integrated: integrated_loudness(&momentary),
+        range: loudness_range(&short_term),
+        max_momentary: max_loudness(&momentary),
+        max_short_term: max_loudness(&short_term),
+        sample_peak: to_db(sample_peak),
+        true_peak: to_db(true_peak.max(sample_peak)),
+        short_term: curve,
+        step,
+    })
+}
+
+// The weight of each channel's mean square, by speaker position
+fn channel_weight(channel: usize, num_channels: usize) -> f64 {
+    match channel_label(channel, num_channels).as_str() {
+        "LFE" => 0.0,
+        "Ls" | "Rs" | "Lrs" | "Rrs" => 1.41,
+        _ => 1.0,
+    }
+}
+
+// Loudness in LUFS of a weighted mean square
+fn loudness(mean_square: f64) -> f64 {
+    if mean_square > 0.0 {
+        -0.691 + 10.0 * mean_square.log10()
+    } else {
+        std::f64::NEG_INFINITY
+    }
+}
+
+fn to_db(amplitude: f32) -> f64 {
+    if amplitude > 0.0 {
+        20.0 * (amplitude as f64).log10()
+    } else {
+        std::f64::NEG_INFINITY
+    }
+}
+
+fn max_loudness(values: &[f64]) -> Option<f64> {
+    values
+        .iter()
+        .cloned()
+        .filter(|value| value.is_finite())
+        .fold(None, |max: Option<f64>, value| Some(max.map_or(value, |max| max.max(value))))
+}
+
+// The mean of the momentary blocks above the absolute gate and the relative gate
+fn integrated_loudness(momentary: &[f64]) -> Option<f64> {
+    let mean_loudness = |blocks: &[f64]| -> Option<f64> {
+        if blocks.is_empty() {
+            return None;
+        }
+        let energy: f64 = blocks.iter().map(|block| 10f64.powf((block + 0.691) / 10.0)).sum();
+        Some(loudness(energy / blocks.len() as f64))
+    };
+
+    let above_absolute: Vec<f64> = momentary.iter().cloned().filter(|block| *block > ABSOLUTE_GATE).collect();
+    let relative_gate = mean_loudness(&above_absolute)? + RELATIVE_GATE;
+    let above_relative: Vec<f64> = above_absolute.into_iter().filter(|block| *block > relative_gate).collect();
+    mean_loudness(&above_relative)
+}
+
+// The spread between the 10th and 95th percentiles of the gated short-term loudness
+fn loudness_range(short_term: &[f64]) -> Option<f64> {
+    let above_absolute: Vec<f64> = short_term.iter().cloned().filter(|value| *value > ABSOLUTE_GATE).collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let energy: f64 = above_absolute.iter().map(|value| 10f64.powf((value + 0.691) / 10.0)).sum();
+    let relative_gate = loudness(energy / above_absolute.len() as f64) + RANGE_RELATIVE_GATE;
+
+    let mut gated: Vec<f64> = above_absolute.into_iter().filter(|value| *value > relative_gate).collect();
+    if gated.is_empty() {
+        return None;
+    }
+    gated.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    let percentile = |fraction: f64| gated[((gated.len() - 1) as f64 * fraction).round() as usize];
+    Some(percentile(RANGE_HIGH_PERCENTILE) - percentile(RANGE_LOW_PERCENTILE))
+}
+
+// A biquad filter in direct form I
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+// The K-weighting filter of BS.1770: a high shelf modelling the head, then a high-pass.
+// The coefficients are derived for any sample rate, matching those given for 48 kHz
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let pi = std::f64::consts::PI;
+
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (pi * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (pi * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(input))
+    }
+}
+
+// Finds the highest absolute value between samples with a polyphase interpolating filter
+struct TruePeak {
+    history: [f32; TRUE_PEAK_TAPS],
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        Self {
+            history: [0.0; TRUE_PEAK_TAPS],
+        }
+    }
+
+    // Add a sample, returning the highest absolute value interpolated around the
+    // samples at the centre of the history
+    fn push(&mut self, sample: f32) -> f32 {
+        self.history.rotate_left(1);
+        self.history[TRUE_PEAK_TAPS - 1] = sample;
+
+        TRUE_PEAK_PHASES
+            .iter()
+            .map(|taps| {
+                taps.iter()
+                    .zip(self.history.iter())
+                    .map(|(tap, sample)| tap * sample)
+                    .sum::<f32>()
+                    .abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    // Push silence through so the last samples reach the centre of the history
+    fn flush(&mut self) -> f32 {
+        (0..TRUE_PEAK_TAPS / 2).map(|_| self.push(0.0)).fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::open_wav;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// Write a stereo float file with the same signal on both channels, built from
+    /// (seconds, amplitude) sections of a sine, and measure it
+    fn measure(name: &str, frequency: f64, phase: f64, sections: &[(f64, f64)]) -> LoudnessReport {
+        let mut samples = Vec::new();
+        for (seconds, amplitude) in sections {
+            for _ in 0..(seconds * SAMPLE_RATE) as usize {
+                let t = (samples.len() / 2) as f64 / SAMPLE_RATE;
+                let sample = (amplitude * (2.0 * std::f64::consts::PI * frequency * t + phase).sin()) as f32;
+                samples.push(sample);
+                samples.push(sample);
+            }
+        }
+
+        let file = open_wav(&format!("loudness_{}.wav", name), 2, SAMPLE_RATE as u32, &samples);
+        analyse(&file, 0, file.num_samples, &Progress::new()).unwrap()
+    }
+
+    fn amplitude(dbfs: f64) -> f64 {
+        10f64.powf(dbfs / 20.0)
+    }
+
+    #[test]
+    fn sine_at_minus_23() {
+        let report = measure("sine", 997.0, 0.0, &[(20.0, amplitude(-23.0))]);
+        let integrated = report.integrated.unwrap();
+        assert!((integrated + 23.0).abs() < 0.1, "integrated {}", integrated);
+        let momentary = report.max_momentary.unwrap();
+        assert!((momentary + 23.0).abs() < 0.1, "momentary {}", momentary);
+    }
+
+    #[test]
+    fn absolute_gate() {
+        // The blocks at -72 LUFS are below the absolute gate, but not the relative gate of the mean
+        let report = measure("gate", 997.0, 0.0, &[(10.0, amplitude(-65.0)), (10.0, amplitude(-72.0))]);
+        let integrated = report.integrated.unwrap();
+        assert!((integrated + 65.0).abs() < 0.1, "integrated {}", integrated);
+
+        let report = measure("silent", 997.0, 0.0, &[(10.0, amplitude(-75.0))]);
+        assert!(report.integrated.is_none());
+    }
+
+    #[test]
+    fn range_of_two_levels() {
+        // EBU Tech 3342 case 1: 20 s at -20 LUFS then 20 s at -30 LUFS has a range of 10 LU
+        let report = measure("range", 997.0, 0.0, &[(20.0, amplitude(-20.0)), (20.0, amplitude(-30.0))]);
+        let range = report.range.unwrap();
+        assert!((range - 10.0).abs() < 1.0, "range {}", range);
+    }
+
+    #[test]
+    fn true_peak_between_samples() {
+        // A sine at a quarter of the sample rate, sampled 45 degrees from its peaks
+        let report = measure("true_peak", SAMPLE_RATE / 4.0, std::f64::consts::FRAC_PI_4, &[(1.0, 0.5)]);
+        assert!((report.sample_peak + 9.03).abs() < 0.01, "sample peak {}", report.sample_peak);
+        assert!(report.true_peak > report.sample_peak + 2.5, "true peak {}", report.true_peak);
+        assert!((report.true_peak + 6.02).abs() < 0.4, "true peak {}", report.true_peak);
+    }
+}
@@ -0,0 +1,92 @@
+//! Measurements of a region of an audio file. Each runs on its own worker thread
+//! and reports its progress so the UI can show it.
+
+pub mod issues;
+pub mod loudness;
+
+use crate::audio_file::AudioFile;
+use crate::utils::Flag;
+use basedrop::Shared;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/// Shared between an analysis and its worker thread, to report progress and check for cancellation
+#[derive(Clone)]
+pub struct Progress {
+    cancel: Flag,
+    // The fraction done, as the bits of an f32
+    fraction: Arc<AtomicU32>,
+}
+
+impl Progress {
+    /// create progress for work that isn't run as an `Analysis`, which is never cancelled
+    pub fn new() -> Self {
+        Self {
+            cancel: Flag::new(),
+            fraction: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// record the fraction of the work done so far. Returns false if the analysis
+    /// has been cancelled, in which case the worker should stop
+    pub fn update(&self, fraction: f32) -> bool {
+        self.fraction.store(fraction.to_bits(), Ordering::Relaxed);
+        !self.cancel.is_set()
+    }
+
+    fn fraction(&self) -> f32 {
+        f32::from_bits(self.fraction.load(Ordering::Relaxed))
+    }
+}
+
+/// An analysis of the samples between `start` and `end` running on a worker thread.
+/// Dropping it cancels the analysis.
+pub struct Analysis<T> {
+    pub start: usize,
+    pub end: usize,
+    rx: Receiver<T>,
+    progress: Progress,
+}
+
+impl<T: Send + 'static> Analysis<T> {
+    /// run `analyse` on a new thread. It returns `None` if it was cancelled
+    pub fn start<F>(file: Shared<AudioFile>, start: usize, end: usize, analyse: F) -> Self
+    where
+        F: FnOnce(&AudioFile, usize, usize, &Progress) -> Option<T> + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let progress = Progress::new();
+
+        let thread_progress = progress.clone();
+        thread::spawn(move || {
+            if let Some(result) = analyse(&file, start, end, &thread_progress) {
+                let _ = tx.send(result);
+            }
+        });
+
+        Self {
+            start,
+            end,
+            rx,
+            progress,
+        }
+    }
+
+    /// the fraction of the analysis done so far
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    /// return the result, without blocking, once the analysis has finished
+    pub fn try_recv(&self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Drop for Analysis<T> {
+    fn drop(&mut self) {
+        self.progress.cancel.set();
+    }
+}
@@ -1,67 +1,115 @@
-use crate::utils::deinterleave;
-use hound::{SampleFormat, WavReader};
+use crate::decoder::{self, Samples};
+use crate::editor::{EditedSamples, Segment};
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use std::sync::Arc;
 
-/// An audio file, loaded into memory
+/// An audio file. Uncompressed files are memory-mapped and read on demand,
+/// other formats are decoded into memory. Edited files read their samples through
+/// a list of segments.
 pub struct AudioFile {
-    /// The sample data
-    pub data: Vec<f32>,
+    /// The interleaved sample data
+    samples: Arc<Samples>,
     /// Sample rate of the audio file
     pub sample_rate: f64,
     /// number of channels in the audio file
     pub num_channels: usize,
     /// number of sample sin the audio file
     pub num_samples: usize,
+    /// format description and metadata
+    pub info: FileInfo,
 }
 
 impl AudioFile {
 
-    /// return a buffer of samples corresponding to a channel in the audio file
-    #[allow(dead_code)]
-    pub fn get_channel(&self, idx: usize) -> &'_ [f32] {
-        debug_assert!(idx < self.num_channels);
-        let start = self.num_samples * idx;
-        &self.data[start..(start + self.num_samples)]
+    /// copy samples of a channel, starting at sample `start`, into `out`.
+    /// Returns the number of samples copied, which is less than `out.len()`
+    /// at the end of the file
+    pub fn read(&self, channel: usize, start: usize, out: &mut [f32]) -> usize {
+        debug_assert!(channel < self.num_channels);
+        let start = start.min(self.num_samples);
+        let len = out.len().min(self.num_samples - start);
+        self.samples.read_channel(self.num_channels, channel, start, &mut out[..len]);
+        len
     }
 
-    /// open a file
-    pub fn open(path: &str) -> Result<Self, hound::Error> {
-        let mut reader = WavReader::open(path)?;
-        let spec = reader.spec();
-        let mut data = Vec::with_capacity((spec.channels as usize) * (reader.duration() as usize));
-        match (spec.bits_per_sample, spec.sample_format) {
-            (16, SampleFormat::Int) => {
-                for sample in reader.samples::<i16>() {
-                    data.push((sample? as f32) / (0x7fffi32 as f32));
-                }
-            }
-            (24, SampleFormat::Int) => {
-                for sample in reader.samples::<i32>() {
-                    let val = (sample? as f32) / (0x00ff_ffffi32 as f32);
-                    data.push(val);
-                }
-            }
-            (32, SampleFormat::Int) => {
-                for sample in reader.samples::<i32>() {
-                    data.push((sample? as f32) / (0x7fff_ffffi32 as f32));
-                }
-            }
-            (32, SampleFormat::Float) => {
-                for sample in reader.samples::<f32>() {
-                    data.push(sample?);
-                }
-            }
-            _ => return Err(hound::Error::Unsupported),
+    /// copy interleaved frames, starting at sample `start`, into `out`.
+    /// Returns the number of frames copied
+    pub fn read_frames(&self, start: usize, out: &mut [f32]) -> usize {
+        let start = start.min(self.num_samples);
+        let frames = (out.len() / self.num_channels).min(self.num_samples - start);
+        self.samples
+            .read_frames(self.num_channels, start, &mut out[..frames * self.num_channels]);
+        frames
+    }
+
+    /// return a single sample of a channel, or 0.0 past the end of the file
+    pub fn sample(&self, channel: usize, idx: usize) -> f32 {
+        let mut sample = [0.0];
+        self.read(channel, idx, &mut sample);
+        sample[0]
+    }
+
+    /// returns true if the samples are read from disk on demand
+    pub fn is_mapped(&self) -> bool {
+        matches!(*self.samples, Samples::Mapped(_))
+    }
+
+    /// the segments the samples are read from, a single one if the file hasn't been edited
+    pub fn segments(&self) -> Vec<Segment> {
+        match &*self.samples {
+            Samples::Edited(edited) => edited.segments().to_vec(),
+            _ => vec![Segment::Source {
+                start: 0,
+                len: self.num_samples,
+            }],
+        }
+    }
+
+    /// a copy of this file with its samples read from `segments`, which refer to the
+    /// samples decoded from the file
+    pub fn with_segments(&self, segments: Vec<Segment>) -> Self {
+        let source = match &*self.samples {
+            Samples::Edited(edited) => Arc::clone(edited.source()),
+            _ => Arc::clone(&self.samples),
+        };
+        let edited = EditedSamples::new(source, self.num_channels, segments);
+
+        Self {
+            num_samples: edited.num_frames(),
+            samples: Arc::new(Samples::Edited(edited)),
+            sample_rate: self.sample_rate,
+            num_channels: self.num_channels,
+            info: self.info.clone(),
+        }
+    }
+
+    /// duration of the audio file in seconds
+    pub fn duration(&self) -> f64 {
+        self.num_samples as f64 / self.sample_rate
+    }
+
+    /// open a file in any of the supported formats
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let decoded = decoder::decode(path)?;
+        let num_channels = decoded.num_channels;
+
+        if num_channels == 0 {
+            return Err(AppError::EmptyFile);
+        }
+
+        // Any trailing partial frame is ignored
+        let num_samples = decoded.samples.num_frames(num_channels);
+        if num_samples == 0 {
+            return Err(AppError::EmptyFile);
         }
 
-        let mut deinterleaved = vec![0.0; data.len()];
-        let num_channels = spec.channels as usize;
-        let num_samples = deinterleaved.len() / num_channels;
-        deinterleave(&data, &mut deinterleaved, num_channels);
         Ok(Self {
-            data: deinterleaved,
-            sample_rate: spec.sample_rate as f64,
+            samples: Arc::new(decoded.samples),
+            sample_rate: decoded.sample_rate,
             num_channels,
             num_samples,
+            info: decoded.info,
         })
     }
-}
\ No newline at end of file
+}
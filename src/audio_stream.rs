@@ -1,6 +1,20 @@
+use crate::sample_player::SamplePlayer;
 use crate::utils::interleave;
-use cpal::traits::{DeviceTrait, HostTrait};
-use cpal::Stream;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, HostId, SampleFormat, SampleRate, Stream, StreamConfig, SupportedBufferSize};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Sample rates offered for a device, where it supports them
+pub const SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+
+/// Buffer sizes in frames offered for a device, where it supports them
+pub const BUFFER_SIZES: [u32; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+// How often the player's messages are taken while there is no stream
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
 
 /// The playback context is used by the audio callback to map data from the audio
 /// file to the playback buffer.
@@ -19,26 +33,145 @@ impl<'a> PlaybackContext<'a> {
     }
 }
 
+/// The output chosen by the user. Anything not chosen uses the default of the host or device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputSettings {
+    pub host: Option<HostId>,
+    /// name of the device
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// frames per buffer
+    pub buffer_size: Option<u32>,
+}
+
+/// An output device and the settings from `SAMPLE_RATES` and `BUFFER_SIZES` it supports
+#[derive(Debug, Clone)]
+pub struct OutputDevice {
+    pub name: String,
+    /// whether the host uses this device when none is chosen
+    pub is_default: bool,
+    pub sample_rates: Vec<u32>,
+    pub buffer_sizes: Vec<u32>,
+}
+
+/// The device and format of a running stream
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    pub host: HostId,
+    pub device: String,
+    pub sample_rate: u32,
+    pub num_channels: usize,
+    /// `None` when the host chooses the buffer size
+    pub buffer_size: Option<u32>,
+}
+
+/// the hosts that can be used on this machine
+pub fn output_hosts() -> Vec<HostId> {
+    cpal::available_hosts()
+}
+
+/// list the output devices of a host, or of the default host
+pub fn output_devices(host: Option<HostId>) -> Result<Vec<OutputDevice>, String> {
+    let host = match host {
+        Some(id) => cpal::host_from_id(id).map_err(|err| err.to_string())?,
+        None => cpal::default_host(),
+    };
+
+    let default_name = host.default_output_device().and_then(|device| device.name().ok());
+    let devices = host.output_devices().map_err(|err| err.to_string())?;
+    Ok(devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            // Streams are opened with f32 samples
+            let configs: Vec<_> = device
+                .supported_output_configs()
+                .map(|configs| configs.filter(|config| config.sample_format() == SampleFormat::F32).collect())
+                .unwrap_or_default();
+
+            let sample_rates = SAMPLE_RATES
+                .iter()
+                .copied()
+                .filter(|rate| {
+                    configs
+                        .iter()
+                        .any(|config| config.min_sample_rate().0 <= *rate && *rate <= config.max_sample_rate().0)
+                })
+                .collect();
+            let buffer_sizes = BUFFER_SIZES
+                .iter()
+                .copied()
+                .filter(|size| {
+                    configs.iter().any(|config| match config.buffer_size() {
+                        SupportedBufferSize::Range { min, max } => min <= size && size <= max,
+                        SupportedBufferSize::Unknown => false,
+                    })
+                })
+                .collect();
+
+            Some(OutputDevice {
+                is_default: default_name.as_ref() == Some(&name),
+                name,
+                sample_rates,
+                buffer_sizes,
+            })
+        })
+        .collect())
+}
+
 /// start the audio stream
-pub fn audio_stream(mut main_callback: impl FnMut(PlaybackContext) + Send + 'static) -> Stream {
-    
-    
-    println!("Audio Stream");
-    
-    let host = cpal::default_host();
-    let output_device = host.default_output_device().expect("no output found");
-    let config = output_device
-        .default_output_config()
-        .expect("no default output config")
-        .config();
+pub fn audio_stream(
+    settings: &OutputSettings,
+    mut main_callback: impl FnMut(PlaybackContext) + Send + 'static,
+) -> Result<(Stream, OutputInfo), String> {
+    let host = match settings.host {
+        Some(id) => cpal::host_from_id(id).map_err(|err| err.to_string())?,
+        None => cpal::default_host(),
+    };
+    let output_device = match settings.device.as_ref() {
+        Some(name) => host
+            .output_devices()
+            .map_err(|err| err.to_string())?
+            .find(|device| device.name().ok().as_ref() == Some(name))
+            .ok_or_else(|| format!("{} is not connected", name))?,
+        None => host.default_output_device().ok_or("no output device found")?,
+    };
+
+    // The default config may not be f32, or not at the chosen rate, so the stream uses a
+    // supported f32 config at that rate, with the default's channels where one has them
+    let default_config = output_device.default_output_config().ok();
+    let sample_rate = settings
+        .sample_rate
+        .map(SampleRate)
+        .or_else(|| default_config.as_ref().map(|config| config.sample_rate()));
+    let default_channels = default_config.as_ref().map(|config| config.channels());
+    let supported = output_device
+        .supported_output_configs()
+        .map_err(|err| err.to_string())?
+        .filter(|config| config.sample_format() == SampleFormat::F32)
+        .filter(|config| {
+            sample_rate.map_or(true, |rate| {
+                config.min_sample_rate() <= rate && rate <= config.max_sample_rate()
+            })
+        })
+        .max_by_key(|config| Some(config.channels()) == default_channels)
+        .ok_or_else(|| match sample_rate {
+            Some(rate) => format!("the device can't play 32 bit float at {} Hz", rate.0),
+            None => "the device can't play 32 bit float".to_string(),
+        })?;
+    let supported = match sample_rate {
+        Some(rate) => supported.with_sample_rate(rate),
+        None => supported.with_max_sample_rate(),
+    };
+    let config = StreamConfig {
+        buffer_size: settings.buffer_size.map_or(BufferSize::Default, BufferSize::Fixed),
+        ..supported.config()
+    };
 
     let sample_rate = config.sample_rate.0 as f64;
     let num_channels = config.channels as usize;
     let mut output_buffer = vec![];
-    let mut input_buffer = vec![];
 
     output_buffer.resize_with(1 << 16, || 0.0);
-    input_buffer.resize_with(1 << 16, || 0.0);
 
     let callback = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
         let buffer_size = data.len() / num_channels;
@@ -61,7 +194,86 @@ pub fn audio_stream(mut main_callback: impl FnMut(PlaybackContext) + Send + 'sta
         interleave(&output_buffer, data, num_channels);
     };
 
-    output_device
+    let stream = output_device
         .build_output_stream(&config, callback, |err| eprintln!("{}", err))
-        .expect("failed to open stream")
-}
\ No newline at end of file
+        .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+
+    let info = OutputInfo {
+        host: host.id(),
+        device: output_device.name().unwrap_or_default(),
+        sample_rate: config.sample_rate.0,
+        num_channels,
+        buffer_size: settings.buffer_size,
+    };
+    Ok((stream, info))
+}
+
+/// Plays the sample player through an output stream, which is rebuilt whenever the
+/// settings change. Streams can't be moved between threads on every platform, so they
+/// are opened and dropped on a thread of their own. When a stream can't be opened
+/// nothing is played, and the rest of the app carries on without audio.
+pub struct AudioOutput {
+    tx: Sender<OutputSettings>,
+    rx: Receiver<Result<OutputInfo, String>>,
+}
+
+impl AudioOutput {
+    /// open a stream on a new thread, reporting the result through `try_recv`
+    pub fn start(player: SamplePlayer, settings: OutputSettings) -> Self {
+        let (tx, settings_rx) = channel::<OutputSettings>();
+        let (result_tx, rx) = channel();
+
+        // The player is locked by the running stream, or by this thread while there is
+        // none, so the audio callback never waits for it
+        let player = Arc::new(Mutex::new(player));
+        thread::spawn(move || {
+            let mut stream = None;
+            loop {
+                let settings = match settings_rx.recv_timeout(IDLE_INTERVAL) {
+                    Ok(settings) => settings,
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Without a stream the controller's messages are still taken, so it
+                        // never waits on a full queue
+                        if stream.is_none() {
+                            if let Ok(mut player) = player.lock() {
+                                player.receive_messages();
+                            }
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                drop(stream.take());
+
+                let stream_player = Arc::clone(&player);
+                let result = audio_stream(&settings, move |mut context| {
+                    if let Ok(mut player) = stream_player.try_lock() {
+                        player.advance(&mut context);
+                    }
+                })
+                .map(|(new_stream, info)| {
+                    stream = Some(new_stream);
+                    info
+                });
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let output = Self { tx, rx };
+        output.configure(settings);
+        output
+    }
+
+    /// replace the stream with one opened with new settings
+    pub fn configure(&self, settings: OutputSettings) {
+        let _ = self.tx.send(settings);
+    }
+
+    /// return the result of opening the last stream, once, without blocking
+    pub fn try_recv(&self) -> Option<Result<OutputInfo, String>> {
+        self.rx.try_recv().ok()
+    }
+}
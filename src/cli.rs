@@ -0,0 +1,789 @@
+//! Commands that run without opening a window, for checking and converting many files
+//! at once. Each command takes any number of files and directories, which are searched
+//! for audio files, and prints one result per file, as indented text or as one JSON
+//! object per line with `--json`.
+
+use crate::analysis::issues::{self, IssueKind, DC_OFFSET_THRESHOLD};
+use crate::analysis::loudness;
+use crate::analysis::Progress;
+use crate::audio_file::AudioFile;
+use crate::decoder;
+use crate::export::{self, BitDepth, ExportSettings};
+use crate::render::{self, ChannelLayout, RenderOptions, UnitsMode, RMS_MIN_SAMPLES_PER_PIXEL};
+use crate::utils::{channel_label, json_string};
+use crate::waveform::to_f32;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: wav_viewer [FILE]
+       wav_viewer COMMAND [OPTIONS] PATH...
+
+Commands, run on each audio file given or found in the directories given:
+  info        format, sample rate, channels and length
+  peaks       peak, rms and DC offset of each channel, and the waveform with --width
+  loudness    EBU R128 loudness and true peak
+  check       clipping, digital silence and DC offset
+  render-png  draw the waveform to a PNG image
+  render-svg  draw the waveform to an SVG image
+  convert     write the audio to a WAV file
+
+Options:
+  --json           print one JSON object per file
+  --width N        waveform width in pixels (images: 800)
+  --height N       image height in pixels (images: 200)
+  --units U        image scale, linear or db
+  --layout L       image channels, stacked, overlaid or a channel number
+  --background C   image background colour, as #rrggbb or #rrggbbaa
+  --color C        image waveform colour
+  --rms-color C    image rms colour, or none to leave the rms out
+  --output PATH    output file, or directory when there is more than one input.
+                   Files found in a directory keep their path below it
+  --bits 16|24|32f bit depth for convert (default 24)
+  --dither         add TPDF dither when converting to 16 or 24 bits
+
+The exit status is 1 if any file could not be processed or, for check, had a problem.
+";
+
+// Number of samples read from each channel at a time
+const CHUNK_SAMPLES: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Info,
+    Peaks,
+    Loudness,
+    Check,
+    RenderPng,
+    RenderSvg,
+    Convert,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "info" => Some(Command::Info),
+            "peaks" => Some(Command::Peaks),
+            "loudness" => Some(Command::Loudness),
+            "check" => Some(Command::Check),
+            "render-png" => Some(Command::RenderPng),
+            "render-svg" => Some(Command::RenderSvg),
+            "convert" => Some(Command::Convert),
+            _ => None,
+        }
+    }
+}
+
+struct Options {
+    json: bool,
+    width: Option<usize>,
+    height: Option<usize>,
+    output: Option<String>,
+    bit_depth: BitDepth,
+    dither: bool,
+    // The width and height are only used when given
+    render: RenderOptions,
+    paths: Vec<String>,
+}
+
+// A value in the output, printed as text or JSON
+enum Value {
+    Int(u64),
+    // A number and the decimal places it is printed with
+    Float(f64, usize),
+    Bool(bool),
+    Text(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn to_text(&self) -> String {
+        match self {
+            Value::Int(value) => value.to_string(),
+            Value::Float(value, decimals) => format!("{:.*}", decimals, value),
+            Value::Bool(value) => value.to_string(),
+            Value::Text(text) => text.clone(),
+            Value::List(values) => values.iter().map(Value::to_text).collect::<Vec<_>>().join(", "),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Value::Int(value) => value.to_string(),
+            // -inf dB for silence has no JSON number
+            Value::Float(value, _) if !value.is_finite() => "null".to_string(),
+            Value::Float(value, decimals) => format!("{:.*}", decimals, value),
+            Value::Bool(value) => value.to_string(),
+            Value::Text(text) => json_string(text),
+            Value::List(values) => {
+                format!("[{}]", values.iter().map(Value::to_json).collect::<Vec<_>>().join(","))
+            }
+        }
+    }
+}
+
+// The result for one file
+struct Record {
+    fields: Vec<(&'static str, Value)>,
+    // false if a check found a problem
+    passed: bool,
+}
+
+impl Record {
+    fn new(path: &str) -> Self {
+        Self {
+            fields: vec![("path", Value::Text(path.to_string()))],
+            passed: true,
+        }
+    }
+
+    fn add(&mut self, name: &'static str, value: Value) {
+        self.fields.push((name, value));
+    }
+
+    fn to_json(&self) -> String {
+        let members: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), value.to_json()))
+            .collect();
+        format!("{{{}}}", members.join(","))
+    }
+
+    fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.to_json());
+        } else {
+            for (idx, (name, value)) in self.fields.iter().enumerate() {
+                if idx == 0 {
+                    println!("{}", value.to_text());
+                } else {
+                    println!("  {}: {}", name, value.to_text());
+                }
+            }
+        }
+    }
+}
+
+/// run the command named by the first argument, after the program name, and return the
+/// exit status. Returns `None` if the arguments aren't a command, to open the GUI instead
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = match args.first().map(String::as_str) {
+        Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            return Some(0);
+        }
+        Some(name) => Command::from_name(name)?,
+        None => return None,
+    };
+
+    let options = match parse_options(&args[1..]) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return Some(2);
+        }
+    };
+
+    let mut status = 0;
+    let mut files = Vec::new();
+    for path in options.paths.iter() {
+        if let Err(err) = find_audio_files(Path::new(path), Path::new(path), &mut files) {
+            print_error(path, &err.to_string(), options.json);
+            status = 1;
+        }
+    }
+    if files.is_empty() {
+        eprintln!("No audio files found");
+        return Some(1);
+    }
+
+    // Several outputs go into a directory
+    let output_dir = options
+        .output
+        .as_ref()
+        .filter(|output| files.len() > 1 || Path::new(output).is_dir());
+    if let Some(dir) = output_dir {
+        if let Err(err) = fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir, err);
+            return Some(1);
+        }
+    }
+
+    let mut outputs = HashSet::new();
+    for input in files.iter() {
+        let path = input.path.to_string_lossy().to_string();
+        let output = match (output_dir, options.output.as_ref()) {
+            (Some(dir), _) => {
+                let output = output_path(&Path::new(dir).join(&input.relative), command);
+                // Inputs that differ only in their extension, or named twice, would write the same file
+                if !outputs.insert(output.clone()) {
+                    status = 1;
+                    print_error(&path, &format!("{} is written for another input", output), options.json);
+                    continue;
+                }
+                if let Some(Err(err)) = Path::new(&output).parent().map(fs::create_dir_all) {
+                    status = 1;
+                    print_error(&path, &err.to_string(), options.json);
+                    continue;
+                }
+                Some(output)
+            }
+            (None, Some(output)) => Some(output.clone()),
+            (None, None) if matches!(command, Command::RenderPng | Command::RenderSvg) => {
+                Some(output_path(&input.path, command))
+            }
+            (None, None) => None,
+        };
+
+        match process(command, &path, output.as_deref(), &options) {
+            Ok(record) => {
+                if !record.passed {
+                    status = 1;
+                }
+                record.print(options.json);
+            }
+            Err(err) => {
+                status = 1;
+                print_error(&path, &err, options.json);
+            }
+        }
+    }
+
+    Some(status)
+}
+
+// Errors are part of the JSON output, so that every file has a line
+fn print_error(path: &str, err: &str, json: bool) {
+    if json {
+        let mut record = Record::new(path);
+        record.add("error", Value::Text(err.to_string()));
+        record.print(true);
+    } else {
+        eprintln!("{}: {}", path, err);
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        json: false,
+        width: None,
+        height: None,
+        output: None,
+        bit_depth: BitDepth::Int24,
+        dither: false,
+        render: RenderOptions::default(),
+        paths: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--dither" => options.dither = true,
+            "--width" => options.width = Some(parse_size("--width", &value("--width")?)?),
+            "--height" => options.height = Some(parse_size("--height", &value("--height")?)?),
+            "--output" | "-o" => options.output = Some(value("--output")?),
+            "--bits" => {
+                let bits = value("--bits")?;
+                options.bit_depth = BitDepth::ALL
+                    .iter()
+                    .copied()
+                    .find(|depth| depth.name() == bits || depth.bits().to_string() == bits)
+                    .ok_or_else(|| format!("unknown bit depth {}, use 16, 24 or 32f", bits))?;
+            }
+            "--units" => {
+                options.render.units = match value("--units")?.as_str() {
+                    "linear" => UnitsMode::Linear,
+                    "db" => UnitsMode::Decibel,
+                    units => return Err(format!("unknown units {}, use linear or db", units)),
+                };
+            }
+            "--layout" => {
+                let layout = value("--layout")?;
+                options.render.layout = match layout.as_str() {
+                    "stacked" => ChannelLayout::Stacked,
+                    "overlaid" => ChannelLayout::Overlaid,
+                    channel => match channel.parse::<usize>() {
+                        Ok(channel) if channel > 0 => ChannelLayout::Single(channel - 1),
+                        _ => {
+                            return Err(format!(
+                                "unknown layout {}, use stacked, overlaid or a channel number",
+                                layout
+                            ))
+                        }
+                    },
+                };
+            }
+            "--background" => options.render.background = parse_color("--background", &value("--background")?)?,
+            "--color" => options.render.peak_color = parse_color("--color", &value("--color")?)?,
+            "--rms-color" => {
+                let color = value("--rms-color")?;
+                options.render.rms_color = match color.as_str() {
+                    "none" => None,
+                    color => Some(parse_color("--rms-color", color)?),
+                };
+            }
+            option if option.starts_with('-') && option.len() > 1 => {
+                return Err(format!("unknown option {}", option));
+            }
+            path => options.paths.push(path.to_string()),
+        }
+    }
+
+    if options.paths.is_empty() {
+        return Err("no files given".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_color(name: &str, text: &str) -> Result<render::Color, String> {
+    render::parse_color(text).ok_or_else(|| format!("{} must be a colour such as #3232ff, not {}", name, text))
+}
+
+fn parse_size(name: &str, text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("{} must be a whole number above 0, not {}", name, text)),
+    }
+}
+
+// An audio file to process, and the path of its output below an output directory
+struct Input {
+    path: PathBuf,
+    relative: PathBuf,
+}
+
+// Add a file, or the audio files in a directory and those below it, in name order. Files
+// found in a directory are placed in the output directory by their path below `root`
+fn find_audio_files(path: &Path, root: &Path, files: &mut Vec<Input>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        fs::metadata(path)?;
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) if relative != Path::new("") => relative.to_path_buf(),
+            _ => PathBuf::from(path.file_name().unwrap_or_default()),
+        };
+        files.push(Input {
+            path: path.to_path_buf(),
+            relative,
+        });
+        return Ok(());
+    }
+
+    let extensions = decoder::extensions();
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            find_audio_files(&entry, root, files)?;
+        } else {
+            let extension = entry
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if extensions.contains(&extension.as_str()) {
+                find_audio_files(&entry, root, files)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The file written for an input, with the extension of the command's output
+fn output_path(path: &Path, command: Command) -> String {
+    let extension = match command {
+        Command::RenderPng => "png",
+        Command::RenderSvg => "svg",
+        _ => "wav",
+    };
+    path.with_extension(extension).to_string_lossy().to_string()
+}
+
+fn process(command: Command, path: &str, output: Option<&str>, options: &Options) -> Result<Record, String> {
+    let file = AudioFile::open(path).map_err(|err| err.to_string())?;
+    let progress = Progress::new();
+    let mut record = Record::new(path);
+
+    match command {
+        Command::Info => {
+            record.add("format", Value::Text(file.info.format.clone()));
+            record.add("sample_format", Value::Text(file.info.sample_format.clone()));
+            if let Some(bits) = file.info.bits_per_sample {
+                record.add("bits_per_sample", Value::Int(bits as u64));
+            }
+            record.add("sample_rate", Value::Float(file.sample_rate, 0));
+            record.add("channels", Value::Int(file.num_channels as u64));
+            record.add("samples", Value::Int(file.num_samples as u64));
+            record.add("duration", Value::Float(file.duration(), 3));
+            record.add("markers", Value::Int(file.info.cues.len() as u64));
+            if let Some(bext) = file.info.bext.as_ref() {
+                record.add("description", Value::Text(bext.description.clone()));
+            }
+        }
+
+        Command::Peaks => {
+            let levels = channel_levels(&file);
+            let list = |values: Vec<f64>, decimals| {
+                Value::List(values.into_iter().map(|value| Value::Float(value, decimals)).collect())
+            };
+            record.add(
+                "channels",
+                Value::List(
+                    (0..file.num_channels)
+                        .map(|channel| Value::Text(channel_label(channel, file.num_channels)))
+                        .collect(),
+                ),
+            );
+            record.add(
+                "peak_dbfs",
+                list(levels.iter().map(|level| to_db(level.peak)).collect(), 2),
+            );
+            record.add(
+                "rms_dbfs",
+                list(levels.iter().map(|level| to_db(level.rms)).collect(), 2),
+            );
+            record.add(
+                "dc_offset",
+                list(levels.iter().map(|level| level.dc_offset).collect(), 5),
+            );
+
+            // Each pixel is the min, max and rms of the samples it covers
+            if let Some(width) = options.width {
+                let waveforms = render::fit_peaks(&file, width);
+                record.add(
+                    "waveform",
+                    Value::List(
+                        waveforms
+                            .iter()
+                            .map(|peaks| {
+                                Value::List(
+                                    peaks
+                                        .iter()
+                                        .map(|(min, max, rms)| {
+                                            Value::List(vec![
+                                                Value::Float(to_f32(*min) as f64, 4),
+                                                Value::Float(to_f32(*max) as f64, 4),
+                                                Value::Float(to_f32(*rms) as f64, 4),
+                                            ])
+                                        })
+                                        .collect(),
+                                )
+                            })
+                            .collect(),
+                    ),
+                );
+            }
+        }
+
+        Command::Loudness => {
+            let report = loudness::analyse(&file, 0, file.num_samples, &progress).ok_or("cancelled")?;
+            let level = |value: Option<f64>| Value::Float(value.unwrap_or(std::f64::NEG_INFINITY), 1);
+            record.add("integrated_lufs", level(report.integrated));
+            record.add("range_lu", Value::Float(report.range.unwrap_or(std::f64::NAN), 1));
+            record.add("max_momentary_lufs", level(report.max_momentary));
+            record.add("max_short_term_lufs", level(report.max_short_term));
+            record.add("sample_peak_dbfs", Value::Float(report.sample_peak, 1));
+            record.add("true_peak_dbtp", Value::Float(report.true_peak, 1));
+        }
+
+        Command::Check => {
+            let report = issues::analyse(&file, 0, file.num_samples, &progress).ok_or("cancelled")?;
+            let clipped = report
+                .issues
+                .iter()
+                .filter(|issue| matches!(issue.kind, IssueKind::Clipping(_)))
+                .count();
+            let silent = report.issues.len() - clipped;
+            let dc_offset = report
+                .dc_offsets
+                .iter()
+                .any(|offset| offset.abs() > DC_OFFSET_THRESHOLD);
+
+            record.add("clipped", Value::Int(clipped as u64));
+            record.add("silent", Value::Int(silent as u64));
+            record.add(
+                "dc_offset",
+                Value::List(
+                    report
+                        .dc_offsets
+                        .iter()
+                        .map(|offset| Value::Float(*offset, 5))
+                        .collect(),
+                ),
+            );
+            record.add("truncated", Value::Bool(report.truncated));
+            record.passed = clipped == 0 && silent == 0 && !dc_offset;
+            record.add("passed", Value::Bool(record.passed));
+        }
+
+        Command::RenderPng | Command::RenderSvg => {
+            let output = output.ok_or("no output file")?;
+            let mut render_options = options.render.clone();
+            render_options.width = options.width.unwrap_or(render_options.width);
+            render_options.height = options.height.unwrap_or(render_options.height);
+            if let ChannelLayout::Single(channel) = render_options.layout {
+                if channel >= file.num_channels {
+                    return Err(format!("there is no channel {}", channel + 1));
+                }
+            }
+            // As in the window, the rms is left out where it would cover the min/max
+            if (file.num_samples as f32 / render_options.width as f32) < RMS_MIN_SAMPLES_PER_PIXEL {
+                render_options.rms_color = None;
+            }
+
+            let peaks = render::fit_peaks(&file, render_options.width);
+            if command == Command::RenderPng {
+                render::render_png(&peaks, &render_options)
+                    .save(output)
+                    .map_err(|err| err.to_string())?;
+            } else {
+                fs::write(output, render::render_svg(&peaks, &render_options)).map_err(|err| err.to_string())?;
+            }
+            record.add("output", Value::Text(output.to_string()));
+        }
+
+        Command::Convert => {
+            let output = output.ok_or("convert needs --output")?;
+            let settings = ExportSettings {
+                channels: (0..file.num_channels).collect(),
+                bit_depth: options.bit_depth,
+                dither: options.dither,
+            };
+            export::export(&file, 0, file.num_samples, path, output, &settings, &progress)
+                .ok_or("cancelled")?
+                .map_err(|err| err.to_string())?;
+            record.add("output", Value::Text(output.to_string()));
+            record.add("bits", Value::Text(options.bit_depth.name().to_string()));
+        }
+    }
+
+    Ok(record)
+}
+
+struct ChannelLevel {
+    peak: f64,
+    rms: f64,
+    dc_offset: f64,
+}
+
+fn channel_levels(file: &AudioFile) -> Vec<ChannelLevel> {
+    let mut buffer = vec![0.0; CHUNK_SAMPLES];
+    (0..file.num_channels)
+        .map(|channel| {
+            let (mut peak, mut sum, mut sum_sq) = (0.0f64, 0.0f64, 0.0f64);
+            let mut position = 0;
+            while position < file.num_samples {
+                let len = file.read(channel, position, &mut buffer);
+                if len == 0 {
+                    break;
+                }
+                for sample in buffer[..len].iter().map(|sample| *sample as f64) {
+                    peak = peak.max(sample.abs());
+                    sum += sample;
+                    sum_sq += sample * sample;
+                }
+                position += len;
+            }
+
+            let count = file.num_samples.max(1) as f64;
+            ChannelLevel {
+                peak,
+                rms: (sum_sq / count).sqrt(),
+                dc_offset: sum / count,
+            }
+        })
+        .collect()
+}
+
+fn to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        20.0 * amplitude.log10()
+    } else {
+        std::f64::NEG_INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{float_spec, temp_path, write_wav};
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn parse_error(text: &str) -> String {
+        match parse_options(&args(text)) {
+            Ok(_) => panic!("{} should not parse", text),
+            Err(err) => err,
+        }
+    }
+
+    // A fresh directory holding `files`, as paths below it
+    fn temp_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = temp_path(name);
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_wav(&path, float_spec(1, 44100), &[0.5f32, -0.25, 0.0, 0.125]);
+        }
+        dir
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse_options(&args(
+            "--json --width 640 --height 120 -o out --bits 16 --dither --units db --layout 2 \
+             --background #000000 --color #ff000080 --rms-color none a.wav b",
+        ))
+        .unwrap();
+        assert!(options.json && options.dither);
+        assert_eq!((options.width, options.height), (Some(640), Some(120)));
+        assert_eq!(options.output.as_deref(), Some("out"));
+        assert_eq!(options.bit_depth, BitDepth::Int16);
+        assert_eq!(options.render.units, UnitsMode::Decibel);
+        assert_eq!(options.render.layout, ChannelLayout::Single(1));
+        assert_eq!(options.render.background, [0, 0, 0, 255]);
+        assert_eq!(options.render.peak_color, [255, 0, 0, 128]);
+        assert_eq!(options.render.rms_color, None);
+        assert_eq!(options.paths, args("a.wav b"));
+
+        let options = parse_options(&args("--bits 32f a.wav")).unwrap();
+        assert_eq!(options.bit_depth, BitDepth::Float32);
+        assert_eq!(options.render.layout, ChannelLayout::Stacked);
+        assert_eq!(options.width, None);
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        for option in [
+            "--width", "--height", "--output", "-o", "--bits", "--units", "--layout", "--color",
+        ]
+        .iter()
+        {
+            let err = parse_error(&format!("a.wav {}", option));
+            assert!(err.ends_with("needs a value"), "{}: {}", option, err);
+        }
+        assert_eq!(parse_error("--json"), "no files given");
+        assert_eq!(parse_error(""), "no files given");
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        for (text, expected) in [
+            ("--width 0", "--width must be a whole number above 0, not 0"),
+            ("--height -3", "--height must be a whole number above 0, not -3"),
+            ("--width wide", "--width must be a whole number above 0, not wide"),
+            ("--bits 8", "unknown bit depth 8, use 16, 24 or 32f"),
+            ("--units log", "unknown units log, use linear or db"),
+            (
+                "--layout 0",
+                "unknown layout 0, use stacked, overlaid or a channel number",
+            ),
+            (
+                "--layout left",
+                "unknown layout left, use stacked, overlaid or a channel number",
+            ),
+            ("--color #12345", "--color must be a colour such as #3232ff, not #12345"),
+            (
+                "--rms-color blue",
+                "--rms-color must be a colour such as #3232ff, not blue",
+            ),
+            ("--verbose", "unknown option --verbose"),
+        ]
+        .iter()
+        {
+            assert_eq!(parse_error(&format!("a.wav {}", text)), *expected);
+        }
+    }
+
+    #[test]
+    fn audio_files_keep_their_path_below_the_directory() {
+        let dir = temp_dir("cli_find", &["b.wav", "a.wav", "sub/c.wav", "sub/deeper/d.WAV"]);
+        fs::write(dir.join("notes.txt"), "not audio").unwrap();
+        fs::write(dir.join("sub/wav"), "no extension").unwrap();
+
+        let mut files = Vec::new();
+        find_audio_files(&dir, &dir, &mut files).unwrap();
+        // A file given by itself is placed by its name
+        find_audio_files(&dir.join("sub/c.wav"), &dir.join("sub/c.wav"), &mut files).unwrap();
+        let relative: Vec<PathBuf> = files.iter().map(|input| input.relative.clone()).collect();
+        assert_eq!(
+            relative,
+            ["a.wav", "b.wav", "sub/c.wav", "sub/deeper/d.WAV", "c.wav"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+        assert!(files.iter().all(|input| input.path.is_file()));
+        assert!(find_audio_files(&dir.join("missing.wav"), &dir, &mut files).is_err());
+
+        let out = Path::new("out");
+        assert_eq!(
+            output_path(&out.join(&files[3].relative), Command::Convert),
+            "out/sub/deeper/d.wav"
+        );
+        assert_eq!(
+            output_path(&out.join(&files[0].relative), Command::RenderPng),
+            "out/a.png"
+        );
+        assert_eq!(
+            output_path(&out.join(&files[0].relative), Command::RenderSvg),
+            "out/a.svg"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inputs_writing_the_same_output_are_errors() {
+        let dir = temp_dir("cli_collide", &["tone.wav", "sub/tone.wav", "sub/tone.WAV"]);
+        let out = temp_path("cli_collide_out");
+        let _ = fs::remove_dir_all(&out);
+
+        // sub/tone.WAV converts to the same file as sub/tone.wav, so only it fails
+        let status = run(&args(&format!("convert --output {} {}", out.display(), dir.display())));
+        assert_eq!(status, Some(1));
+        assert!(out.join("tone.wav").is_file());
+        assert!(out.join("sub/tone.wav").is_file());
+        let written = fs::read_dir(out.join("sub")).unwrap().count();
+        assert_eq!(written, 1);
+
+        // As is a file named twice
+        let file = dir.join("tone.wav");
+        let status = run(&args(&format!(
+            "convert -o {} {} {}",
+            out.display(),
+            file.display(),
+            file.display()
+        )));
+        assert_eq!(status, Some(1));
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn silence_is_null_in_json() {
+        let path = temp_path("cli_silence.wav");
+        write_wav(&path, float_spec(2, 44100), &[0.0f32, 0.5, 0.0, -0.5]);
+        let path = path.to_string_lossy().to_string();
+        let options = parse_options(&args("--json x")).unwrap();
+        let record = process(Command::Peaks, &path, None, &options).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            record.to_json(),
+            format!(
+                "{{\"path\":{},\"channels\":[\"L\",\"R\"],\"peak_dbfs\":[null,-6.02],\"rms_dbfs\":[null,-6.02],\
+                 \"dc_offset\":[0.00000,0.00000]}}",
+                json_string(&path)
+            )
+        );
+        assert_eq!(Value::Float(std::f64::NAN, 1).to_json(), "null");
+        assert_eq!(Value::Float(std::f64::NEG_INFINITY, 1).to_text(), "-inf");
+    }
+}
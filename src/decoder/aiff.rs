@@ -0,0 +1,172 @@
+use super::{DecodedAudio, Decoder, Samples};
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+/// Decodes uncompressed AIFF and AIFF-C files
+pub struct AiffDecoder;
+
+/// Sample encodings found in the AIFF-C `COMM` compression type
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    BigEndianInt,
+    LittleEndianInt,
+    Float32,
+    Float64,
+}
+
+impl Decoder for AiffDecoder {
+    fn probe(&self, header: &[u8]) -> bool {
+        header.len() >= 12
+            && &header[0..4] == b"FORM"
+            && (&header[8..12] == b"AIFF" || &header[8..12] == b"AIFC")
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["aif", "aiff", "aifc"]
+    }
+
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError> {
+        let bytes = fs::read(path)?;
+        if !self.probe(&bytes) {
+            return Err(malformed("missing FORM header"));
+        }
+        let is_aifc = &bytes[8..12] == b"AIFC";
+
+        let mut num_channels = 0;
+        let mut num_frames = 0;
+        let mut bits_per_sample = 0;
+        let mut sample_rate = 0.0;
+        let mut encoding = Encoding::BigEndianInt;
+        let mut sound_data = None;
+
+        // Walk the chunks following the FORM header
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let size = read_u32(&bytes[pos + 4..]) as usize;
+            let body = &bytes[(pos + 8).min(bytes.len())..(pos + 8 + size).min(bytes.len())];
+
+            match id {
+                b"COMM" => {
+                    if body.len() < 18 {
+                        return Err(malformed("COMM chunk too short"));
+                    }
+                    num_channels = read_u16(&body[0..]) as usize;
+                    num_frames = read_u32(&body[2..]) as usize;
+                    bits_per_sample = read_u16(&body[6..]) as usize;
+                    sample_rate = read_extended(body[8..18].try_into().unwrap());
+                    if is_aifc && body.len() >= 22 {
+                        encoding = match &body[18..22] {
+                            b"NONE" | b"twos" => Encoding::BigEndianInt,
+                            b"sowt" => Encoding::LittleEndianInt,
+                            b"fl32" | b"FL32" => Encoding::Float32,
+                            b"fl64" | b"FL64" => Encoding::Float64,
+                            other => {
+                                return Err(AppError::UnsupportedFormat(format!(
+                                    "AIFF-C compression '{}'",
+                                    String::from_utf8_lossy(other)
+                                )))
+                            }
+                        };
+                    }
+                }
+                b"SSND" => {
+                    if body.len() < 8 {
+                        return Err(malformed("SSND chunk too short"));
+                    }
+                    let offset = read_u32(&body[0..]) as usize;
+                    sound_data = Some(&body[(8 + offset).min(body.len())..]);
+                }
+                _ => {}
+            }
+
+            // Chunks are padded to an even length
+            pos += 8 + size + (size & 1);
+        }
+
+        let sound_data = sound_data.ok_or_else(|| malformed("missing SSND chunk"))?;
+        if num_channels == 0 || sample_rate <= 0.0 {
+            return Err(malformed("missing or invalid COMM chunk"));
+        }
+
+        let bytes_per_sample = match encoding {
+            Encoding::Float32 => 4,
+            Encoding::Float64 => 8,
+            _ => (bits_per_sample + 7) / 8,
+        };
+        if bytes_per_sample == 0 || (bytes_per_sample > 4 && encoding != Encoding::Float64) {
+            return Err(AppError::UnsupportedFormat(format!(
+                "{}-bit AIFF",
+                bits_per_sample
+            )));
+        }
+
+        // Integer samples are left-justified in their container
+        let full_scale = ((1i64 << (bytes_per_sample * 8 - 1)) - 1) as f32;
+        let num_samples = (num_frames * num_channels).min(sound_data.len() / bytes_per_sample);
+        let data = sound_data
+            .chunks_exact(bytes_per_sample)
+            .take(num_samples)
+            .map(|sample| match encoding {
+                Encoding::BigEndianInt => read_int(sample.iter().copied(), bytes_per_sample) as f32 / full_scale,
+                Encoding::LittleEndianInt => read_int(sample.iter().rev().copied(), bytes_per_sample) as f32 / full_scale,
+                Encoding::Float32 => f32::from_be_bytes(sample.try_into().unwrap()),
+                Encoding::Float64 => f64::from_be_bytes(sample.try_into().unwrap()) as f32,
+            })
+            .collect();
+
+        Ok(DecodedAudio {
+            samples: Samples::Memory(data),
+            sample_rate,
+            num_channels,
+            info: FileInfo {
+                format: if is_aifc { "AIFF-C" } else { "AIFF" }.to_string(),
+                sample_format: match encoding {
+                    Encoding::Float32 | Encoding::Float64 => "IEEE float",
+                    _ => "PCM integer",
+                }
+                .to_string(),
+                bits_per_sample: Some(match encoding {
+                    Encoding::Float32 => 32,
+                    Encoding::Float64 => 64,
+                    _ => bits_per_sample as u16,
+                }),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+fn malformed(msg: &str) -> AppError {
+    AppError::FileReadError(format!("malformed AIFF: {}", msg))
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Read a signed integer from its bytes, most significant first
+fn read_int(bytes: impl Iterator<Item = u8>, num_bytes: usize) -> i32 {
+    let value = bytes.fold(0u32, |acc, byte| (acc << 8) | byte as u32);
+    // Sign extend from the container width
+    let shift = 32 - num_bytes * 8;
+    ((value << shift) as i32) >> shift
+}
+
+/// Convert an 80-bit IEEE 754 extended precision float, used for the AIFF sample rate
+fn read_extended(bytes: [u8; 10]) -> f64 {
+    let sign = if bytes[0] & 0x80 != 0 { -1.0 } else { 1.0 };
+    let exponent = (((bytes[0] & 0x7f) as i32) << 8) | bytes[1] as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    sign * mantissa as f64 * 2f64.powi(exponent - 16383 - 63)
+}
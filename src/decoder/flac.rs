@@ -0,0 +1,52 @@
+use super::{DecodedAudio, Decoder, Samples};
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use claxon::FlacReader;
+use std::path::Path;
+
+/// Decodes FLAC files using claxon
+pub struct FlacDecoder;
+
+impl Decoder for FlacDecoder {
+    fn probe(&self, header: &[u8]) -> bool {
+        header.starts_with(b"fLaC")
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["flac"]
+    }
+
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError> {
+        let mut reader = FlacReader::open(path).map_err(flac_error)?;
+        let info = reader.streaminfo();
+
+        // claxon returns samples as integers in the range of the stream's bit depth
+        let full_scale = ((1i64 << (info.bits_per_sample - 1)) - 1) as f32;
+        let mut data = Vec::with_capacity(
+            info.samples.unwrap_or(0) as usize * info.channels as usize,
+        );
+        for sample in reader.samples() {
+            data.push(sample.map_err(flac_error)? as f32 / full_scale);
+        }
+
+        Ok(DecodedAudio {
+            samples: Samples::Memory(data),
+            sample_rate: info.sample_rate as f64,
+            num_channels: info.channels as usize,
+            info: FileInfo {
+                format: "FLAC".to_string(),
+                sample_format: "PCM integer".to_string(),
+                bits_per_sample: Some(info.bits_per_sample as u16),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+fn flac_error(err: claxon::Error) -> AppError {
+    match err {
+        claxon::Error::IoError(err) => err.into(),
+        claxon::Error::Unsupported(msg) => AppError::UnsupportedFormat(msg.to_string()),
+        err => AppError::FileReadError(err.to_string()),
+    }
+}
@@ -0,0 +1,216 @@
+//! Decoders for the audio file formats that can be opened. Every decoder
+//! produces the same interleaved `f32` layout. WAV files are memory-mapped
+//! and converted on demand, while other formats are decoded into memory.
+//! Formats other than WAV are each behind a cargo feature.
+
+#[cfg(feature = "aiff")]
+mod aiff;
+#[cfg(feature = "flac")]
+mod flac;
+#[cfg(feature = "mp3")]
+mod mp3;
+#[cfg(feature = "vorbis")]
+mod vorbis;
+pub mod wav;
+
+use crate::editor::EditedSamples;
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub use wav::MappedSamples;
+
+/// Interleaved sample data, normalised to the range [-1.0, 1.0]
+pub enum Samples {
+    /// Samples decoded into memory
+    Memory(Vec<f32>),
+    /// Samples read on demand from a memory-mapped file
+    Mapped(MappedSamples),
+    /// Samples of a file that has been edited, read through its segments
+    Edited(EditedSamples),
+}
+
+impl Samples {
+    /// number of whole frames of `num_channels` samples
+    pub fn num_frames(&self, num_channels: usize) -> usize {
+        match self {
+            Samples::Memory(data) => data.len() / num_channels,
+            Samples::Mapped(mapped) => mapped.num_frames(),
+            Samples::Edited(edited) => edited.num_frames(),
+        }
+    }
+
+    /// copy interleaved frames starting at `start` into `out`, which must
+    /// hold a whole number of frames that are all in range
+    pub fn read_frames(&self, num_channels: usize, start: usize, out: &mut [f32]) {
+        match self {
+            Samples::Memory(data) => {
+                let offset = start * num_channels;
+                out.copy_from_slice(&data[offset..offset + out.len()]);
+            }
+            Samples::Mapped(mapped) => mapped.read_frames(start, out),
+            Samples::Edited(edited) => edited.read_frames(start, out),
+        }
+    }
+
+    /// copy the samples of one channel starting at frame `start` into `out`,
+    /// which must be in range
+    pub fn read_channel(&self, num_channels: usize, channel: usize, start: usize, out: &mut [f32]) {
+        match self {
+            Samples::Memory(data) => {
+                for (idx, sample) in out.iter_mut().enumerate() {
+                    *sample = data[(start + idx) * num_channels + channel];
+                }
+            }
+            Samples::Mapped(mapped) => mapped.read_channel(channel, start, out),
+            Samples::Edited(edited) => edited.read_channel(channel, start, out),
+        }
+    }
+}
+
+/// Audio opened from a file
+pub struct DecodedAudio {
+    /// Interleaved sample data
+    pub samples: Samples,
+    /// Sample rate of the decoded audio
+    pub sample_rate: f64,
+    /// Number of interleaved channels
+    pub num_channels: usize,
+    /// Format description and any metadata found in the file
+    pub info: FileInfo,
+}
+
+/// A decoder for one audio file format
+pub trait Decoder {
+    /// Returns true if a file starting with `header` is in this decoder's format
+    fn probe(&self, header: &[u8]) -> bool;
+
+    /// File name extensions used for this format, in lower case
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Decode the file, either into memory or by mapping it
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError>;
+}
+
+/// The decoders compiled into this build, in the order they are probed
+fn decoders() -> Vec<Box<dyn Decoder>> {
+    #[allow(unused_mut)]
+    let mut decoders: Vec<Box<dyn Decoder>> = vec![Box::new(wav::WavDecoder)];
+    #[cfg(feature = "aiff")]
+    decoders.push(Box::new(aiff::AiffDecoder));
+    #[cfg(feature = "flac")]
+    decoders.push(Box::new(flac::FlacDecoder));
+    #[cfg(feature = "vorbis")]
+    decoders.push(Box::new(vorbis::VorbisDecoder));
+    // MP3 has the weakest signature so it is probed last
+    #[cfg(feature = "mp3")]
+    decoders.push(Box::new(mp3::Mp3Decoder));
+    decoders
+}
+
+/// The file name extensions of every format that can be opened, in lower case
+pub fn extensions() -> Vec<&'static str> {
+    decoders().iter().flat_map(|decoder| decoder.extensions().iter().copied()).collect()
+}
+
+/// Decode a file with whichever decoder recognises its header
+pub fn decode(path: &str) -> Result<DecodedAudio, AppError> {
+    let path = Path::new(path);
+
+    let mut header = [0u8; 16];
+    let mut file = File::open(path)?;
+    let len = file.read(&mut header)?;
+    let header = &header[..len];
+
+    for decoder in decoders() {
+        if decoder.probe(header) {
+            return decoder.decode(path);
+        }
+    }
+
+    Err(AppError::UnsupportedFormat(format!(
+        "unrecognised file type: {}",
+        path.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode a file of the fixtures and return its interleaved samples
+    fn decode_fixture(name: &str) -> (DecodedAudio, Vec<f32>) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("sounds").join(name);
+        let decoded = decode(path.to_str().unwrap()).unwrap();
+        let num_frames = decoded.samples.num_frames(decoded.num_channels);
+        let mut samples = vec![0.0; num_frames * decoded.num_channels];
+        decoded.samples.read_frames(decoded.num_channels, 0, &mut samples);
+        (decoded, samples)
+    }
+
+    /// Check that a fixture decodes to the same audio as kick2.wav, to within an RMS
+    /// difference of `tolerance`. Lossy encoders may add up to `max_extra` frames,
+    /// before or after the audio, so the best lined up of those starts is compared
+    fn matches_wav(name: &str, max_extra: usize, tolerance: f32) {
+        let (expected, expected_samples) = decode_fixture("kick2.wav");
+        let (decoded, samples) = decode_fixture(name);
+        assert_eq!(decoded.sample_rate, expected.sample_rate, "{}", name);
+        assert_eq!(decoded.num_channels, expected.num_channels, "{}", name);
+        let num_channels = decoded.num_channels;
+        let (len, expected_len) = (samples.len() / num_channels, expected_samples.len() / num_channels);
+        assert!(
+            len >= expected_len && len <= expected_len + max_extra,
+            "{}: {} frames for {}",
+            name,
+            len,
+            expected_len
+        );
+
+        let rms_difference = |start: usize| {
+            let samples = &samples[start * num_channels..];
+            let sum: f32 = samples
+                .iter()
+                .zip(expected_samples.iter())
+                .map(|(a, b)| (a - b).powi(2))
+                .sum();
+            (sum / expected_samples.len() as f32).sqrt()
+        };
+        let difference = (0..=len - expected_len).map(rms_difference).fold(f32::MAX, f32::min);
+        assert!(difference <= tolerance, "{}: rms difference {}", name, difference);
+    }
+
+    #[test]
+    fn wav() {
+        let (decoded, _) = decode_fixture("kick2.wav");
+        assert_eq!(decoded.sample_rate, 44100.0);
+        assert_eq!(decoded.num_channels, 2);
+        assert_eq!(decoded.samples.num_frames(2), 8269);
+    }
+
+    #[cfg(feature = "aiff")]
+    #[test]
+    fn aiff() {
+        matches_wav("kick2.aif", 0, 0.0);
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac() {
+        matches_wav("kick2.flac", 0, 0.0);
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn vorbis() {
+        matches_wav("kick2.ogg", 2048, 0.01);
+    }
+
+    // MP3 encoders delay the start by up to a frame and a half, and pad the end to a frame
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3() {
+        matches_wav("kick2.mp3", 2880, 0.01);
+    }
+}
@@ -0,0 +1,87 @@
+use super::{DecodedAudio, Decoder, Samples};
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use minimp3::ffi;
+use std::path::Path;
+
+/// Decodes MPEG-1/2 layer III files using minimp3
+pub struct Mp3Decoder;
+
+impl Decoder for Mp3Decoder {
+    fn probe(&self, header: &[u8]) -> bool {
+        // Either an ID3v2 tag or an MPEG audio frame sync word
+        header.starts_with(b"ID3")
+            || (header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0)
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mp3"]
+    }
+
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError> {
+        // The whole file is decoded into memory, so it is read in one go and handed to the
+        // C decoder directly. The streaming `minimp3::Decoder` buffers through slice-deque,
+        // which fails debug assertions on current compilers.
+        let bytes = std::fs::read(path)?;
+        let mut decoder = Box::new(unsafe { std::mem::zeroed::<ffi::mp3dec_t>() });
+        unsafe { ffi::mp3dec_init(&mut *decoder) };
+
+        let mut pcm = vec![0i16; minimp3::MAX_SAMPLES_PER_FRAME];
+        let mut data = Vec::new();
+        let mut sample_rate = 0;
+        let mut num_channels = 0;
+        let mut position = 0;
+        while position < bytes.len() {
+            let remaining = &bytes[position..];
+            let mut frame = unsafe { std::mem::zeroed::<ffi::mp3dec_frame_info_t>() };
+            let num_frames = unsafe {
+                ffi::mp3dec_decode_frame(
+                    &mut *decoder,
+                    remaining.as_ptr(),
+                    remaining.len().min(i32::MAX as usize) as i32,
+                    pcm.as_mut_ptr(),
+                    &mut frame,
+                )
+            } as usize;
+            // No frame in the rest of the file
+            if frame.frame_bytes <= 0 {
+                break;
+            }
+            position += frame.frame_bytes as usize;
+            // Tags and other data between frames decode to nothing
+            if num_frames == 0 {
+                continue;
+            }
+
+            // The first frame decides the layout of the file
+            let channels = frame.channels as usize;
+            if num_channels == 0 {
+                sample_rate = frame.hz;
+                num_channels = channels;
+            }
+            if channels != num_channels || frame.hz != sample_rate {
+                return Err(AppError::UnsupportedFormat(
+                    "mp3 stream changes channel count or sample rate".to_string(),
+                ));
+            }
+            let samples = &pcm[..num_frames * num_channels];
+            data.extend(samples.iter().map(|sample| *sample as f32 / (0x7fffi32 as f32)));
+        }
+
+        if num_channels == 0 {
+            return Err(AppError::FileReadError("no mp3 frames found".to_string()));
+        }
+
+        Ok(DecodedAudio {
+            samples: Samples::Memory(data),
+            sample_rate: sample_rate as f64,
+            num_channels,
+            info: FileInfo {
+                format: "MP3".to_string(),
+                sample_format: "MPEG layer III".to_string(),
+                bits_per_sample: None,
+                ..Default::default()
+            },
+        })
+    }
+}
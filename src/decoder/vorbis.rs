@@ -0,0 +1,50 @@
+use super::{DecodedAudio, Decoder, Samples};
+use crate::error::AppError;
+use crate::file_info::FileInfo;
+use lewton::inside_ogg::OggStreamReader;
+use lewton::samples::InterleavedSamples;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Decodes Ogg Vorbis files using lewton
+pub struct VorbisDecoder;
+
+impl Decoder for VorbisDecoder {
+    fn probe(&self, header: &[u8]) -> bool {
+        header.starts_with(b"OggS")
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ogg", "oga"]
+    }
+
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError> {
+        let file = BufReader::new(File::open(path)?);
+        let mut reader = OggStreamReader::new(file).map_err(vorbis_error)?;
+
+        let mut data = Vec::new();
+        while let Some(packet) = reader
+            .read_dec_packet_generic::<InterleavedSamples<f32>>()
+            .map_err(vorbis_error)?
+        {
+            data.extend_from_slice(&packet.samples);
+        }
+
+        Ok(DecodedAudio {
+            samples: Samples::Memory(data),
+            sample_rate: reader.ident_hdr.audio_sample_rate as f64,
+            num_channels: reader.ident_hdr.audio_channels as usize,
+            info: FileInfo {
+                format: "Ogg".to_string(),
+                sample_format: "Vorbis".to_string(),
+                bits_per_sample: None,
+                ..Default::default()
+            },
+        })
+    }
+}
+
+fn vorbis_error(err: lewton::VorbisError) -> AppError {
+    AppError::FileReadError(err.to_string())
+}
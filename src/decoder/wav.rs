@@ -0,0 +1,475 @@
+use super::{DecodedAudio, Decoder, Samples};
+use crate::error::AppError;
+use crate::file_info::{parse_bext, parse_cue, parse_list, parse_smpl, FileInfo};
+use memmap2::Mmap;
+use std::convert::{TryFrom, TryInto};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Opens RIFF WAVE files with integer PCM samples of 1 to 32 bits, or
+/// 32 and 64-bit float samples. The data chunk is memory-mapped rather than
+/// decoded, so opening a file takes the same time regardless of its length.
+pub struct WavDecoder;
+
+/// The samples of a wav file's data chunk, converted to `f32` as they are read
+pub struct MappedSamples {
+    map: Mmap,
+    /// offset of the data chunk within the file
+    offset: usize,
+    num_frames: usize,
+    format: WavFormat,
+}
+
+impl MappedSamples {
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// the bytes of one sample
+    #[inline]
+    fn sample_bytes(&self, frame: usize, channel: usize) -> &[u8] {
+        let start = self.offset
+            + (frame * self.format.num_channels + channel) * self.format.bytes_per_sample;
+        &self.map[start..start + self.format.bytes_per_sample]
+    }
+
+    /// copy interleaved frames starting at `start` into `out`
+    pub fn read_frames(&self, start: usize, out: &mut [f32]) {
+        let num_channels = self.format.num_channels;
+        for (idx, sample) in out.iter_mut().enumerate() {
+            let bytes = self.sample_bytes(start + idx / num_channels, idx % num_channels);
+            *sample = self.format.decode_sample(bytes);
+        }
+    }
+
+    /// copy the samples of one channel starting at frame `start` into `out`
+    pub fn read_channel(&self, channel: usize, start: usize, out: &mut [f32]) {
+        for (idx, sample) in out.iter_mut().enumerate() {
+            *sample = self.format.decode_sample(self.sample_bytes(start + idx, channel));
+        }
+    }
+}
+
+/// How the samples of a wav file are encoded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleEncoding {
+    Int,
+    Float,
+}
+
+/// The sample layout described by the `fmt ` chunk
+#[derive(Clone, Copy, Debug)]
+pub struct WavFormat {
+    pub encoding: SampleEncoding,
+    pub num_channels: usize,
+    pub sample_rate: u32,
+    /// number of significant bits in each sample
+    pub bits_per_sample: u16,
+    /// number of bytes each sample is stored in
+    pub bytes_per_sample: usize,
+}
+
+/// The position of a chunk within a RIFF file
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkInfo {
+    pub id: [u8; 4],
+    /// offset of the chunk body from the start of the file
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl WavFormat {
+    /// Parse the body of a `fmt ` chunk
+    pub fn parse(body: &[u8]) -> Result<Self, AppError> {
+        if body.len() < 16 {
+            return Err(malformed("fmt chunk too short"));
+        }
+
+        let mut format_tag = u16::from_le_bytes([body[0], body[1]]);
+        let num_channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+        let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+        let block_align = u16::from_le_bytes([body[12], body[13]]) as usize;
+        let container_bits = u16::from_le_bytes([body[14], body[15]]);
+        let mut bits_per_sample = container_bits;
+
+        // WAVE_FORMAT_EXTENSIBLE stores the real format in the first two bytes
+        // of the sub-format GUID, and may have fewer valid bits than the container
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            if body.len() < 40 {
+                return Err(malformed("extensible fmt chunk too short"));
+            }
+            let valid_bits = u16::from_le_bytes([body[18], body[19]]);
+            if valid_bits > 0 && valid_bits <= container_bits {
+                bits_per_sample = valid_bits;
+            }
+            format_tag = u16::from_le_bytes([body[24], body[25]]);
+        }
+
+        if num_channels == 0 {
+            return Err(malformed("no channels"));
+        }
+        if sample_rate == 0 {
+            return Err(malformed("zero sample rate"));
+        }
+
+        let bytes_per_sample = block_align / num_channels;
+        let encoding = match (format_tag, bytes_per_sample, bits_per_sample) {
+            (WAVE_FORMAT_PCM, 1..=4, 1..=32) => SampleEncoding::Int,
+            (WAVE_FORMAT_IEEE_FLOAT, 4, 32) | (WAVE_FORMAT_IEEE_FLOAT, 8, 64) => SampleEncoding::Float,
+            _ => {
+                return Err(AppError::UnsupportedFormat(format!(
+                    "wav format tag {:#06x} with {} bits per sample",
+                    format_tag, bits_per_sample
+                )))
+            }
+        };
+
+        if bits_per_sample as usize > bytes_per_sample * 8 {
+            return Err(malformed("bits per sample larger than block size"));
+        }
+
+        Ok(Self {
+            encoding,
+            num_channels,
+            sample_rate,
+            bits_per_sample,
+            bytes_per_sample,
+        })
+    }
+
+    /// Convert a single little-endian sample to the range [-1.0, 1.0]
+    pub fn decode_sample(&self, bytes: &[u8]) -> f32 {
+        match self.encoding {
+            SampleEncoding::Float if self.bytes_per_sample == 8 => {
+                f64::from_le_bytes(bytes[..8].try_into().unwrap()) as f32
+            }
+            SampleEncoding::Float => f32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            SampleEncoding::Int => {
+                let container_bits = self.bytes_per_sample as u32 * 8;
+                let raw = bytes[..self.bytes_per_sample]
+                    .iter()
+                    .rev()
+                    .fold(0u32, |acc, byte| (acc << 8) | *byte as u32);
+
+                // 8-bit samples are unsigned, every other width is signed
+                let value = if container_bits == 8 {
+                    raw as i32 - 128
+                } else {
+                    let shift = 32 - container_bits;
+                    ((raw << shift) as i32) >> shift
+                };
+
+                // Samples are left-justified in the container, so drop the padding bits
+                let value = value >> (container_bits - self.bits_per_sample as u32);
+                let full_scale = ((1i64 << (self.bits_per_sample - 1)) - 1).max(1) as f32;
+                value as f32 / full_scale
+            }
+        }
+    }
+}
+
+/// List the chunks of a RIFF WAVE file without reading their contents
+pub fn read_chunks<R: Read + Seek>(reader: &mut R) -> Result<Vec<ChunkInfo>, AppError> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(malformed("missing RIFF WAVE header"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= file_len {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header)?;
+        let id = chunk_header[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+
+        // Truncated files, or files from streaming writers that never filled
+        // in the chunk size, are read up to the end of the file
+        let offset = pos + 8;
+        let size = size.min(file_len - offset);
+        chunks.push(ChunkInfo { id, offset, size });
+
+        // Chunks are padded to an even length
+        pos = offset + size + (size & 1);
+    }
+
+    Ok(chunks)
+}
+
+/// Read the body of a chunk into memory
+pub fn read_chunk<R: Read + Seek>(reader: &mut R, chunk: &ChunkInfo) -> Result<Vec<u8>, AppError> {
+    let mut body = vec![0; chunk.size as usize];
+    reader.seek(SeekFrom::Start(chunk.offset))?;
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Find the first chunk with the given id
+pub fn find_chunk<'a>(chunks: &'a [ChunkInfo], id: &[u8; 4]) -> Option<&'a ChunkInfo> {
+    chunks.iter().find(|chunk| &chunk.id == id)
+}
+
+/// Add a chunk to the end of a wav file, updating the RIFF size
+pub fn append_chunk(path: &str, id: &[u8; 4], body: &[u8]) -> io::Result<()> {
+    let too_large = || io::Error::new(ErrorKind::InvalidInput, "file is too large for WAV");
+    let size = u32::try_from(body.len()).map_err(|_| too_large())?;
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    // Chunks start on an even offset, and writers don't always pad the last one
+    if pos & 1 == 1 {
+        file.write_all(&[0])?;
+        pos += 1;
+    }
+
+    file.write_all(id)?;
+    file.write_all(&size.to_le_bytes())?;
+    file.write_all(body)?;
+    if body.len() & 1 == 1 {
+        file.write_all(&[0])?;
+    }
+
+    let end = pos + 8 + body.len() as u64 + (body.len() as u64 & 1);
+    let riff_size = u32::try_from(end - 8).map_err(|_| too_large())?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_size.to_le_bytes())?;
+    file.flush()
+}
+
+/// Read the format description and metadata chunks of a wav file
+pub fn read_info<R: Read + Seek>(
+    reader: &mut R,
+    chunks: &[ChunkInfo],
+    format: &WavFormat,
+) -> Result<FileInfo, AppError> {
+    let mut info = FileInfo {
+        format: "WAV".to_string(),
+        sample_format: match format.encoding {
+            SampleEncoding::Int => "PCM integer",
+            SampleEncoding::Float => "IEEE float",
+        }
+        .to_string(),
+        bits_per_sample: Some(format.bits_per_sample),
+        ..Default::default()
+    };
+
+    if let Some(chunk) = find_chunk(chunks, b"bext") {
+        info.bext = parse_bext(&read_chunk(reader, chunk)?);
+    }
+    if let Some(chunk) = find_chunk(chunks, b"smpl") {
+        info.loops = parse_smpl(&read_chunk(reader, chunk)?);
+    }
+    // Cue points are read before the LIST chunks so adtl labels can be attached
+    if let Some(chunk) = find_chunk(chunks, b"cue ") {
+        info.cues = parse_cue(&read_chunk(reader, chunk)?);
+    }
+    for chunk in chunks.iter().filter(|chunk| &chunk.id == b"LIST") {
+        parse_list(&read_chunk(reader, chunk)?, &mut info);
+    }
+
+    Ok(info)
+}
+
+impl Decoder for WavDecoder {
+    fn probe(&self, header: &[u8]) -> bool {
+        header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav", "wave"]
+    }
+
+    fn decode(&self, path: &Path) -> Result<DecodedAudio, AppError> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(&file);
+        let chunks = read_chunks(&mut reader)?;
+
+        let fmt = find_chunk(&chunks, b"fmt ").ok_or_else(|| malformed("missing fmt chunk"))?;
+        let format = WavFormat::parse(&read_chunk(&mut reader, fmt)?)?;
+        let data_chunk = find_chunk(&chunks, b"data").ok_or_else(|| malformed("missing data chunk"))?;
+        let info = read_info(&mut reader, &chunks, &format)?;
+
+        // Only whole frames are read, so a truncated final frame is dropped
+        let frame_size = format.bytes_per_sample * format.num_channels;
+        let num_frames = data_chunk.size as usize / frame_size;
+
+        // Safety: the file is opened read-only. If another process truncates
+        // it while it is open, reads past the new end will fault.
+        let map = unsafe { Mmap::map(&file)? };
+
+        Ok(DecodedAudio {
+            samples: Samples::Mapped(MappedSamples {
+                map,
+                offset: data_chunk.offset as usize,
+                num_frames,
+                format,
+            }),
+            sample_rate: format.sample_rate as f64,
+            num_channels: format.num_channels,
+            info,
+        })
+    }
+}
+
+fn malformed(msg: &str) -> AppError {
+    AppError::FileReadError(format!("malformed wav: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, write_wav};
+    use hound::{SampleFormat, WavSpec};
+    use std::io::Write;
+
+    /// Open a file and read all of its interleaved samples, returning the
+    /// channel count, sample rate and samples
+    fn read_all(path: &Path) -> (usize, f64, Vec<f32>) {
+        let decoded = WavDecoder.decode(path).unwrap();
+        let num_channels = decoded.num_channels;
+        let mut data = vec![0.0; decoded.samples.num_frames(num_channels) * num_channels];
+        decoded.samples.read_frames(num_channels, 0, &mut data);
+        (num_channels, decoded.sample_rate, data)
+    }
+
+    fn spec(bits_per_sample: u16, sample_format: SampleFormat) -> WavSpec {
+        WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+
+    /// Write interleaved integer samples with hound, decode them and compare
+    /// against the expected normalised values
+    fn round_trip_int(bits: u16, samples: &[i32], expected: &[f32]) {
+        let path = temp_path(&format!("wav_int{}.wav", bits));
+        write_wav(&path, spec(bits, SampleFormat::Int), samples);
+
+        let (num_channels, sample_rate, data) = read_all(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(num_channels, 2);
+        assert_eq!(sample_rate, 48000.0);
+        assert_eq!(data.len(), expected.len());
+        for (decoded, expected) in data.iter().zip(expected) {
+            assert!((decoded - expected).abs() < 1e-6, "{} bit: {} != {}", bits, decoded, expected);
+        }
+    }
+
+    fn full_scale_samples(bits: u16) -> (Vec<i32>, Vec<f32>) {
+        let max = ((1i64 << (bits - 1)) - 1) as i32;
+        let samples = vec![0, max, -max, max / 2, -max / 2, 1];
+        let expected = samples.iter().map(|s| *s as f32 / max as f32).collect();
+        (samples, expected)
+    }
+
+    #[test]
+    fn int_8bit() {
+        let (samples, expected) = full_scale_samples(8);
+        round_trip_int(8, &samples, &expected);
+    }
+
+    #[test]
+    fn int_16bit() {
+        let (samples, expected) = full_scale_samples(16);
+        round_trip_int(16, &samples, &expected);
+    }
+
+    #[test]
+    fn int_24bit_is_full_scale() {
+        let (samples, expected) = full_scale_samples(24);
+        assert_eq!(expected[1], 1.0);
+        round_trip_int(24, &samples, &expected);
+    }
+
+    #[test]
+    fn int_32bit() {
+        let (samples, expected) = full_scale_samples(32);
+        round_trip_int(32, &samples, &expected);
+    }
+
+    #[test]
+    fn float_32bit() {
+        let samples = [0.0f32, 1.0, -1.0, 0.5, -0.25, 1.5];
+        let path = temp_path("wav_float32.wav");
+        write_wav(&path, spec(32, SampleFormat::Float), &samples);
+
+        let (_, _, data) = read_all(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data, samples);
+    }
+
+    #[test]
+    fn float_64bit() {
+        // hound can't write 64-bit float, so build the file by hand
+        let samples = [0.0f64, 1.0, -1.0, 0.5, -0.25, 0.125];
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes().to_vec()).collect();
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&48000u32.to_le_bytes());
+        fmt.extend_from_slice(&(48000u32 * 16).to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&64u16.to_le_bytes());
+
+        let path = temp_path("wav_float64.wav");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"RIFF").unwrap();
+        file.write_all(&(4 + 8 + fmt.len() as u32 + 8 + data.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(b"WAVEfmt ").unwrap();
+        file.write_all(&(fmt.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&fmt).unwrap();
+        file.write_all(b"data").unwrap();
+        file.write_all(&(data.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&data).unwrap();
+        drop(file);
+
+        let (_, _, data) = read_all(&path);
+        std::fs::remove_file(&path).unwrap();
+        let expected: Vec<f32> = samples.iter().map(|s| *s as f32).collect();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn truncated_file_drops_partial_frame() {
+        let (samples, expected) = full_scale_samples(16);
+        let path = temp_path("wav_truncated.wav");
+        let samples: Vec<i16> = samples.iter().map(|sample| *sample as i16).collect();
+        write_wav(&path, spec(16, SampleFormat::Int), &samples);
+
+        // Cut the last frame in half
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+
+        let (_, _, data) = read_all(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), expected.len() - 2);
+    }
+
+    #[test]
+    fn zero_sample_rate_is_malformed() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&4u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        assert!(WavFormat::parse(&body).is_err());
+
+        body[4..8].copy_from_slice(&48000u32.to_le_bytes());
+        assert!(WavFormat::parse(&body).is_ok());
+    }
+}
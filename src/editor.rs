@@ -0,0 +1,579 @@
+//! Destructive editing of a loaded file. An edited file is a list of segments, each
+//! taken from the samples decoded from the file, from samples made by an edit, or
+//! silence. Edits build a new `AudioFile` from the segments of the last one, so the
+//! original samples are never copied and undo only has to keep the earlier files.
+
+use crate::analysis::Progress;
+use crate::audio_file::AudioFile;
+use crate::decoder::{wav, Samples};
+use crate::export::{self, BitDepth, ExportSettings};
+use crate::marker_files;
+use crate::markers::Markers;
+use basedrop::Shared;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind};
+use std::sync::Arc;
+
+/// Level of the loudest sample after normalizing, in dBFS
+pub const NORMALIZE_PEAK_DB: f32 = -1.0;
+
+// Number of edits that can be undone
+const MAX_UNDO: usize = 100;
+
+/// A run of frames in an edited file
+#[derive(Clone)]
+pub enum Segment {
+    /// frames of the samples decoded from the file
+    Source { start: usize, len: usize },
+    /// frames made by an edit, interleaved
+    Memory { data: Arc<Vec<f32>>, start: usize, len: usize },
+    Silence { len: usize },
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        match self {
+            Segment::Source { len, .. } | Segment::Memory { len, .. } | Segment::Silence { len } => *len,
+        }
+    }
+
+    // The part of the segment from `start` to `end`, relative to its own start
+    fn slice(&self, start: usize, end: usize) -> Segment {
+        let len = end - start;
+        match self {
+            Segment::Source { start: offset, .. } => Segment::Source { start: offset + start, len },
+            Segment::Memory { data, start: offset, .. } => Segment::Memory {
+                data: Arc::clone(data),
+                start: offset + start,
+                len,
+            },
+            Segment::Silence { .. } => Segment::Silence { len },
+        }
+    }
+}
+
+/// The samples of an edited file, read through its segments
+pub struct EditedSamples {
+    source: Arc<Samples>,
+    num_channels: usize,
+    segments: Vec<Segment>,
+    // The frame each segment starts at
+    offsets: Vec<usize>,
+    num_frames: usize,
+}
+
+impl EditedSamples {
+    pub fn new(source: Arc<Samples>, num_channels: usize, segments: Vec<Segment>) -> Self {
+        let segments: Vec<Segment> = segments.into_iter().filter(|segment| segment.len() > 0).collect();
+        let mut offsets = Vec::with_capacity(segments.len());
+        let mut num_frames = 0;
+        for segment in segments.iter() {
+            offsets.push(num_frames);
+            num_frames += segment.len();
+        }
+
+        Self {
+            source,
+            num_channels,
+            segments,
+            offsets,
+            num_frames,
+        }
+    }
+
+    /// the samples decoded from the file
+    pub fn source(&self) -> &Arc<Samples> {
+        &self.source
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// copy interleaved frames starting at `start` into `out`, which must be in range
+    pub fn read_frames(&self, start: usize, out: &mut [f32]) {
+        let num_channels = self.num_channels;
+        self.read(start, out.len() / num_channels, |segment, segment_start, position, len| {
+            let out = &mut out[(position - start) * num_channels..(position - start + len) * num_channels];
+            match segment {
+                Segment::Source { start: offset, .. } => {
+                    self.source.read_frames(num_channels, offset + segment_start, out)
+                }
+                Segment::Memory { data, start: offset, .. } => {
+                    let first = (offset + segment_start) * num_channels;
+                    out.copy_from_slice(&data[first..first + out.len()]);
+                }
+                Segment::Silence { .. } => out.iter_mut().for_each(|sample| *sample = 0.0),
+            }
+        });
+    }
+
+    /// copy the samples of one channel starting at frame `start` into `out`, which must be in range
+    pub fn read_channel(&self, channel: usize, start: usize, out: &mut [f32]) {
+        let num_channels = self.num_channels;
+        self.read(start, out.len(), |segment, segment_start, position, len| {
+            let out = &mut out[position - start..position - start + len];
+            match segment {
+                Segment::Source { start: offset, .. } => {
+                    self.source.read_channel(num_channels, channel, offset + segment_start, out)
+                }
+                Segment::Memory { data, start: offset, .. } => {
+                    for (idx, sample) in out.iter_mut().enumerate() {
+                        *sample = data[(offset + segment_start + idx) * num_channels + channel];
+                    }
+                }
+                Segment::Silence { .. } => out.iter_mut().for_each(|sample| *sample = 0.0),
+            }
+        });
+    }
+
+    // Call `copy` with each segment that holds frames from `start` to `start + len`, the
+    // first of those frames within the segment, its position in the file and the number of frames
+    fn read<F>(&self, start: usize, len: usize, mut copy: F)
+    where
+        F: FnMut(&Segment, usize, usize, usize),
+    {
+        let end = start + len;
+        let first = match self.offsets.binary_search(&start) {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        };
+
+        let mut position = start;
+        for (segment, offset) in self.segments[first..].iter().zip(self.offsets[first..].iter()) {
+            if position >= end {
+                break;
+            }
+            let segment_start = position - offset;
+            let len = (segment.len() - segment_start).min(end - position);
+            copy(segment, segment_start, position, len);
+            position += len;
+        }
+    }
+}
+
+// The segments covering frames `start` to `end`
+fn slice(segments: &[Segment], start: usize, end: usize) -> Vec<Segment> {
+    let mut sliced = Vec::new();
+    let mut offset = 0;
+    for segment in segments {
+        let segment_end = offset + segment.len();
+        if segment_end > start && offset < end {
+            sliced.push(segment.slice(start.max(offset) - offset, end.min(segment_end) - offset));
+        }
+        offset = segment_end;
+    }
+    sliced
+}
+
+/// Samples copied from a file, to be pasted
+#[derive(Clone)]
+pub struct Clipboard {
+    data: Arc<Vec<f32>>,
+    num_channels: usize,
+}
+
+impl Clipboard {
+    /// copy the frames from `start` to `end`
+    pub fn copy(file: &AudioFile, start: usize, end: usize) -> Self {
+        Self {
+            data: Arc::new(render(file, start, end)),
+            num_channels: file.num_channels,
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.data.len() / self.num_channels
+    }
+}
+
+pub enum Edit {
+    Delete,
+    Crop,
+    /// replace the selection with the clipboard, or insert it at the cursor
+    Paste(Clipboard),
+    /// insert a number of frames of silence at the start of the selection
+    InsertSilence(usize),
+    FadeIn,
+    FadeOut,
+    Normalize,
+    /// change the level by a number of dB
+    Gain(f32),
+    Reverse,
+    Invert,
+}
+
+/// The frames that an edit changed. Those from `start` to `old_end` in the file before
+/// the edit became those from `start` to `new_end`
+#[derive(Debug, Clone, Copy)]
+pub struct Change {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+/// apply an edit to the frames from `start` to `end` of a file, moving the markers to
+/// follow the samples. Returns the edited file and what it changed
+pub fn apply(
+    file: &AudioFile,
+    markers: &mut Markers,
+    edit: &Edit,
+    start: usize,
+    end: usize,
+) -> Result<(AudioFile, Change), String> {
+    let num_samples = file.num_samples;
+    let end = end.min(num_samples);
+    let start = start.min(end);
+    let segments = file.segments();
+
+    // Cropping removes the audio either side of the selection
+    if let Edit::Crop = edit {
+        if start == end {
+            return Err("select the audio to keep".to_string());
+        }
+        markers.replace_range(end, num_samples, 0);
+        markers.replace_range(0, start, 0);
+        let change = Change {
+            start: 0,
+            old_end: num_samples,
+            new_end: end - start,
+        };
+        return Ok((file.with_segments(slice(&segments, start, end)), change));
+    }
+
+    // Every other edit replaces part of the file
+    let (replaced_end, replacement) = match edit {
+        Edit::Crop => unreachable!(),
+        Edit::Delete => (end, Vec::new()),
+        Edit::Paste(clipboard) => {
+            if clipboard.num_channels != file.num_channels {
+                return Err(format!(
+                    "can't paste {} channels into a file with {}",
+                    clipboard.num_channels, file.num_channels
+                ));
+            }
+            let pasted = Segment::Memory {
+                data: Arc::clone(&clipboard.data),
+                start: 0,
+                len: clipboard.num_frames(),
+            };
+            (end, vec![pasted])
+        }
+        Edit::InsertSilence(len) => (start, vec![Segment::Silence { len: *len }]),
+        _ => {
+            let mut data = render(file, start, end);
+            process(edit, &mut data, file.num_channels);
+            let processed = Segment::Memory {
+                len: end - start,
+                data: Arc::new(data),
+                start: 0,
+            };
+            (end, vec![processed])
+        }
+    };
+
+    let replacement_len: usize = replacement.iter().map(Segment::len).sum();
+    if replacement_len == 0 && replaced_end == start {
+        return Err("nothing to change, select some audio first".to_string());
+    }
+
+    if replacement_len == 0 && start == 0 && replaced_end == num_samples {
+        return Err("can't delete all of the file".to_string());
+    }
+
+    let mut edited = slice(&segments, 0, start);
+    edited.extend(replacement);
+    edited.extend(slice(&segments, replaced_end, num_samples));
+
+    markers.replace_range(start, replaced_end, replacement_len);
+    let change = Change {
+        start,
+        old_end: replaced_end,
+        new_end: start + replacement_len,
+    };
+    Ok((file.with_segments(edited), change))
+}
+
+// The interleaved frames from `start` to `end`
+fn render(file: &AudioFile, start: usize, end: usize) -> Vec<f32> {
+    let mut data = vec![0.0; (end - start) * file.num_channels];
+    let num_frames = file.read_frames(start, &mut data);
+    data.truncate(num_frames * file.num_channels);
+    data
+}
+
+// Change interleaved frames in place
+fn process(edit: &Edit, data: &mut [f32], num_channels: usize) {
+    let num_frames = data.len() / num_channels;
+    match edit {
+        Edit::FadeIn | Edit::FadeOut => {
+            for (idx, frame) in data.chunks_mut(num_channels).enumerate() {
+                // From silence at the first frame to unity at the last, or the other way round
+                let gain = match edit {
+                    Edit::FadeIn => idx as f32 / (num_frames - 1).max(1) as f32,
+                    _ => (num_frames - idx - 1) as f32 / (num_frames - 1).max(1) as f32,
+                };
+                frame.iter_mut().for_each(|sample| *sample *= gain);
+            }
+        }
+        Edit::Normalize => {
+            let peak = data.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            if peak > 0.0 {
+                let gain = 10f32.powf(NORMALIZE_PEAK_DB / 20.0) / peak;
+                data.iter_mut().for_each(|sample| *sample *= gain);
+            }
+        }
+        Edit::Gain(db) => {
+            let gain = 10f32.powf(db / 20.0);
+            data.iter_mut().for_each(|sample| *sample *= gain);
+        }
+        Edit::Reverse => {
+            let reversed: Vec<f32> = data.chunks(num_channels).rev().flatten().cloned().collect();
+            data.copy_from_slice(&reversed);
+        }
+        Edit::Invert => data.iter_mut().for_each(|sample| *sample = -*sample),
+        Edit::Delete | Edit::Crop | Edit::Paste(_) | Edit::InsertSilence(_) => {}
+    }
+}
+
+/// A file and its markers before or after an edit
+#[derive(Clone)]
+pub struct Snapshot {
+    pub file: Shared<AudioFile>,
+    pub markers: Markers,
+}
+
+/// An edit that can be undone
+pub struct Step {
+    pub before: Snapshot,
+    pub after: Snapshot,
+    pub change: Change,
+}
+
+/// The edits that can be undone and redone
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    // Number of undo steps when the file was last saved, if they haven't been dropped
+    saved: Option<usize>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            saved: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// record an edit, dropping any that were undone
+    pub fn push(&mut self, step: Step) {
+        if self.saved.map_or(false, |saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+        self.undo.push(step);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+            self.saved = self.saved.and_then(|saved| saved.checked_sub(1));
+        }
+    }
+
+    /// the last edit, which is moved to the redo list
+    pub fn undo(&mut self) -> Option<&Step> {
+        let step = self.undo.pop()?;
+        self.redo.push(step);
+        self.redo.last()
+    }
+
+    /// the last edit undone, which is moved back to the undo list
+    pub fn redo(&mut self) -> Option<&Step> {
+        let step = self.redo.pop()?;
+        self.undo.push(step);
+        self.undo.last()
+    }
+
+    /// note that the file as it is now has been saved
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    /// true if there are edits that haven't been saved
+    pub fn is_modified(&self) -> bool {
+        self.saved != Some(self.undo.len())
+    }
+}
+
+/// the bit depth a file is saved at, the one it was opened with. `None` if its samples
+/// can't be written back unchanged, as with 64-bit floats, so it has to be saved as a new file
+pub fn save_bit_depth(file: &AudioFile) -> Option<BitDepth> {
+    match (file.info.sample_format.as_str(), file.info.bits_per_sample) {
+        ("IEEE float", Some(32)) => Some(BitDepth::Float32),
+        ("IEEE float", _) => None,
+        (_, Some(8)) => Some(BitDepth::Int8),
+        (_, Some(16)) => Some(BitDepth::Int16),
+        (_, Some(24)) => Some(BitDepth::Int24),
+        (_, Some(32)) => Some(BitDepth::Int32),
+        _ => None,
+    }
+}
+
+/// the file an edited file is written to before it replaces `path`
+pub fn temp_path(path: &str) -> String {
+    format!("{}.saving", path)
+}
+
+/// write the whole of an edited file to `temp_path(path)` as a WAV file with its markers.
+/// The other chunks of a WAV source, such as `LIST`/`INFO`, `smpl` and `iXML`, are copied
+/// over. The samples are still read from `source_path`, so the file is only moved over
+/// `path` by `replace` once nothing reads from it. Returns `None` if cancelled
+pub fn save(
+    file: &AudioFile,
+    source_path: &str,
+    path: &str,
+    bit_depth: BitDepth,
+    markers: &Markers,
+    progress: &Progress,
+) -> Option<io::Result<()>> {
+    let temp_path = temp_path(path);
+    let settings = ExportSettings {
+        channels: (0..file.num_channels).collect(),
+        bit_depth,
+        dither: false,
+    };
+
+    let result = export::export(file, 0, file.num_samples, source_path, &temp_path, &settings, progress)?
+        .and_then(|_| {
+            if file.info.format == "WAV" {
+                copy_chunks(source_path, &temp_path)?;
+            }
+            marker_files::append_markers(&temp_path, markers)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Some(result)
+}
+
+/// move a file written by `save` over `path`. The file at `path` must not be open, as a
+/// memory-mapped file can't be replaced on every platform
+pub fn replace(path: &str) -> io::Result<()> {
+    fs::rename(temp_path(path), path)
+}
+
+// Append the chunks of the source that the saved file doesn't have yet. The format, the
+// samples and the markers are written afresh, as is the `bext` chunk when it could be read
+fn copy_chunks(source_path: &str, path: &str) -> io::Result<()> {
+    let app_error = |err| io::Error::new(ErrorKind::InvalidData, format!("{}", err));
+    let written = wav::read_chunks(&mut BufReader::new(File::open(path)?)).map_err(app_error)?;
+
+    let mut reader = BufReader::new(File::open(source_path)?);
+    let chunks = wav::read_chunks(&mut reader).map_err(app_error)?;
+    for chunk in chunks.iter() {
+        let rewritten = matches!(&chunk.id, b"fact" | b"cue ")
+            || (&chunk.id == b"LIST" && marker_files::is_adtl(&mut reader, chunk)?)
+            || wav::find_chunk(&written, &chunk.id).is_some();
+        if !rewritten {
+            let body = wav::read_chunk(&mut reader, chunk).map_err(app_error)?;
+            wav::append_chunk(path, &chunk.id, &body)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, write_wav};
+    use hound::{SampleFormat, WavSpec};
+    use std::path::Path;
+
+    /// Write a stereo file of integer samples with hound and open it
+    fn write_int(path: &Path, bits: u16, samples: &[i32]) -> AudioFile {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: bits,
+            sample_format: SampleFormat::Int,
+        };
+        write_wav(path, spec, samples);
+        AudioFile::open(path.to_str().unwrap()).unwrap()
+    }
+
+    /// Save a file to a new path and read back its samples as stored
+    fn save_and_read(file: &AudioFile, source: &Path, dest: &Path) -> Vec<i32> {
+        let (source, dest) = (source.to_str().unwrap(), dest.to_str().unwrap());
+        let bit_depth = save_bit_depth(file).unwrap();
+        save(file, source, dest, bit_depth, &Markers::default(), &Progress::new()).unwrap().unwrap();
+        replace(dest).unwrap();
+        let mut reader = hound::WavReader::open(dest).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, file.info.bits_per_sample.unwrap());
+        reader.samples::<i32>().map(|sample| sample.unwrap()).collect()
+    }
+
+    #[test]
+    fn fade_in_reaches_unity() {
+        let mut data = vec![1.0; 5 * 2];
+        process(&Edit::FadeIn, &mut data, 2);
+        assert_eq!(&data[..2], &[0.0, 0.0]);
+        assert_eq!(&data[4..6], &[0.5, 0.5]);
+        assert_eq!(&data[8..], &[1.0, 1.0]);
+    }
+
+    #[test]
+    fn fade_out_starts_at_unity() {
+        let mut data = vec![1.0; 5];
+        process(&Edit::FadeOut, &mut data, 1);
+        assert_eq!(data, vec![1.0, 0.75, 0.5, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn save_keeps_source_format() {
+        for (bits, max) in [(8, 127), (16, 32767), (24, 8_388_607), (32, i32::MAX)].iter() {
+            let samples = vec![0, *max, -*max, *max / 3, -*max / 7, 1];
+            let source = temp_path(&format!("editor_source{}.wav", bits));
+            let dest = temp_path(&format!("editor_dest{}.wav", bits));
+            let file = write_int(&source, *bits, &samples);
+            let saved = save_and_read(&file, &source, &dest);
+            drop(file);
+            fs::remove_file(&source).unwrap();
+            fs::remove_file(&dest).unwrap();
+
+            // 32 bit samples are only as exact as the f32 they are decoded to
+            let tolerance = if *bits == 32 { 256 } else { 0 };
+            for (saved, sample) in saved.iter().zip(samples.iter()) {
+                assert!((*saved as i64 - *sample as i64).abs() <= tolerance, "{} bit: {} != {}", bits, saved, sample);
+            }
+        }
+    }
+
+    #[test]
+    fn save_copies_metadata_chunks() {
+        let source = temp_path("editor_chunks_source.wav");
+        let dest = temp_path("editor_chunks_dest.wav");
+        let source_str = source.to_str().unwrap();
+        write_int(&source, 16, &[0, 1, 2, 3]);
+        wav::append_chunk(source_str, b"LIST", b"INFOINAM\x06\0\0\0Title\0").unwrap();
+        wav::append_chunk(source_str, b"iXML", b"<BWFXML/>").unwrap();
+        let file = AudioFile::open(source_str).unwrap();
+
+        save_and_read(&file, &source, &dest);
+        let mut reader = BufReader::new(File::open(&dest).unwrap());
+        let chunks = wav::read_chunks(&mut reader).unwrap();
+        let list = wav::read_chunk(&mut reader, wav::find_chunk(&chunks, b"LIST").unwrap()).unwrap();
+        let ixml = wav::read_chunk(&mut reader, wav::find_chunk(&chunks, b"iXML").unwrap()).unwrap();
+        drop(file);
+        fs::remove_file(&source).unwrap();
+        fs::remove_file(&dest).unwrap();
+
+        assert_eq!(&list, b"INFOINAM\x06\0\0\0Title\0");
+        assert_eq!(&ixml, b"<BWFXML/>");
+        assert_eq!(chunks.iter().filter(|chunk| &chunk.id == b"fmt ").count(), 1);
+        assert_eq!(chunks.iter().filter(|chunk| &chunk.id == b"data").count(), 1);
+    }
+}
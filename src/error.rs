@@ -0,0 +1,50 @@
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while loading an audio file
+#[derive(Debug)]
+pub enum AppError {
+    /// The file does not exist
+    FileNotFound(String),
+    /// The file exists but could not be read or is malformed
+    FileReadError(String),
+    /// The file is valid but uses a format that isn't supported
+    UnsupportedFormat(String),
+    /// The file contains no audio
+    EmptyFile,
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::FileNotFound(path) => write!(f, "file not found: {}", path),
+            AppError::FileReadError(msg) => write!(f, "failed to read file: {}", msg),
+            AppError::UnsupportedFormat(msg) => write!(f, "unsupported format: {}", msg),
+            AppError::EmptyFile => write!(f, "file contains no audio"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            AppError::FileNotFound(err.to_string())
+        } else {
+            AppError::FileReadError(err.to_string())
+        }
+    }
+}
+
+impl From<hound::Error> for AppError {
+    fn from(err: hound::Error) -> Self {
+        match err {
+            hound::Error::IoError(err) => err.into(),
+            hound::Error::Unsupported | hound::Error::InvalidSampleFormat => {
+                AppError::UnsupportedFormat(err.to_string())
+            }
+            err => AppError::FileReadError(err.to_string()),
+        }
+    }
+}
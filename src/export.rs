@@ -0,0 +1,312 @@
+//! Writing part of a file to a new WAV file. Samples are stored as 8 to 32 bit
+//! integers, optionally with TPDF dither, or as 32 bit floats, at the sample rate of
+//! the source. Broadcast WAV metadata is carried over from the source file, with the
+//! time reference moved to the first exported sample.
+
+use crate::analysis::Progress;
+use crate::audio_file::AudioFile;
+use crate::decoder::wav;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind};
+
+// Number of frames read at a time
+const CHUNK_FRAMES: usize = 16384;
+
+// Offsets of fields within the `bext` chunk
+const BEXT_TIME_REFERENCE: usize = 338;
+const BEXT_VERSION: usize = 346;
+const BEXT_LOUDNESS: usize = 412;
+const BEXT_CODING_HISTORY: usize = 602;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    Int8,
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl BitDepth {
+    /// the depths offered for export. 8 and 32 bit integers are only used to save a file
+    /// in the format it was opened in
+    pub const ALL: [BitDepth; 3] = [BitDepth::Int16, BitDepth::Int24, BitDepth::Float32];
+
+    pub fn bits(&self) -> u16 {
+        match self {
+            BitDepth::Int8 => 8,
+            BitDepth::Int16 => 16,
+            BitDepth::Int24 => 24,
+            BitDepth::Int32 | BitDepth::Float32 => 32,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BitDepth::Int8 => "8",
+            BitDepth::Int16 => "16",
+            BitDepth::Int24 => "24",
+            BitDepth::Int32 => "32",
+            BitDepth::Float32 => "32f",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSettings {
+    /// the channels of the source to write, in order
+    pub channels: Vec<usize>,
+    pub bit_depth: BitDepth,
+    /// add TPDF dither when reducing to an integer bit depth
+    pub dither: bool,
+}
+
+/// write the samples from `start` to `end` of `file`, read from `source_path`, to a new
+/// WAV file at `path`. Returns `None` if cancelled, leaving no file behind
+pub fn export(
+    file: &AudioFile,
+    start: usize,
+    end: usize,
+    source_path: &str,
+    path: &str,
+    settings: &ExportSettings,
+    progress: &Progress,
+) -> Option<io::Result<()>> {
+    // The source may be memory-mapped, so can't be written over while it's open
+    if let (Ok(source), Ok(dest)) = (fs::canonicalize(source_path), fs::canonicalize(path)) {
+        if source == dest {
+            return Some(Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "can't export over the open file, choose a new file name",
+            )));
+        }
+    }
+
+    let result = write_samples(file, start, end, path, settings, progress).and_then(|done| {
+        if done && file.info.bext.is_some() {
+            copy_bext(source_path, path, start, file.sample_rate, settings)?;
+        }
+        Ok(done)
+    });
+
+    match result {
+        Ok(true) => Some(Ok(())),
+        Ok(false) => {
+            let _ = fs::remove_file(path);
+            None
+        }
+        Err(err) => {
+            let _ = fs::remove_file(path);
+            Some(Err(err))
+        }
+    }
+}
+
+fn from_hound_error(err: hound::Error) -> io::Error {
+    match err {
+        hound::Error::IoError(err) => err,
+        err => io::Error::new(ErrorKind::Other, err.to_string()),
+    }
+}
+
+// Returns false if cancelled
+fn write_samples(
+    file: &AudioFile,
+    start: usize,
+    end: usize,
+    path: &str,
+    settings: &ExportSettings,
+    progress: &Progress,
+) -> io::Result<bool> {
+    if settings.channels.is_empty() || settings.channels.iter().any(|channel| *channel >= file.num_channels) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "no channels to export"));
+    }
+
+    let spec = hound::WavSpec {
+        channels: settings.channels.len() as u16,
+        sample_rate: file.sample_rate.round() as u32,
+        bits_per_sample: settings.bit_depth.bits(),
+        sample_format: match settings.bit_depth {
+            BitDepth::Float32 => hound::SampleFormat::Float,
+            _ => hound::SampleFormat::Int,
+        },
+    };
+    let mut writer = hound::WavWriter::create(path, spec).map_err(from_hound_error)?;
+
+    // The same scale the decoder divides by, so exported samples read back unchanged. The
+    // dither is a fraction of a step, so it is added in f64, which holds a 32 bit step
+    let full_scale = ((1i64 << (settings.bit_depth.bits() - 1)) - 1) as f64;
+    let mut dither = Dither::new();
+
+    let end = end.min(file.num_samples);
+    let start = start.min(end);
+    let mut buffer = vec![0.0; CHUNK_FRAMES * file.num_channels];
+    let mut position = start;
+    while position < end {
+        if !progress.update((position - start) as f32 / (end - start) as f32) {
+            return Ok(false);
+        }
+
+        let len = CHUNK_FRAMES.min(end - position);
+        let num_frames = file.read_frames(position, &mut buffer[..len * file.num_channels]);
+        if num_frames == 0 {
+            break;
+        }
+
+        for frame in buffer[..num_frames * file.num_channels].chunks(file.num_channels) {
+            for channel in settings.channels.iter() {
+                let sample = frame[*channel];
+                let written = if settings.bit_depth == BitDepth::Float32 {
+                    writer.write_sample(sample)
+                } else {
+                    let noise = if settings.dither { dither.next() } else { 0.0 };
+                    let value = (sample as f64 * full_scale + noise)
+                        .round()
+                        .max(-full_scale - 1.0)
+                        .min(full_scale);
+                    writer.write_sample(value as i32)
+                };
+                written.map_err(from_hound_error)?;
+            }
+        }
+        position += num_frames;
+    }
+
+    writer.finalize().map_err(from_hound_error)?;
+    progress.update(1.0);
+    Ok(true)
+}
+
+// Copy the `bext` chunk of the source to the end of the exported file. Its time reference
+// is moved to the start of the export, the loudness values of the source are cleared as
+// they don't describe the export, and a line is added to the coding history
+fn copy_bext(source_path: &str, path: &str, start: usize, sample_rate: f64, settings: &ExportSettings) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(source_path)?);
+    let app_error = |err| io::Error::new(ErrorKind::InvalidData, format!("{}", err));
+    let chunks = wav::read_chunks(&mut reader).map_err(app_error)?;
+    let mut bext = match wav::find_chunk(&chunks, b"bext") {
+        Some(chunk) => wav::read_chunk(&mut reader, chunk).map_err(app_error)?,
+        None => return Ok(()),
+    };
+    if bext.len() < BEXT_CODING_HISTORY {
+        return Ok(());
+    }
+
+    let mut time_reference = [0u8; 8];
+    time_reference.copy_from_slice(&bext[BEXT_TIME_REFERENCE..BEXT_TIME_REFERENCE + 8]);
+    let time_reference = u64::from_le_bytes(time_reference) + start as u64;
+    bext[BEXT_TIME_REFERENCE..BEXT_TIME_REFERENCE + 8].copy_from_slice(&time_reference.to_le_bytes());
+
+    let version = u16::from_le_bytes([bext[BEXT_VERSION], bext[BEXT_VERSION + 1]]);
+    if version >= 2 {
+        for field in bext[BEXT_LOUDNESS..BEXT_LOUDNESS + 10].chunks_mut(2) {
+            field.copy_from_slice(&0x7fffu16.to_le_bytes());
+        }
+    }
+
+    // The coding history is null-terminated text of CR LF terminated lines
+    let history_end = bext[BEXT_CODING_HISTORY..]
+        .iter()
+        .position(|byte| *byte == 0)
+        .map_or(bext.len(), |end| BEXT_CODING_HISTORY + end);
+    bext.truncate(history_end);
+    if history_end > BEXT_CODING_HISTORY && !bext.ends_with(b"\r\n") {
+        bext.extend_from_slice(b"\r\n");
+    }
+    let mode = match settings.channels.len() {
+        1 => ",M=mono",
+        2 => ",M=stereo",
+        _ => "",
+    };
+    bext.extend_from_slice(
+        format!(
+            "A=PCM,F={},W={}{},T=wav_viewer export\r\n",
+            sample_rate.round(),
+            settings.bit_depth.bits(),
+            mode
+        )
+        .as_bytes(),
+    );
+
+    // Chunks may come in any order, so the metadata follows the samples written by hound
+    wav::append_chunk(path, b"bext", &bext)
+}
+
+// Triangular noise from the sum of two uniform values, spanning one step either side.
+// A small xorshift generator is plenty for dither
+struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    fn new() -> Self {
+        Self { state: 0x9e37_79b9 }
+    }
+
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f64 / (1u64 << 32) as f64 - 0.5
+    }
+
+    fn next(&mut self) -> f64 {
+        self.uniform() + self.uniform()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{float_spec, temp_path, write_wav};
+
+    /// Export every channel of a file at a bit depth and read back the stored integers
+    fn export_ints(file: &AudioFile, source: &str, name: &str, bit_depth: BitDepth, dither: bool) -> Vec<i32> {
+        let path = temp_path(&format!("export_{}.wav", name));
+        let settings = ExportSettings {
+            channels: (0..file.num_channels).collect(),
+            bit_depth,
+            dither,
+        };
+        let (path_str, progress) = (path.to_str().unwrap(), Progress::new());
+        let result = export(file, 0, file.num_samples, source, path_str, &settings, &progress);
+        result.unwrap().unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let samples = reader.samples::<i32>().map(|sample| sample.unwrap()).collect();
+        fs::remove_file(&path).unwrap();
+        samples
+    }
+
+    #[test]
+    fn dither_changes_24_bit_output() {
+        // A loud float sine, whose samples fall between 24 bit steps
+        let source = temp_path("export_dither_source.wav");
+        let samples: Vec<f32> = (0..4800).map(|idx| 0.9 * (idx as f32 * 0.05).sin()).collect();
+        write_wav(&source, float_spec(1, 48000), &samples);
+        let source_str = source.to_str().unwrap();
+        let file = AudioFile::open(source_str).unwrap();
+
+        let plain = export_ints(&file, source_str, "plain", BitDepth::Int24, false);
+        let dithered = export_ints(&file, source_str, "dithered", BitDepth::Int24, true);
+        drop(file);
+        fs::remove_file(&source).unwrap();
+
+        assert_eq!(plain.len(), dithered.len());
+        let changed = plain
+            .iter()
+            .zip(dithered.iter())
+            .filter(|(plain, dithered)| plain != dithered)
+            .count();
+        assert!(
+            changed > plain.len() / 4,
+            "only {} of {} samples dithered",
+            changed,
+            plain.len()
+        );
+        // TPDF dither moves a sample by at most one step either way
+        assert!(plain
+            .iter()
+            .zip(dithered.iter())
+            .all(|(plain, dithered)| (plain - dithered).abs() <= 1));
+    }
+}
@@ -0,0 +1,478 @@
+//! Metadata read from audio files, including the Broadcast WAV `bext`
+//! chunk, `LIST`/`INFO` tags, `cue ` markers and `smpl` loop points.
+
+use std::convert::TryInto;
+
+/// Information about an audio file beyond its sample data
+#[derive(Clone, Debug, Default)]
+pub struct FileInfo {
+    /// Container format, e.g. "WAV" or "FLAC"
+    pub format: String,
+    /// How samples are encoded, e.g. "PCM integer" or "IEEE float"
+    pub sample_format: String,
+    /// Bit depth of the stored samples, if the format has one
+    pub bits_per_sample: Option<u16>,
+    /// Broadcast WAV metadata
+    pub bext: Option<BroadcastExtension>,
+    /// `LIST`/`INFO` tags as (name, value) pairs
+    pub tags: Vec<(String, String)>,
+    /// Cue points with any labels from the `LIST`/`adtl` chunk
+    pub cues: Vec<CuePoint>,
+    /// Loop points from the `smpl` chunk
+    pub loops: Vec<SampleLoop>,
+}
+
+/// The Broadcast WAV `bext` chunk (EBU Tech 3285)
+#[derive(Clone, Debug, Default)]
+pub struct BroadcastExtension {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// Date of creation as yyyy-mm-dd
+    pub origination_date: String,
+    /// Time of creation as hh:mm:ss
+    pub origination_time: String,
+    /// Timecode of the first sample, in samples since midnight
+    pub time_reference: u64,
+    pub version: u16,
+    /// SMPTE UMID as a hex string, empty if unset
+    pub umid: String,
+    /// Loudness fields added in version 2, in LUFS/LU/dBTP
+    pub loudness_value: Option<f32>,
+    pub loudness_range: Option<f32>,
+    pub max_true_peak_level: Option<f32>,
+    pub max_momentary_loudness: Option<f32>,
+    pub max_short_term_loudness: Option<f32>,
+    pub coding_history: String,
+}
+
+/// A marker from the `cue ` chunk
+#[derive(Clone, Debug, Default)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Position of the marker in sample frames
+    pub position: u32,
+    /// Label from an `adtl` `labl` sub-chunk
+    pub label: Option<String>,
+    /// Comment from an `adtl` `note` sub-chunk
+    pub note: Option<String>,
+    /// Region length in sample frames from an `adtl` `ltxt` sub-chunk
+    pub length: Option<u32>,
+}
+
+/// The type of a `smpl` loop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopType {
+    Forward,
+    PingPong,
+    Backward,
+    Other(u32),
+}
+
+/// A loop point from the `smpl` chunk
+#[derive(Clone, Debug)]
+pub struct SampleLoop {
+    pub id: u32,
+    pub loop_type: LoopType,
+    /// First sample frame of the loop
+    pub start: u32,
+    /// Last sample frame of the loop, inclusive
+    pub end: u32,
+    /// Number of times to play the loop, 0 means infinitely
+    pub play_count: u32,
+}
+
+impl std::fmt::Display for LoopType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoopType::Forward => write!(f, "forward"),
+            LoopType::PingPong => write!(f, "ping-pong"),
+            LoopType::Backward => write!(f, "backward"),
+            LoopType::Other(val) => write!(f, "type {}", val),
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read a fixed-size or null-terminated text field
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+/// Parse a `bext` chunk body
+pub fn parse_bext(body: &[u8]) -> Option<BroadcastExtension> {
+    if body.len() < 602 {
+        return None;
+    }
+
+    let version = read_u16(body, 346);
+
+    // Loudness values are stored as 100 times their value, and 0x7fff means unset
+    let loudness = |offset: usize| {
+        let val = read_u16(body, offset) as i16;
+        if version >= 2 && val != 0x7fff {
+            Some(val as f32 / 100.0)
+        } else {
+            None
+        }
+    };
+
+    let umid = &body[348..412];
+    let umid = if umid.iter().all(|b| *b == 0) {
+        String::new()
+    } else {
+        umid.iter().map(|b| format!("{:02x}", b)).collect()
+    };
+
+    Some(BroadcastExtension {
+        description: read_string(&body[0..256]),
+        originator: read_string(&body[256..288]),
+        originator_reference: read_string(&body[288..320]),
+        origination_date: read_string(&body[320..330]).replace(':', "-"),
+        origination_time: read_string(&body[330..338]).replace('-', ":"),
+        time_reference: read_u32(body, 338) as u64 | ((read_u32(body, 342) as u64) << 32),
+        version,
+        umid,
+        loudness_value: loudness(412),
+        loudness_range: loudness(414),
+        max_true_peak_level: loudness(416),
+        max_momentary_loudness: loudness(418),
+        max_short_term_loudness: loudness(420),
+        coding_history: read_string(&body[602..]),
+    })
+}
+
+/// Human readable names for the common `INFO` tags
+fn info_tag_name(id: &[u8]) -> String {
+    match id {
+        b"INAM" => "Title",
+        b"IART" => "Artist",
+        b"IPRD" => "Album",
+        b"ICMT" => "Comment",
+        b"ICRD" => "Date",
+        b"IGNR" => "Genre",
+        b"ICOP" => "Copyright",
+        b"IENG" => "Engineer",
+        b"ITCH" => "Technician",
+        b"ISFT" => "Software",
+        b"ISRC" => "Source",
+        b"ISBJ" => "Subject",
+        b"IKEY" => "Keywords",
+        b"ITRK" | b"IPRT" => "Track",
+        _ => return String::from_utf8_lossy(id).to_string(),
+    }
+    .to_string()
+}
+
+/// Iterate over the sub-chunks of a `LIST` chunk body, after the list type
+fn sub_chunks(body: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 4;
+    std::iter::from_fn(move || {
+        if pos + 8 > body.len() {
+            return None;
+        }
+        let id = &body[pos..pos + 4];
+        let size = read_u32(body, pos + 4) as usize;
+        let start = pos + 8;
+        let end = (start + size).min(body.len());
+        pos = start + size + (size & 1);
+        Some((id, &body[start..end]))
+    })
+}
+
+/// Parse a `LIST` chunk body, adding `INFO` tags and `adtl` labels to `info`.
+/// Cue points must already have been read for labels to be attached.
+pub fn parse_list(body: &[u8], info: &mut FileInfo) {
+    if body.len() < 4 {
+        return;
+    }
+
+    match &body[0..4] {
+        b"INFO" => {
+            for (id, data) in sub_chunks(body) {
+                let value = read_string(data);
+                if !value.is_empty() {
+                    info.tags.push((info_tag_name(id), value));
+                }
+            }
+        }
+        b"adtl" => {
+            for (id, data) in sub_chunks(body) {
+                if data.len() < 4 {
+                    continue;
+                }
+                let cue_id = read_u32(data, 0);
+                let cue = match info.cues.iter_mut().find(|cue| cue.id == cue_id) {
+                    Some(cue) => cue,
+                    None => continue,
+                };
+                match id {
+                    b"labl" => cue.label = Some(read_string(&data[4..])),
+                    b"note" => cue.note = Some(read_string(&data[4..])),
+                    b"ltxt" if data.len() >= 8 => {
+                        cue.length = Some(read_u32(data, 4));
+                        if data.len() > 20 && cue.label.is_none() {
+                            cue.label = Some(read_string(&data[20..]));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parse a `cue ` chunk body
+pub fn parse_cue(body: &[u8]) -> Vec<CuePoint> {
+    if body.len() < 4 {
+        return Vec::new();
+    }
+    let count = read_u32(body, 0) as usize;
+    body[4..]
+        .chunks_exact(24)
+        .take(count)
+        .map(|point| CuePoint {
+            id: read_u32(point, 0),
+            // The sample offset is the position within the data chunk
+            position: read_u32(point, 20),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Parse the loops of a `smpl` chunk body
+pub fn parse_smpl(body: &[u8]) -> Vec<SampleLoop> {
+    if body.len() < 36 {
+        return Vec::new();
+    }
+    // The loops come straight after the header, before any sampler specific data
+    let count = read_u32(body, 28) as usize;
+    body[36..]
+        .chunks_exact(24)
+        .take(count)
+        .map(|data| SampleLoop {
+            id: read_u32(data, 0),
+            loop_type: match read_u32(data, 4) {
+                0 => LoopType::Forward,
+                1 => LoopType::PingPong,
+                2 => LoopType::Backward,
+                other => LoopType::Other(other),
+            },
+            start: read_u32(data, 8),
+            end: read_u32(data, 12),
+            play_count: read_u32(data, 20),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunk with its header, padded to an even length
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    /// A `bext` body of `version`, with a loudness of -23 LUFS and no true peak
+    fn bext(version: u16) -> Vec<u8> {
+        let mut body = vec![0u8; 602];
+        let mut put = |offset: usize, bytes: &[u8]| body[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(0, b"A kick drum");
+        put(256, b"wav_viewer");
+        put(288, b"REF-1");
+        put(320, b"2024:01:02");
+        put(330, b"10-20-30");
+        put(338, &0x1234_5678u32.to_le_bytes());
+        put(342, &1u32.to_le_bytes());
+        put(346, &version.to_le_bytes());
+        put(348, &[0xab; 64]);
+        put(412, &(-2300i16).to_le_bytes());
+        put(414, &750i16.to_le_bytes());
+        put(416, &0x7fffi16.to_le_bytes());
+        put(418, &(-1850i16).to_le_bytes());
+        put(420, &(-2010i16).to_le_bytes());
+        body.extend_from_slice(b"A=PCM,F=48000\r\n\0");
+        body
+    }
+
+    #[test]
+    fn bext_fields() {
+        let bext = parse_bext(&bext(2)).unwrap();
+        assert_eq!(bext.description, "A kick drum");
+        assert_eq!(bext.originator, "wav_viewer");
+        assert_eq!(bext.originator_reference, "REF-1");
+        assert_eq!(bext.origination_date, "2024-01-02");
+        assert_eq!(bext.origination_time, "10:20:30");
+        assert_eq!(bext.time_reference, 0x1_1234_5678);
+        assert_eq!(bext.version, 2);
+        assert_eq!(bext.umid, "ab".repeat(64));
+        assert_eq!(bext.loudness_value, Some(-23.0));
+        assert_eq!(bext.loudness_range, Some(7.5));
+        assert_eq!(bext.max_true_peak_level, None);
+        assert_eq!(bext.max_momentary_loudness, Some(-18.5));
+        assert_eq!(bext.max_short_term_loudness, Some(-20.1));
+        assert_eq!(bext.coding_history, "A=PCM,F=48000");
+    }
+
+    #[test]
+    fn bext_before_version_2_has_no_loudness() {
+        let bext = parse_bext(&bext(1)).unwrap();
+        assert_eq!(bext.loudness_value, None);
+        assert_eq!(bext.max_short_term_loudness, None);
+    }
+
+    #[test]
+    fn truncated_bext() {
+        assert!(parse_bext(&bext(2)[..601]).is_none());
+        let bext = parse_bext(&bext(2)[..602]).unwrap();
+        assert_eq!(bext.coding_history, "");
+    }
+
+    /// A `cue ` body claiming `count` points, holding those of `ids` at ten times their id
+    fn cue(count: u32, ids: &[u32]) -> Vec<u8> {
+        let mut body = count.to_le_bytes().to_vec();
+        for id in ids {
+            body.extend_from_slice(&id.to_le_bytes());
+            body.extend_from_slice(&[0; 16]);
+            body.extend_from_slice(&(id * 10).to_le_bytes());
+        }
+        body
+    }
+
+    #[test]
+    fn cue_points() {
+        let cues = parse_cue(&cue(2, &[1, 2]));
+        let points: Vec<_> = cues.iter().map(|cue| (cue.id, cue.position)).collect();
+        assert_eq!(points, [(1, 10), (2, 20)]);
+    }
+
+    #[test]
+    fn truncated_cue() {
+        // A count larger than the points there, and a point cut short
+        let body = cue(3, &[1, 2]);
+        assert_eq!(parse_cue(&body).len(), 2);
+        assert_eq!(parse_cue(&body[..body.len() - 1]).len(), 1);
+        assert!(parse_cue(&body[..3]).is_empty());
+    }
+
+    /// A `smpl` body claiming `count` loops, holding `loops` of (type, start, end)
+    fn smpl(count: u32, loops: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut body = vec![0u8; 36];
+        body[28..32].copy_from_slice(&count.to_le_bytes());
+        for (idx, (loop_type, start, end)) in loops.iter().enumerate() {
+            for value in [idx as u32, *loop_type, *start, *end, 0, 3].iter() {
+                body.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        body
+    }
+
+    #[test]
+    fn sample_loops() {
+        let loops = parse_smpl(&smpl(2, &[(0, 100, 200), (7, 300, 400)]));
+        assert_eq!(loops.len(), 2);
+        assert_eq!(
+            (loops[0].id, loops[0].loop_type, loops[0].start, loops[0].end),
+            (0, LoopType::Forward, 100, 200)
+        );
+        assert_eq!(loops[0].play_count, 3);
+        assert_eq!(loops[1].loop_type, LoopType::Other(7));
+    }
+
+    #[test]
+    fn truncated_smpl() {
+        let body = smpl(2, &[(1, 100, 200), (2, 300, 400)]);
+        assert_eq!(parse_smpl(&body[..body.len() - 1]).len(), 1);
+        assert!(parse_smpl(&body[..35]).is_empty());
+        assert_eq!(parse_smpl(&smpl(5, &[(1, 100, 200)])).len(), 1);
+    }
+
+    #[test]
+    fn info_tags() {
+        // An odd length value is followed by a pad byte
+        let mut body = b"INFO".to_vec();
+        body.extend(chunk(b"INAM", b"Kick"));
+        body.extend(chunk(b"IART", b"abc"));
+        body.extend(chunk(b"ICMT", b"Test\0"));
+        body.extend(chunk(b"IXYZ", b"other"));
+        let mut info = FileInfo::default();
+        parse_list(&body, &mut info);
+        let tags: Vec<_> = info
+            .tags
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                ("Title", "Kick"),
+                ("Artist", "abc"),
+                ("Comment", "Test"),
+                ("IXYZ", "other")
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_info() {
+        // The last value is cut short, and then the next header
+        let mut body = b"INFO".to_vec();
+        body.extend(chunk(b"INAM", b"Kick"));
+        body.extend(chunk(b"IART", b"Someone"));
+        let mut info = FileInfo::default();
+        parse_list(&body[..body.len() - 4], &mut info);
+        assert_eq!(
+            info.tags,
+            [
+                ("Title".to_string(), "Kick".to_string()),
+                ("Artist".to_string(), "Some".to_string())
+            ]
+        );
+
+        let mut info = FileInfo::default();
+        parse_list(&body[..4 + 12 + 6], &mut info);
+        assert_eq!(info.tags.len(), 1);
+        parse_list(b"INF", &mut info);
+        assert_eq!(info.tags.len(), 1);
+    }
+
+    #[test]
+    fn adtl_labels() {
+        let mut info = FileInfo {
+            cues: parse_cue(&cue(2, &[1, 2])),
+            ..Default::default()
+        };
+        let mut body = b"adtl".to_vec();
+        body.extend(chunk(b"labl", b"\x01\0\0\0Kick\0"));
+        body.extend(chunk(b"note", b"\x02\0\0\0A note\0"));
+        let mut ltxt = vec![2, 0, 0, 0, 50, 0, 0, 0];
+        ltxt.extend_from_slice(&[0; 12]);
+        ltxt.extend_from_slice(b"Region\0");
+        body.extend(chunk(b"ltxt", &ltxt));
+        // Labels of cue points that aren't there are left out
+        body.extend(chunk(b"labl", b"\x09\0\0\0Missing\0"));
+        parse_list(&body, &mut info);
+
+        assert_eq!(info.cues[0].label.as_deref(), Some("Kick"));
+        assert_eq!(info.cues[0].length, None);
+        assert_eq!(info.cues[1].label.as_deref(), Some("Region"));
+        assert_eq!(info.cues[1].note.as_deref(), Some("A note"));
+        assert_eq!(info.cues[1].length, Some(50));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::audio_file::AudioFile;
+use crate::error::AppError;
+use crate::peak_cache::{self, PeakCacheWriter};
+use crate::utils::Flag;
+use crate::waveform::{Waveform, BLOCK_SAMPLES};
+use basedrop::{Handle, Shared};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// Messages sent from the loader thread to the UI
+pub enum LoadMessage {
+    /// The file has been decoded and can be played while its waveforms are built
+    Opened(Shared<AudioFile>),
+    /// The next block of waveform peaks for each channel, with the fraction of the file done so far
+    Peaks(f32, Vec<Waveform>),
+    /// All of the waveforms have been sent
+    Finished,
+    /// The file could not be opened
+    Failed(AppError),
+}
+
+/// Decodes an audio file and builds its waveforms on a worker thread, or rebuilds the
+/// waveforms of an edited file. Dropping the loader cancels it.
+pub struct Loader {
+    /// path of the file being loaded
+    pub path: String,
+    /// what the loader is doing, to show with its progress
+    pub action: &'static str,
+    rx: Receiver<LoadMessage>,
+    cancel: Flag,
+}
+
+impl Loader {
+    /// start loading a file on a new thread
+    pub fn start(path: &str, collector: Handle) -> Self {
+        let thread_path = path.to_string();
+        Self::spawn(path, "Loading", move |cancel, tx| {
+            if let Err(err) = load(&thread_path, &collector, cancel, tx) {
+                let _ = tx.send(LoadMessage::Failed(err));
+            }
+        })
+    }
+
+    /// start rebuilding the waveforms of an edited file, opened from `path`, on a new
+    /// thread. The peaks are sent in blocks from sample `position`, a multiple of
+    /// `BLOCK_SAMPLES`, to the end of the file, to append to waveforms truncated there
+    pub fn rebuild(path: &str, file: Shared<AudioFile>, position: usize) -> Self {
+        let thread_path = path.to_string();
+        Self::spawn(path, "Drawing", move |cancel, tx| {
+            if send_blocks(&thread_path, &file, position, &mut None, cancel, tx) {
+                let _ = tx.send(LoadMessage::Finished);
+            }
+        })
+    }
+
+    fn spawn<F>(path: &str, action: &'static str, work: F) -> Self
+    where
+        F: FnOnce(&Flag, &Sender<LoadMessage>) + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let cancel = Flag::new();
+
+        let thread_cancel = cancel.clone();
+        thread::spawn(move || work(&thread_cancel, &tx));
+
+        Self {
+            path: path.to_string(),
+            action,
+            rx,
+            cancel,
+        }
+    }
+
+    /// ask the worker thread to stop at the end of the current block
+    pub fn cancel(&self) {
+        self.cancel.set();
+    }
+
+    /// return the next message from the worker thread without blocking.
+    /// If the thread has stopped without finishing, `Finished` is returned
+    pub fn try_recv(&self) -> Option<LoadMessage> {
+        match self.rx.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(LoadMessage::Finished),
+        }
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Runs on the worker thread. Returns early without an error when cancelled
+// or when the receiving loader has been dropped.
+fn load(
+    path: &str,
+    collector: &Handle,
+    cancel: &Flag,
+    tx: &Sender<LoadMessage>,
+) -> Result<(), AppError> {
+    let file = Shared::new(collector, AudioFile::open(path)?);
+
+    if cancel.is_set() || tx.send(LoadMessage::Opened(Shared::clone(&file))).is_err() {
+        return Ok(());
+    }
+
+    if let Some(waveforms) = peak_cache::read(path, file.num_channels, file.num_samples) {
+        let _ = tx.send(LoadMessage::Peaks(1.0, waveforms));
+        let _ = tx.send(LoadMessage::Finished);
+        return Ok(());
+    }
+
+    // The peaks are cached as they are built. Failing to write the cache doesn't stop the load
+    let mut cache = match PeakCacheWriter::create(path, file.num_channels, file.num_samples) {
+        Ok(cache) => Some(cache),
+        Err(err) => {
+            eprintln!("Could not create peak cache for {}: {}", path, err);
+            None
+        }
+    };
+
+    if !send_blocks(path, &file, 0, &mut cache, cancel, tx) {
+        return Ok(());
+    }
+
+    if let Some(writer) = cache {
+        if let Err(err) = writer.finish() {
+            eprintln!("Could not write peak cache for {}: {}", path, err);
+        }
+    }
+
+    let _ = tx.send(LoadMessage::Finished);
+    Ok(())
+}
+
+// Build and send the peaks of each block from `position` to the end of the file, writing
+// them to `cache` as well. Returns false if cancelled or the loader has been dropped
+fn send_blocks(
+    path: &str,
+    file: &AudioFile,
+    mut position: usize,
+    cache: &mut Option<PeakCacheWriter>,
+    cancel: &Flag,
+    tx: &Sender<LoadMessage>,
+) -> bool {
+    // Each block is a multiple of every zoom level, so the blocks can be
+    // appended to each other without splitting a pixel
+    let mut buffer = vec![0.0; BLOCK_SAMPLES];
+    while position < file.num_samples {
+        if cancel.is_set() {
+            return false;
+        }
+
+        let waveforms: Vec<Waveform> = (0..file.num_channels)
+            .map(|channel| {
+                let len = file.read(channel, position, &mut buffer);
+                let mut waveform = Waveform::new();
+                waveform.push(&buffer[..len]);
+                waveform.finish();
+                waveform
+            })
+            .collect();
+
+        if let Some(writer) = cache.as_mut() {
+            if let Err(err) = writer.write_block(&waveforms) {
+                eprintln!("Could not write peak cache for {}: {}", path, err);
+                *cache = None;
+            }
+        }
+
+        position += BLOCK_SAMPLES;
+        let progress = position.min(file.num_samples) as f32 / file.num_samples as f32;
+
+        if tx.send(LoadMessage::Peaks(progress, waveforms)).is_err() {
+            return false;
+        }
+    }
+    true
+}
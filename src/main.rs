@@ -13,16 +13,123 @@ const ICON_LOCK_OPEN: &str = "\u{1f513}";
 const ICON_LOCK: &str = "\u{1f512}";
 const ICON_LOOP: &str = "\u{1f501}";
 
+// Zoom factors offered in the zoom dropdown. At 1X one pixel covers 10 ms of audio
+const ZOOM_PRESETS: [f64; 13] = [
+    0.1, 0.25, 0.5, 1.0, 3.0, 9.0, 21.0, 49.0, 147.0, 441.0, 1764.0, 7056.0, 28224.0
+];
+
+// Zoom multiplier for each step of ctrl+scroll and for the zoom buttons
+const ZOOM_SCROLL_STEP: f64 = 1.25;
+const ZOOM_BUTTON_STEP: f64 = 2.0;
+
+// Playback speeds offered in the audio panel, from `MIN_RATE` to `MAX_RATE`
+const SPEED_PRESETS: [f64; 8] = [0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 4.0];
+
+// Furthest zoom out, unless the file needs to be zoomed out further to fit the window
+const MIN_ZOOM: f64 = 0.1;
+// Closest zoom in
+const MIN_SAMPLES_PER_PIXEL: f64 = 1.0 / 64.0;
+
+// Spectrogram tiles kept on the GPU before those of other zoom levels are dropped
+const MAX_SPECTROGRAM_TILES: usize = 256;
+
+// Range of the spectrum analyser axes
+const SPECTRUM_MIN_FREQUENCY: f32 = 20.0;
+const SPECTRUM_MIN_DB: f32 = -120.0;
+// Gridlines of the spectrum analyser, in Hz and dB
+const SPECTRUM_FREQUENCY_LINES: [f32; 9] = [
+    50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0, 20000.0
+];
+const SPECTRUM_DB_STEP: f32 = 20.0;
+// Distance in pixels either side of the mouse searched for a spectral peak
+const SPECTRUM_PEAK_SEARCH: f32 = 5.0;
+// Colour of each channel's spectrum, repeating for files with more channels
+const SPECTRUM_COLORS: [(u8, u8, u8); 4] = [
+    (80, 80, 255),
+    (255, 140, 50),
+    (50, 200, 120),
+    (220, 80, 220),
+];
+
+// Range of the short-term loudness curve drawn over the waveform, in LUFS
+const LOUDNESS_CURVE_MIN: f32 = -60.0;
+const LOUDNESS_CURVE_MAX: f32 = 0.0;
+
+// Size of the issue markers along the top of the waveview, and the distance in pixels
+// within which a click on the navigator picks an issue
+const ISSUE_MARKER_HEIGHT: f32 = 8.0;
+const ISSUE_MARKER_MIN_WIDTH: f32 = 3.0;
+const NAVIGATOR_PICK_DISTANCE: f32 = 3.0;
+
+// Distance in pixels within which a click on the time axis picks a marker
+const MARKER_PICK_DISTANCE: f32 = 5.0;
+
+// How long a saved file waits for the file it replaces to be closed
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Size of the flag drawn for each marker on the time axis
+const MARKER_FLAG_SIZE: f32 = 8.0;
+
+// Minimum distance in pixels between samples for them to be marked with dots or stems
+const MIN_SAMPLE_MARKER_SPACING: f32 = 4.0;
+
+// Format a zoom factor for the zoom dropdown, e.g. "0.25X", "1.5X" or "441X"
+fn zoom_label(zoom: f64) -> String {
+    let decimals = if zoom >= 10.0 { 0 } else if zoom >= 1.0 { 1 } else { 2 };
+    let label = format!("{:.*}", decimals, zoom);
+    let label = if label.contains('.') {
+        label.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &label
+    };
+    format!("{}X", label)
+}
+
 
 
+mod analysis;
 mod audio_file;
 mod audio_stream;
+mod cli;
+mod decoder;
+mod editor;
+mod error;
+mod export;
+mod file_info;
+mod loader;
+mod marker_files;
+mod markers;
+mod meters;
+mod peak_cache;
+mod render;
+mod resampler;
+mod routing;
 mod sample_player;
+mod spectrogram;
+mod spectrum;
+#[cfg(test)]
+mod test_util;
+mod time_stretch;
 mod utils;
-use audio_stream::audio_stream;
-use basedrop::Collector;
-use cpal::{PlayStreamError, traits::StreamTrait};
+use audio_file::AudioFile;
+use audio_stream::{output_devices, output_hosts, AudioOutput, OutputDevice, OutputInfo, OutputSettings};
+use basedrop::{Collector, Shared};
+use cpal::HostId;
+use editor::{Clipboard, Edit, History, Snapshot, Step};
+use export::{BitDepth, ExportSettings};
+use loader::{LoadMessage, Loader};
+use marker_files::MarkerFormat;
+use markers::{MarkerColor, Markers};
+use meters::{meter_position, Meters};
+use render::{UnitsMode, PEAK_COLOR, RMS_COLOR, RMS_MIN_SAMPLES_PER_PIXEL};
+use resampler::ResampleQuality;
+use routing::{Routing, RoutingMatrix};
 use sample_player::*;
+use spectrogram::{
+    ColorMap, FrequencyScale, SpectrogramRenderer, SpectrogramSettings, TileKey, TileRequest,
+    WindowFunction, TILE_HEIGHT, TILE_WIDTH,
+};
+use utils::channel_label;
 mod waveform;
 use waveform::*;
 
@@ -32,7 +139,14 @@ use image::GenericImageView;
 
 use native_dialog::FileDialog;
 
+use analysis::issues::{self, IssueKind, IssueReport, DC_OFFSET_THRESHOLD};
+use analysis::loudness::{self, LoudnessReport};
+use analysis::Analysis;
+use spectrum::{SpectrumAnalyser, SPECTRUM_FFT_SIZE};
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
 
 use dasp_sample::{Sample, I24};
 
@@ -40,6 +154,9 @@ use dasp_sample::{Sample, I24};
 use femtovg::{
     renderer::OpenGl,
     Canvas,
+    ImageFlags,
+    ImageId,
+    ImageSource,
     Paint,
     Path,
 };
@@ -81,7 +198,202 @@ impl From<f32> for TimeValue {
     }
 }
 
-fn main() -> Result<(), PlayStreamError> {
+// Build a labelled row of radio buttons, one per option, each sending its event when checked
+fn build_options(
+    state: &mut State,
+    parent: Entity,
+    target: Entity,
+    title: &str,
+    options: Vec<(String, AppEvent)>,
+    checked: Option<usize>,
+) {
+    Label::new(title).build(state, parent, |builder| builder.class("option_title"));
+    let list = RadioList::new().build(state, parent, |builder| builder.class("checklist"));
+
+    let last = options.len() - 1;
+    for (idx, (text, event)) in options.into_iter().enumerate() {
+        let button = RadioButton::new()
+            .on_checked(Event::new(event).target(target))
+            .build(state, list, |builder| {
+                let builder = builder.set_text(&text).class("wide");
+                if idx == 0 {
+                    builder.class("first")
+                } else if idx == last {
+                    builder.class("last")
+                } else {
+                    builder
+                }
+            });
+
+        if Some(idx) == checked {
+            button.set_checked(state, true);
+        }
+    }
+}
+
+// The index of a setting among options that begin with "Default", which is checked when nothing is chosen
+fn checked_option<T: PartialEq>(setting: &Option<T>, values: &[T]) -> Option<usize> {
+    match setting {
+        None => Some(0),
+        Some(setting) => values.iter().position(|value| value == setting).map(|idx| idx + 1),
+    }
+}
+
+// The last component of a path, for display
+fn file_name(file_path: &str) -> String {
+    std::path::Path::new(file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_path.to_string())
+}
+
+// True if both paths name the same file
+fn same_file(path: &str, other: &str) -> bool {
+    match (std::fs::canonicalize(path), std::fs::canonicalize(other)) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => path == other,
+    }
+}
+
+// A file dialog listing the formats markers can be saved in and imported from
+fn marker_file_dialog() -> FileDialog<'static> {
+    MarkerFormat::ALL
+        .iter()
+        .fold(FileDialog::new(), |dialog, format| {
+            dialog.add_filter(format.description(), format.extensions())
+        })
+}
+
+// Format a number of samples since midnight as a time of day
+fn time_of_day(samples: u64, sample_rate: f64) -> String {
+    let seconds = samples as f64 / sample_rate;
+    let hours = (seconds / 3600.0).floor();
+    let minutes = ((seconds % 3600.0) / 60.0).floor();
+    format!("{:02}:{:02}:{:06.3}", hours, minutes, seconds % 60.0)
+}
+
+// Describe the format and metadata of an audio file, one line per entry
+fn file_info_lines(file: &AudioFile) -> Vec<String> {
+    let info = &file.info;
+    let mut lines = Vec::new();
+
+    let bit_depth = info
+        .bits_per_sample
+        .map(|bits| format!(" {}-bit", bits))
+        .unwrap_or_default();
+    lines.push(format!("Format:  {} {}{}", info.format, info.sample_format, bit_depth));
+    lines.push(format!("Sample rate:  {} Hz    Channels:  {}", file.sample_rate, file.num_channels));
+    lines.push(format!("Duration:  {}  ({} samples)", TimeValue(file.duration() as f32), file.num_samples));
+
+    if let Some(bext) = &info.bext {
+        lines.push(format!("Description:  {}", bext.description));
+        lines.push(format!("Originator:  {}  {}", bext.originator, bext.originator_reference));
+        lines.push(format!("Origination:  {} {}", bext.origination_date, bext.origination_time));
+        lines.push(format!(
+            "Time reference:  {}  ({} samples)",
+            time_of_day(bext.time_reference, file.sample_rate),
+            bext.time_reference
+        ));
+        if !bext.umid.is_empty() {
+            lines.push(format!("UMID:  {}", bext.umid));
+        }
+        if let Some(loudness) = bext.loudness_value {
+            lines.push(format!("Loudness:  {:.1} LUFS", loudness));
+        }
+        if let Some(range) = bext.loudness_range {
+            lines.push(format!("Loudness range:  {:.1} LU", range));
+        }
+        if let Some(true_peak) = bext.max_true_peak_level {
+            lines.push(format!("Max true peak:  {:.1} dBTP", true_peak));
+        }
+        if let Some(momentary) = bext.max_momentary_loudness {
+            lines.push(format!("Max momentary:  {:.1} LUFS", momentary));
+        }
+        if let Some(short_term) = bext.max_short_term_loudness {
+            lines.push(format!("Max short-term:  {:.1} LUFS", short_term));
+        }
+        for history in bext.coding_history.lines().filter(|line| !line.trim().is_empty()) {
+            lines.push(format!("Coding history:  {}", history.trim()));
+        }
+    }
+
+    for (name, value) in &info.tags {
+        lines.push(format!("{}:  {}", name, value));
+    }
+
+    for cue in &info.cues {
+        let time = TimeValue(cue.position as f32 / file.sample_rate as f32);
+        let length = cue
+            .length
+            .map(|length| format!("  (region {})", TimeValue(length as f32 / file.sample_rate as f32)))
+            .unwrap_or_default();
+        lines.push(format!("Cue {}:  {}  {}{}", cue.id, time, cue.label.as_deref().unwrap_or(""), length));
+    }
+
+    for sample_loop in &info.loops {
+        let play_count = if sample_loop.play_count == 0 {
+            "infinite".to_string()
+        } else {
+            format!("{} plays", sample_loop.play_count)
+        };
+        lines.push(format!(
+            "Loop {}:  {} - {}  ({}, {})",
+            sample_loop.id,
+            TimeValue(sample_loop.start as f32 / file.sample_rate as f32),
+            TimeValue(sample_loop.end as f32 / file.sample_rate as f32),
+            sample_loop.loop_type,
+            play_count
+        ));
+    }
+
+    lines
+}
+
+// Describe a loudness measurement, one line per group of values
+fn loudness_lines(report: &LoudnessReport, sample_rate: f64, num_samples: usize) -> Vec<String> {
+    let level = |value: Option<f64>, unit: &str| match value {
+        Some(value) => format!("{:.1} {}", value, unit),
+        None => "-".to_string(),
+    };
+    let peak = |value: f64, unit: &str| level(Some(value).filter(|value| value.is_finite()), unit);
+
+    let region = if report.start == 0 && report.end == num_samples {
+        "whole file".to_string()
+    } else {
+        format!(
+            "{} - {}",
+            TimeValue(report.start as f32 / sample_rate as f32),
+            TimeValue(report.end as f32 / sample_rate as f32)
+        )
+    };
+
+    vec![
+        format!("Region:  {}", region),
+        format!(
+            "Integrated:  {}    Loudness range:  {}",
+            level(report.integrated, "LUFS"),
+            level(report.range, "LU")
+        ),
+        format!(
+            "Max momentary:  {}    Max short-term:  {}",
+            level(report.max_momentary, "LUFS"),
+            level(report.max_short_term, "LUFS")
+        ),
+        format!(
+            "Sample peak:  {}    True peak:  {}",
+            peak(report.sample_peak, "dBFS"),
+            peak(report.true_peak, "dBTP")
+        ),
+    ]
+}
+
+fn main() {
+
+    // Commands such as `wav_viewer info take.wav` run without opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(status) = cli::run(&args[1..]) {
+        std::process::exit(status);
+    }
 
     let icon = image::open("icon.png").expect("Failed to find icon");
 
@@ -90,20 +402,10 @@ fn main() -> Result<(), PlayStreamError> {
     let gc = Collector::new();
 
     // Create the sample player and controller
-    let (mut player, mut controller) = sample_player(&gc);
-    
-    // initialize state and begin the stream
-    std::thread::spawn(move || {
-        
-        let stream = audio_stream(move |mut context| {
-            player.advance(&mut context);
-        });
-
-        // TODO - handle error
-        stream.play();
+    let (player, controller) = sample_player(&gc);
 
-        std::thread::park();
-    });
+    // Begin the stream on the default device. The app still opens if there isn't one
+    let audio_output = AudioOutput::start(player, OutputSettings::default());
 
     // Create a tuix application
     let app = Application::new(|win_desc, state, window| {
@@ -117,7 +419,7 @@ fn main() -> Result<(), PlayStreamError> {
         window.set_background_color(state, Color::rgb(40, 40, 40));
 
         // Create the app widget
-        let app_widget = AppWidget::new(gc, controller).build(state, window, |builder| builder.class("app"));
+        let app_widget = AppWidget::new(gc, controller, audio_output).build(state, window, |builder| builder.class("app"));
 
         // Process command line arguments and send a LoadAudioFile event
         let args: Vec<String> = std::env::args().collect();
@@ -136,24 +438,47 @@ fn main() -> Result<(), PlayStreamError> {
 
     // Start the tuix app event loop
     app.run();
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelMode {
+    // Show every channel, stacked vertically
+    All,
+    // Show a single channel by index
+    Single(usize),
+}
 
-    Ok(())
+// What is drawn in each channel lane
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViewMode {
+    Waveform,
+    Spectrogram,
 }
-pub enum AppError {
-    FileReadError,
+
+// A change to one of the spectrogram settings
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpectrogramOption {
+    FftSize(usize),
+    // Number of frames overlapping each point, the FFT size divided by the hop
+    Overlap(usize),
+    Window(WindowFunction),
+    Scale(FrequencyScale),
+    ColorMap(ColorMap),
 }
 
+// How individual samples are marked at sample-level zoom
 #[derive(Debug, Clone, PartialEq)]
-pub enum ChannelMode {
-    Left,
-    Right,
-    Both,
+pub enum SampleStyle {
+    Dots,
+    Stems,
 }
 
+// How the curve between samples is drawn at sample-level zoom
 #[derive(Debug, Clone, PartialEq)]
-pub enum UnitsMode {
-    Linear,
-    Decibel,
+pub enum Interpolation {
+    // Straight lines between samples
+    Line,
+    // Band-limited reconstruction, which shows inter-sample peaks
+    Sinc,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum PlayState {
@@ -168,15 +493,90 @@ pub enum ZoomMode {
     Mouse,
 }
 
+// A button of the edit panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditCommand {
+    Cut,
+    Copy,
+    Paste,
+    Delete,
+    Crop,
+    InsertSilence,
+    FadeIn,
+    FadeOut,
+    Normalize,
+    Gain,
+    Reverse,
+    Invert,
+    Undo,
+    Redo,
+    Save,
+    SaveAs,
+}
+
+// A choice in the audio panel. `None` leaves it to the host or device
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputOption {
+    Host(Option<HostId>),
+    Device(Option<String>),
+    SampleRate(Option<u32>),
+    BufferSize(Option<u32>),
+}
+
+// A choice of which channels of the file are heard on which outputs
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteOption {
+    Standard,
+    Solo(usize),
+    Custom,
+    // Connect or disconnect a channel of the file and an output of a custom routing
+    Connect(usize, usize, bool),
+}
+
 // Waveform viewer events
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppEvent {
     OpenFileDialog,
     LoadAudioFile(String),
+    CancelLoad,
     SwicthChannel(ChannelMode),
     SwitchUnits(UnitsMode),
-    SetZoomLevel(usize, ZoomMode),
+    SwitchSampleStyle(SampleStyle),
+    SwitchInterpolation(Interpolation),
+    SwitchView(ViewMode),
+    SetSpectrogram(SpectrogramOption),
+    SetZoom(f64, ZoomMode),
     FollowPlayhead(bool),
+    ToggleInfo,
+    ToggleSpectrum,
+    ToggleLoudness,
+    ShowLoudnessCurve(bool),
+    CheckIssues,
+    Poll,
+    ToggleMarkers,
+    AddMarker,
+    DeleteMarker,
+    NextMarker,
+    PreviousMarker,
+    PlayMarker,
+    SetMarkerColor(MarkerColor),
+    SaveMarkers,
+    ImportMarkers,
+    ToggleExport,
+    ExportChannel(usize, bool),
+    SetExportDepth(BitDepth),
+    ExportDither(bool),
+    ExportSelection,
+    ToggleEdit,
+    Edit(EditCommand),
+    ToggleAudio,
+    SetOutput(OutputOption),
+    RefreshDevices,
+    SetResampleQuality(ResampleQuality),
+    SetRate(f64),
+    SetSpeedMode(SpeedMode),
+    Route(RouteOption),
+    SpectrumFollowPlayhead(bool),
     Loop(bool),
     Volume(f32),
 
@@ -193,8 +593,9 @@ pub enum AppEvent {
 
 pub struct AppWidget {
 
-    zoom_level: usize,
-    samples_per_pixel: usize,
+    // Zoom factor, relative to 10 ms per pixel
+    zoom: f64,
+    samples_per_pixel: f32,
 
     zoom_pos_pixel: f32,
 
@@ -210,11 +611,17 @@ pub struct AppWidget {
 
     channel_mode: ChannelMode,
     units_mode: UnitsMode,
+    sample_style: SampleStyle,
+    interpolation: Interpolation,
+    view_mode: ViewMode,
     play_state: PlayState,
 
     time_label: Entity,
     value_label: Entity,
     playhead_label: Entity,
+    error_label: Entity,
+    progress_label: Entity,
+    cancel_button: Entity,
 
     waveview: Entity,
 
@@ -245,33 +652,133 @@ pub struct AppWidget {
     cursor_label: Entity,
     select_label: Entity,
     zoom_levels_dropdown: Entity,
-
-    // Replace this with a better method at some point
-    zoom_0: Entity,
-    zoom_1: Entity,
-    zoom_2: Entity,
-    zoom_3: Entity,
-    zoom_4: Entity,
-    zoom_5: Entity,
-    zoom_6: Entity,
-    zoom_7: Entity,
-    zoom_8: Entity,
-
-    waveform_left: Waveform,
-    waveform_right: Waveform,
+    channels_list: Entity,
+    channel_buttons: Vec<Entity>,
+    info_panel: Entity,
+    info_labels: Vec<Entity>,
+    show_info: bool,
+
+    // Zoom preset radio buttons, with their zoom factors
+    zoom_buttons: Vec<(f64, Entity)>,
+
+    // Spectrogram tiles are rendered in the background and kept as images
+    spectrogram_panel: Entity,
+    spectrogram_settings: SpectrogramSettings,
+    spectrogram: SpectrogramRenderer,
+    spectrogram_tiles: HashMap<TileKey, ImageId>,
+    spectrogram_pending: HashSet<TileKey>,
+    // Images to delete on the next draw, when the canvas is available
+    spectrogram_discarded: Vec<ImageId>,
+
+    // Spectrum analyser for the selected region, and optionally the playhead
+    spectrum_panel: Entity,
+    spectrum_view: Entity,
+    spectrum_label: Entity,
+    show_spectrum: bool,
+    spectrum_follow_playhead: bool,
+    // Computes the playhead spectrum while drawing
+    spectrum_analyser: SpectrumAnalyser,
+    spectrum: Option<Analysis<Vec<Vec<f32>>>>,
+    // One spectrum per channel for the selection it was computed for
+    selection_spectra: Vec<Vec<f32>>,
+    spectrum_selection: Option<(usize, usize)>,
+    // Position of the mouse over the spectrum, from the left of the view
+    spectrum_mouse: Option<f32>,
+
+    // Loudness of the selection, or the whole file if nothing is selected
+    loudness_panel: Entity,
+    loudness_status: Entity,
+    loudness_labels: Vec<Entity>,
+    show_loudness: bool,
+    show_loudness_curve: bool,
+    loudness: Option<Analysis<LoudnessReport>>,
+    loudness_report: Option<LoudnessReport>,
+
+    // Named markers and regions of the file
+    markers: Markers,
+    // Path of the loaded file, which markers are saved into a copy of
+    file_path: String,
+    selected_marker: Option<u32>,
+    dragging_marker: Option<u32>,
+    marker_panel: Entity,
+    marker_name: Entity,
+    marker_color_buttons: Vec<(MarkerColor, Entity)>,
+    show_markers: bool,
+    // Playback stops here when playing a region without looping
+    play_until: Option<usize>,
+
+    // Clipping, silence and DC offset found in the file
+    issues_label: Entity,
+    issues: Option<Analysis<IssueReport>>,
+    issue_report: Option<IssueReport>,
+
+    // Writes the selection, or the whole file, to a new WAV file
+    export_panel: Entity,
+    export_channels: Entity,
+    export_channel_buttons: Vec<Entity>,
+    export_status: Entity,
+    show_export: bool,
+    export_settings: ExportSettings,
+    export: Option<Analysis<io::Result<()>>>,
+    export_path: String,
+
+    // Destructive edits of the loaded file, which can be undone until another file is opened
+    edit_panel: Entity,
+    edit_silence: Entity,
+    edit_gain: Entity,
+    edit_status: Entity,
+    show_edit: bool,
+    history: History,
+    clipboard: Option<Clipboard>,
+    // Length of silence inserted, in seconds, and the change of level, in dB
+    silence_seconds: f64,
+    gain_db: f32,
+    saving: Option<Analysis<io::Result<()>>>,
+    save_path: String,
+    // When the file saved over started to be replaced
+    replacing: Option<Instant>,
+
+    // Choice of output device, rate and buffer size. Devices are only listed while the panel is shown
+    audio_panel: Entity,
+    audio_options: Entity,
+    audio_status: Entity,
+    show_audio: bool,
+    audio_output: AudioOutput,
+    output_settings: OutputSettings,
+    output_devices: Vec<OutputDevice>,
+    // The stream playing, or `None` when there is no audio
+    output_info: Option<OutputInfo>,
+    // How the file is converted to the device rate
+    resample_quality: ResampleQuality,
+    // Playback speed relative to the file, and whether the pitch follows it
+    playback_rate: f64,
+    speed_mode: SpeedMode,
+    // Channels of the file heard on each output. Rebuilt when the file or output changes
+    routing_view: Entity,
+    routing_options: Entity,
+    routing: Routing,
+
+    // Output level meters, fed by the audio thread
+    meter_view: Entity,
+    meters: Meters,
+
+    // One waveform per channel in the loaded file
+    waveforms: Vec<Waveform>,
+    // Decodes the file being opened and builds its waveforms in the background
+    loader: Option<Loader>,
 
 }
 
 impl AppWidget {
-    pub fn new(collector: Collector, controller: SamplePlayerController) -> Self {
+    pub fn new(collector: Collector, controller: SamplePlayerController, audio_output: AudioOutput) -> Self {
         Self {
 
-            zoom_level: 3,
+            zoom: 1.0,
 
             mute: 1.0,
             volume: 1.0,
 
-            samples_per_pixel: 441,
+            samples_per_pixel: 441.0,
 
             zoom_pos_pixel: 0.0,
 
@@ -287,13 +794,19 @@ impl AppWidget {
             select: 0,
 
 
-            channel_mode: ChannelMode::Left,
+            channel_mode: ChannelMode::All,
             units_mode: UnitsMode::Linear,
+            sample_style: SampleStyle::Dots,
+            interpolation: Interpolation::Sinc,
+            view_mode: ViewMode::Waveform,
             play_state: PlayState::Stopped,
 
             time_label: Entity::null(),
             value_label: Entity::null(),
             playhead_label: Entity::null(),
+            error_label: Entity::null(),
+            progress_label: Entity::null(),
+            cancel_button: Entity::null(),
 
             waveview: Entity::null(),
 
@@ -318,260 +831,1969 @@ impl AppWidget {
             cursor_label: Entity::null(),
             select_label: Entity::null(),
             zoom_levels_dropdown: Entity::null(),
-
-            zoom_0: Entity::null(),
-            zoom_1: Entity::null(),
-            zoom_2: Entity::null(),
-            zoom_3: Entity::null(),
-            zoom_4: Entity::null(),
-            zoom_5: Entity::null(),
-            zoom_6: Entity::null(),
-            zoom_7: Entity::null(),
-            zoom_8: Entity::null(),
-
-
-            waveform_left: Waveform::new(),
-            waveform_right: Waveform::new(),
+            channels_list: Entity::null(),
+            channel_buttons: Vec::new(),
+            info_panel: Entity::null(),
+            info_labels: Vec::new(),
+            show_info: false,
+
+            zoom_buttons: Vec::new(),
+
+            spectrogram_panel: Entity::null(),
+            spectrogram_settings: SpectrogramSettings::default(),
+            spectrogram: SpectrogramRenderer::new(),
+            spectrogram_tiles: HashMap::new(),
+            spectrogram_pending: HashSet::new(),
+            spectrogram_discarded: Vec::new(),
+
+            spectrum_panel: Entity::null(),
+            spectrum_view: Entity::null(),
+            spectrum_label: Entity::null(),
+            show_spectrum: false,
+            spectrum_follow_playhead: false,
+            spectrum_analyser: SpectrumAnalyser::new(),
+            spectrum: None,
+            selection_spectra: Vec::new(),
+            spectrum_selection: None,
+            spectrum_mouse: None,
+
+            loudness_panel: Entity::null(),
+            loudness_status: Entity::null(),
+            loudness_labels: Vec::new(),
+            show_loudness: false,
+            show_loudness_curve: false,
+            loudness: None,
+            loudness_report: None,
+
+            markers: Markers::new(),
+            selected_marker: None,
+            dragging_marker: None,
+            marker_panel: Entity::null(),
+            file_path: String::new(),
+            marker_name: Entity::null(),
+            marker_color_buttons: Vec::new(),
+            show_markers: false,
+            play_until: None,
+
+            issues_label: Entity::null(),
+            issues: None,
+            issue_report: None,
+
+            export_panel: Entity::null(),
+            export_channels: Entity::null(),
+            export_channel_buttons: Vec::new(),
+            export_status: Entity::null(),
+            show_export: false,
+            export_settings: ExportSettings {
+                channels: Vec::new(),
+                bit_depth: BitDepth::Int24,
+                dither: false,
+            },
+            export: None,
+            export_path: String::new(),
+
+            edit_panel: Entity::null(),
+            edit_silence: Entity::null(),
+            edit_gain: Entity::null(),
+            edit_status: Entity::null(),
+            show_edit: false,
+            history: History::new(),
+            clipboard: None,
+            silence_seconds: 1.0,
+            gain_db: -3.0,
+            saving: None,
+            save_path: String::new(),
+            replacing: None,
+
+            audio_panel: Entity::null(),
+            audio_options: Entity::null(),
+            audio_status: Entity::null(),
+            show_audio: false,
+            audio_output,
+            output_settings: OutputSettings::default(),
+            output_devices: Vec::new(),
+            output_info: None,
+            resample_quality: ResampleQuality::default(),
+            playback_rate: 1.0,
+            speed_mode: SpeedMode::Varispeed,
+            routing_view: Entity::null(),
+            routing_options: Entity::null(),
+            routing: Routing::default(),
+
+            meter_view: Entity::null(),
+            meters: Meters::new(),
+
+
+            waveforms: Vec::new(),
+            loader: None,
         }
     }
 }
 
 impl AppWidget { 
-    // Draw the audio waveforms
-    fn draw_channel(
-        &self,
-        state: &mut State,
-        entity: Entity,
-        waveform: &Waveform,
-        level: usize,
-        start: usize,
-        posy: f32,
-        height: f32,
-        canvas: &mut Canvas<OpenGl>,
-    ) {
-        let x = state.data.get_posx(self.waveview);
-        let y = posy;
-        let w = state.data.get_width(self.waveview);
-        let h = height;
+    // Start loading an audio file in the background. Any load already in progress is cancelled
+    fn load_audio_file(&mut self, state: &mut State, entity: Entity, file_path: &str) {
+        self.loader = Some(Loader::start(file_path, self.controller.collector()));
 
-        //if data.len() > 0 {
-            //let audio = &data[self.start as usize..self.end as usize];
-            //let audio = data;
+        self.show_error(state, "");
+        self.progress_label.set_text(state, &format!("Loading {}", file_name(file_path)));
+        self.progress_label.set_display(state, Display::Flexbox);
+        self.cancel_button.set_display(state, Display::Flexbox);
 
+        // Keep redrawing so that messages from the loader are picked up in on_draw
+        state.style.border_color.play_animation(entity, self.random_animation);
+    }
 
-            // // Minimum time
-            // let start_time = self.start as f32 / self.sample_rate as f32;
-            // let end_time = self.end as f32 / self.sample_rate as f32;
+    // Handle the messages sent by the loader since the last frame
+    fn poll_loader(&mut self, state: &mut State, entity: Entity) {
+        while let Some(message) = self.loader.as_ref().and_then(|loader| loader.try_recv()) {
+            match message {
+                LoadMessage::Opened(file) => {
+                    self.open_loaded_file(state, entity, file);
+                }
 
-            // // Draw grid
-            // let first = round_up(start_time as u32, 1);
-            // let last = round_up(end_time as u32, 1);
+                LoadMessage::Peaks(progress, blocks) => {
+                    let num_of_pixels = state.data.get_width(entity) as usize;
+                    for (waveform, block) in self.waveforms.iter_mut().zip(blocks.iter()) {
+                        waveform.append(block);
+                        waveform.set_num_pixels(self.num_of_samples, num_of_pixels);
+                    }
 
-            // for n in (first..last+1) {
-            //     let sample = self.sample_rate as f32 * n as f32 - self.start as f32;
-            //     let pixel = ((1.0 / self.samples_per_pixel as f32) * sample).round();
-            //     let mut path = Path::new();
-            //     path.move_to(x + pixel, y + 20.0);
-            //     path.line_to(x + pixel, y + 30.0);
-            //     let mut paint = Paint::color(femtovg::Color::rgba(90, 90, 90, 255));
-            //     paint.set_line_width(1.0);
-            //     paint.set_anti_alias(false);
-            //     canvas.stroke_path(&mut path, paint);
-            // }
+                    if let Some(loader) = self.loader.as_ref() {
+                        let text = format!("{} {} {:.0}%", loader.action, file_name(&loader.path), progress * 100.0);
+                        self.progress_label.set_text(state, &text);
+                    }
+                }
 
-            // Create two paths for min/max and rms
-            let mut path1 = Path::new();
-            let mut path2 = Path::new();
+                LoadMessage::Finished => {
+                    self.finish_loading(state);
+                }
 
-            // Move to the center of the drawing region
-            path1.move_to(x, y + h / 2.0);
-            path2.move_to(x, y + h / 2.0);
+                LoadMessage::Failed(err) => {
+                    // The previously loaded file stays in place
+                    let file_path = self.loader.as_ref().map(|loader| loader.path.clone()).unwrap_or_default();
+                    eprintln!("Failed to load {}: {}", file_path, err);
+                    self.finish_loading(state);
+                    self.show_error(state, &format!("Could not open {}: {}", file_path, err));
+                }
+            }
+        }
+    }
 
+    // Switch the player and the view over to a newly decoded file. Its waveforms
+    // start empty and are filled in as the loader sends them
+    fn open_loaded_file(&mut self, state: &mut State, entity: Entity, file: Shared<AudioFile>) {
+        self.num_of_channels = file.num_channels;
+        self.sample_rate = file.sample_rate;
+        self.num_of_samples = file.num_samples;
+
+        self.waveforms = (0..file.num_channels).map(|_| Waveform::new()).collect();
+
+        self.markers = Markers::from_cues(&file.info.cues);
+        self.file_path = self.loader.as_ref().map(|loader| loader.path.clone()).unwrap_or_default();
+        self.select_marker(state, None);
+        self.play_until = None;
+
+        self.controller.set_file(file);
+        self.controller.seek(0.0);
+        self.is_playing = false;
+
+        self.channel_mode = ChannelMode::All;
+        self.build_channel_selector(state, entity);
+        self.build_info_panel(state);
+        self.reset_analysis(state);
+        self.export_settings.channels = (0..file.num_channels).collect();
+        self.build_export_channels(state, entity);
+        self.build_routing_options(state, entity);
+        self.history = History::new();
+        self.show_edit_status(state);
+
+        state.insert_event(Event::new(AppEvent::SetZoom(1.0, ZoomMode::Cursor)).target(entity));
+    }
 
+    // Drop the spectrogram, spectra, loudness and issues found in the last file or edit
+    fn reset_analysis(&mut self, state: &mut State) {
+        self.reset_spectrogram();
+        self.spectrum = None;
+        self.selection_spectra.clear();
+        self.spectrum_selection = None;
+        self.loudness = None;
+        self.loudness_report = None;
+        self.build_loudness_panel(state);
+        self.issues = None;
+        self.issue_report = None;
+        self.issues_label.set_text(state, "");
+    }
 
-            // TODO
-            // Sample-level drawing
-            // if self.samples_per_pixel < 1.0 {
-            //     for pixel in 0..w as u32 {
-            //         let pixels_per_sample = (1.0 / self.samples_per_pixel) as u32;
+    // Apply an edit to the selection and record it so that it can be undone. The level
+    // changes apply to the whole file when nothing is selected
+    fn edit(&mut self, state: &mut State, entity: Entity, command: EditCommand) {
+        let file = match self.controller.file.as_ref() {
+            Some(file) => Shared::clone(file),
+            None => return,
+        };
+        if let Some(loader) = self.loader.as_ref() {
+            let text = format!("Wait for {} to finish before editing", loader.action.to_lowercase());
+            self.show_error(state, &text);
+            return;
+        }
 
-            //         if pixel % pixels_per_sample == 0 {
-            //             let sample =
-            //                 self.start + (self.samples_per_pixel * pixel as f32).floor() as usize;
-            //             path1.move_to(x + (pixel as f32), y + h / 2.0);
-            //             path1.line_to(
-            //                 x + (pixel as f32),
-            //                 y + h / 2.0 - data[sample as usize] * h / 2.0,
-            //             );
+        let (mut start, mut end) = (self.cursor.min(self.select), self.cursor.max(self.select));
+        let edit = match command {
+            EditCommand::Cut | EditCommand::Copy => {
+                if start == end {
+                    self.show_error(state, "Select the audio to copy");
+                    return;
+                }
+                self.clipboard = Some(Clipboard::copy(&file, start, end));
+                if command == EditCommand::Copy {
+                    return;
+                }
+                Edit::Delete
+            }
+            EditCommand::Paste => match self.clipboard.clone() {
+                Some(clipboard) => Edit::Paste(clipboard),
+                None => {
+                    self.show_error(state, "Nothing has been copied");
+                    return;
+                }
+            },
+            EditCommand::Delete => Edit::Delete,
+            EditCommand::Crop => Edit::Crop,
+            EditCommand::InsertSilence => Edit::InsertSilence((self.silence_seconds * self.sample_rate).round() as usize),
+            EditCommand::FadeIn => Edit::FadeIn,
+            EditCommand::FadeOut => Edit::FadeOut,
+            EditCommand::Normalize => Edit::Normalize,
+            EditCommand::Gain => Edit::Gain(self.gain_db),
+            EditCommand::Reverse => Edit::Reverse,
+            EditCommand::Invert => Edit::Invert,
+            EditCommand::Undo => return self.restore(state, entity, true),
+            EditCommand::Redo => return self.restore(state, entity, false),
+            EditCommand::Save => return self.save(state, false),
+            EditCommand::SaveAs => return self.save(state, true),
+        };
 
-            //             path2.move_to(x + (pixel as f32), y + h / 2.0);
-            //             path2.line_to(
-            //                 x + (pixel as f32),
-            //                 y + h / 2.0 - data[sample as usize] * h / 2.0,
-            //             );
-            //         }
-            //     }
-            // } else {
+        let is_level_change = matches!(edit, Edit::Normalize | Edit::Gain(_) | Edit::Reverse | Edit::Invert);
+        if is_level_change && start == end {
+            start = 0;
+            end = file.num_samples;
+        }
 
-                //let samples_per_pixel = audio.len() as f32 / w;
+        let before = Snapshot {
+            file: Shared::clone(&file),
+            markers: self.markers.clone(),
+        };
+        match editor::apply(&file, &mut self.markers, &edit, start, end) {
+            Ok((edited, change)) => {
+                self.show_error(state, "");
+                let edited = Shared::new(&self.controller.collector(), edited);
+                let after = Snapshot {
+                    file: Shared::clone(&edited),
+                    markers: self.markers.clone(),
+                };
+                self.history.push(Step { before, after, change });
+                self.show_edited(state, entity, edited, change.start, change.new_end);
+
+                // Select what was inserted, or leave the cursor where audio was removed
+                if change.new_end != change.old_end || command == EditCommand::Crop {
+                    self.set_cursor(state, change.start, change.new_end);
+                }
+            }
+            Err(err) => self.show_error(state, &format!("Could not edit: {}", err)),
+        }
+    }
 
-                //let mut chunks = audio.chunks(samples_per_pixel as usize);
-                //let spp = self.num_of_samples as f32 / w;
-                //let mut chunks = audio.chunks(spp as usize);
+    // Go back to the file and markers from before the last edit, or forward again after an undo
+    fn restore(&mut self, state: &mut State, entity: Entity, undo: bool) {
+        if self.loader.is_some() {
+            return;
+        }
 
-                //println!("Samples per pixel: {}  {}", self.samples_per_pixel, audio.len() as f32 / w);
+        let restored = if undo {
+            self.history.undo().map(|step| (step.before.clone(), step.change.start, step.change.old_end))
+        } else {
+            self.history.redo().map(|step| (step.after.clone(), step.change.start, step.change.new_end))
+        };
+        if let Some((snapshot, start, end)) = restored {
+            self.show_error(state, "");
+            self.markers = snapshot.markers;
+            self.show_edited(state, entity, snapshot.file, start, end);
+            self.set_cursor(state, start, end);
+        }
+    }
 
-                let waveform_data = &waveform.get_data(level);
+    // Play and display an edited file. Only the peaks of the samples from `start` to `end`
+    // are rebuilt. When the length changed, everything after `start` moved, so the blocks
+    // from there to the end of the file are rebuilt by the loader and drawn as they arrive
+    fn show_edited(&mut self, state: &mut State, entity: Entity, file: Shared<AudioFile>, start: usize, end: usize) {
+        let num_of_pixels = state.data.get_width(entity) as usize;
+        if file.num_samples != self.num_of_samples {
+            let position = start / BLOCK_SAMPLES * BLOCK_SAMPLES;
+            for waveform in self.waveforms.iter_mut() {
+                waveform.truncate(position);
+                waveform.set_num_pixels(file.num_samples, num_of_pixels);
+            }
+            self.loader = Some(Loader::rebuild(&self.file_path, Shared::clone(&file), position));
+            let text = format!("Drawing {}", file_name(&self.file_path));
+            self.progress_label.set_text(state, &text);
+            self.progress_label.set_display(state, Display::Flexbox);
+            state.style.border_color.play_animation(entity, self.random_animation);
+        } else {
+            for (channel, waveform) in self.waveforms.iter_mut().enumerate() {
+                waveform.rebuild(start, end, file.num_samples, |position, out| {
+                    file.read(channel, position, out)
+                });
+                waveform.set_num_pixels(file.num_samples, num_of_pixels);
+            }
+        }
 
-                 
+        // Keep the zoom, moving the view back if the file got shorter
+        self.num_of_samples = file.num_samples;
+        let visible = self.end - self.start;
+        self.start = self.start.min(self.num_of_samples.saturating_sub(visible));
+        self.end = (self.start + visible).min(self.num_of_samples.saturating_sub(1));
+        self.cursor = self.cursor.min(self.num_of_samples);
+        self.select = self.select.min(self.num_of_samples);
+
+        self.controller.set_file(file);
+        self.play_until = None;
+        self.select_marker(state, None);
+        self.reset_analysis(state);
+        self.show_edit_status(state);
+        state.insert_event(Event::new(WindowEvent::Redraw));
+    }
 
-                //println!("Start: {}", start);
+    // Write the edited file on a worker thread. Save writes over the file when it was opened
+    // from a WAV file whose format can be written again unchanged, and otherwise asks for a
+    // new WAV file, as Save As always does
+    fn save(&mut self, state: &mut State, save_as: bool) {
+        let file = match self.controller.file.as_ref() {
+            Some(file) => Shared::clone(file),
+            None => return,
+        };
+        if self.loader.is_some() || self.saving.is_some() || self.replacing.is_some() {
+            return;
+        }
 
-                for pixel in 0..w as usize {
+        let bit_depth = editor::save_bit_depth(&file);
+        if !save_as && file.info.format == "WAV" && bit_depth.is_none() {
+            let text = format!(
+                "{} can't be saved in its own format without losing detail, use Save As",
+                file_name(&self.file_path)
+            );
+            self.show_error(state, &text);
+            return;
+        }
 
-                    if start + pixel >= waveform_data.len() {
-                        break;
-                    }
+        let path = if save_as || file.info.format != "WAV" {
+            match FileDialog::new().add_filter("WAV file", &["wav"]).show_save_single_file() {
+                Ok(Some(path)) => path.to_string_lossy().to_string(),
+                Ok(None) => return,
+                Err(err) => {
+                    self.show_error(state, &format!("Could not open file dialog: {}", err));
+                    return;
+                }
+            }
+        } else {
+            self.file_path.clone()
+        };
+        // A new file can hold every sample exactly as 32 bit floats
+        let bit_depth = bit_depth.unwrap_or(BitDepth::Float32);
+
+        self.show_error(state, "");
+        self.save_path = path.clone();
+        let source_path = self.file_path.clone();
+        let markers = self.markers.clone();
+        let num_samples = file.num_samples;
+        self.saving = Some(Analysis::start(file, 0, num_samples, move |file, _, _, progress| {
+            editor::save(file, &source_path, &path, bit_depth, &markers, progress)
+        }));
+    }
 
-                    let v_min = to_f32(waveform_data[start + pixel].0);
-                    let v_max = to_f32(waveform_data[start + pixel].1);
-                    let v_mean = to_f32(waveform_data[start + pixel].2);
+    fn poll_saving(&mut self, state: &mut State, entity: Entity) {
+        if self.replacing.is_some() {
+            self.replace_saved_file(state, entity);
+            return;
+        }
 
-                    match self.units_mode {
-                        UnitsMode::Decibel => {
-                            let v_min_db =
-                                1.0 + (20.0 * v_min.abs().log10()).max(-60.0) / 60.0;
-                            let v_max_db =
-                                1.0 + (20.0 * v_max.abs().log10()).max(-60.0) / 60.0;
+        let saving = match self.saving.as_ref() {
+            Some(saving) => saving,
+            None => return,
+        };
 
-                            let v_mean_db =
-                                1.0 + (20.0 * v_mean.abs().log10()).max(-60.0) / 60.0;
+        match saving.try_recv() {
+            Some(Ok(())) => {
+                self.saving = None;
+                if same_file(&self.save_path, &self.file_path) {
+                    // The samples are read from the memory-mapped file, which has to be closed
+                    // before it can be replaced, and is then opened again as saved
+                    self.close_file(state);
+                    self.replacing = Some(Instant::now());
+                    self.replace_saved_file(state, entity);
+                } else {
+                    match editor::replace(&self.save_path) {
+                        Ok(()) => {
+                            self.file_path = self.save_path.clone();
+                            self.history.mark_saved();
+                            self.show_edit_status(state);
+                        }
+                        Err(err) => self.show_save_error(state, &err),
+                    }
+                }
+            }
+            Some(Err(err)) => {
+                self.saving = None;
+                self.show_save_error(state, &err);
+            }
+            None => {
+                let text = format!("Saving {:.0}%", saving.progress() * 100.0);
+                self.edit_status.set_text(state, &text);
+            }
+        }
+    }
 
-                            let v_min_db = if v_min < 0.0 { -v_min_db } else { v_min_db };
+    // Move the saved file over the one it was opened from once the player and any analysis
+    // have let go of the old one, then open it again
+    fn replace_saved_file(&mut self, state: &mut State, entity: Entity) {
+        let started = match self.replacing {
+            Some(started) => started,
+            None => return,
+        };
 
-                            let v_max_db = if v_max < 0.0 { -v_max_db } else { v_max_db };
+        self.collector.collect();
+        match editor::replace(&self.save_path) {
+            Ok(()) => {
+                self.replacing = None;
+                let path = self.save_path.clone();
+                self.load_audio_file(state, entity, &path);
+            }
+            Err(_) if started.elapsed() < REPLACE_TIMEOUT => {
+                state.insert_event(Event::new(WindowEvent::Redraw));
+            }
+            Err(err) => {
+                self.replacing = None;
+                let path = self.file_path.clone();
+                self.load_audio_file(state, entity, &path);
+                let text = format!(
+                    "Could not replace {}, the edited file was left in {}: {}",
+                    file_name(&self.save_path),
+                    editor::temp_path(&self.save_path),
+                    err
+                );
+                self.show_error(state, &text);
+            }
+        }
+    }
 
-                            let v_mean_db = if v_mean < 0.0 { -v_mean_db } else { v_mean_db };
+    fn show_save_error(&mut self, state: &mut State, err: &io::Error) {
+        self.show_edit_status(state);
+        let text = format!("Could not save {}: {}", file_name(&self.save_path), err);
+        self.show_error(state, &text);
+    }
 
+    // Let go of the file, its edits and everything worked out from it
+    fn close_file(&mut self, state: &mut State) {
+        self.controller.close_file();
+        self.is_playing = false;
+        self.history = History::new();
+        self.export = None;
+        self.reset_analysis(state);
+    }
 
+    fn show_edit_status(&mut self, state: &mut State) {
+        let text = if self.history.is_modified() {
+            "Not saved"
+        } else {
+            ""
+        };
+        self.edit_status.set_text(state, text);
+    }
 
-                            path1.line_to(x + (pixel as f32), y + h / 2.0 - v_min_db * h / 2.0);
-                            path1.line_to(x + (pixel as f32), y + h / 2.0 - v_max_db * h / 2.0);
+    // Move the cursor and selection to a region and centre the view on it
+    fn go_to(&mut self, state: &mut State, entity: Entity, start: usize, end: usize) {
+        self.set_cursor(state, start, end);
 
-                            path2.move_to(x + (pixel as f32), y + h / 2.0 + v_mean_db * h / 2.0);
-                            path2.line_to(x + (pixel as f32), y + h / 2.0 - v_mean_db * h / 2.0);
-                        }
+        // A span wider than the view is shown from its start, so that the cursor stays in view
+        let visible = (state.data.get_width(entity) * self.samples_per_pixel) as usize;
+        let first = if end.saturating_sub(start) >= visible {
+            start
+        } else {
+            (start + (end.max(start) - start) / 2).saturating_sub(visible / 2)
+        };
+        self.start = first.min(self.num_of_samples.saturating_sub(visible));
+        self.end = (self.start + visible).min(self.num_of_samples.saturating_sub(1));
+    }
 
-                        UnitsMode::Linear => {
-                            path1.line_to(x + (pixel as f32), y + h / 2.0 - v_min * h / 2.0);
-                            path1.line_to(x + (pixel as f32), y + h / 2.0 - v_max * h / 2.0);
+    // Move the cursor and the end of the selection, updating their time displays
+    fn set_cursor(&mut self, state: &mut State, cursor: usize, select: usize) {
+        self.cursor = cursor;
+        self.select = select;
 
-                            path2.move_to(x + (pixel as f32), y + h / 2.0 + v_mean * h / 2.0);
-                            path2.line_to(x + (pixel as f32), y + h / 2.0 - v_mean * h / 2.0);
-                        }
-                    }
-                }
+        let time_value: TimeValue = (self.cursor as f32 / self.sample_rate as f32).into();
+        self.cursor_label.set_text(state, &format!("Cursor:  {}", time_value));
+        let time_value: TimeValue = (self.select as f32 / self.sample_rate as f32).into();
+        self.select_label.set_text(state, &format!("Select End:  {}", time_value));
 
-                // for chunk in 0..w as u32 {
-                //     if let Some(c) = chunks.next() {
-                //         let v_min = *c
-                //             .iter()
-                //             .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                //             .unwrap();
-                //         let v_max = *c
-                //             .iter()
-                //             .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                //             .unwrap();
-                //         let v_mean: f32 = (c.iter().map(|s| s*s).sum::<f32>() / c.len() as f32).sqrt();
+        state.insert_event(Event::new(WindowEvent::Redraw));
+    }
 
-                //         match self.units_mode {
-                //             UnitsMode::Decibel => {
-                //                 let v_min_db =
-                //                     1.0 + (20.0 * v_min.abs().log10()).max(-60.0) / 60.0;
-                //                 let v_max_db =
-                //                     1.0 + (20.0 * v_max.abs().log10()).max(-60.0) / 60.0;
+    // Select a marker for editing, showing its name and colour in the marker panel
+    fn select_marker(&mut self, state: &mut State, id: Option<u32>) {
+        self.selected_marker = id;
+        let marker = id.and_then(|id| self.markers.get(id));
+
+        let name = marker.map(|marker| marker.name.clone()).unwrap_or_default();
+        state.insert_event(Event::new(TextboxEvent::SetValue(name)).target(self.marker_name));
+
+        let color = marker.map(|marker| marker.color);
+        for (button_color, button) in self.marker_color_buttons.iter() {
+            let checkbox_event = if Some(*button_color) == color {
+                CheckboxEvent::Check
+            } else {
+                CheckboxEvent::Uncheck
+            };
+            state.insert_event(Event::new(checkbox_event).target(*button));
+        }
+    }
 
-                //                 let v_mean_db =
-                //                     1.0 + (20.0 * v_mean.abs().log10()).max(-60.0) / 60.0;
+    // Select a marker and move the cursor to it, or select the region
+    fn go_to_marker(&mut self, state: &mut State, entity: Entity, id: u32) {
+        if let Some((start, end)) = self.markers.get(id).map(|marker| (marker.position, marker.end())) {
+            self.select_marker(state, Some(id));
+            self.go_to(state, entity, start, end);
+        }
+    }
 
-                //                 let v_min_db = if v_min < 0.0 { -v_min_db } else { v_min_db };
+    // Return the marker closest to a position on the time axis, if one is close enough
+    fn marker_at(&self, state: &State, posx: f32) -> Option<u32> {
+        let x = state.data.get_posx(self.waveview);
+        self.markers
+            .iter()
+            .map(|marker| {
+                let marker_x = x + (marker.position as f32 - self.start as f32) / self.samples_per_pixel;
+                ((marker_x - posx).abs(), marker.id)
+            })
+            .filter(|(distance, _)| *distance <= MARKER_PICK_DISTANCE)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+            .map(|(_, id)| id)
+    }
 
-                //                 let v_max_db = if v_max < 0.0 { -v_max_db } else { v_max_db };
+    // Draw markers as flags on the time axis with a line through the waveview,
+    // and regions as a shaded band between their start and end
+    fn draw_markers(&self, state: &mut State, canvas: &mut Canvas<OpenGl>) {
+        let x = state.data.get_posx(self.waveview);
+        let y = state.data.get_posy(self.waveview);
+        let w = state.data.get_width(self.waveview);
+        let h = state.data.get_height(self.waveview);
 
-                //                 let v_mean_db = if v_mean < 0.0 { -v_mean_db } else { v_mean_db };
+        let time_axis_posy = state.data.get_posy(self.time_axis);
+        let time_axis_height = state.data.get_height(self.time_axis);
 
+        let pixel = |position: usize| x + (position as f32 - self.start as f32) / self.samples_per_pixel;
 
+        canvas.save();
+        canvas.scissor(x, time_axis_posy, w, y + h - time_axis_posy);
 
-                //                 path1.line_to(x + (chunk as f32), y + h / 2.0 - v_min_db * h / 2.0);
-                //                 path1.line_to(x + (chunk as f32), y + h / 2.0 - v_max_db * h / 2.0);
+        for marker in self.markers.iter() {
+            let start_x = pixel(marker.position).floor();
+            let end_x = pixel(marker.end()).floor();
+            if end_x < x - MARKER_FLAG_SIZE || start_x > x + w + MARKER_FLAG_SIZE {
+                continue;
+            }
 
-                //                 path2.move_to(x + (chunk as f32), y + h / 2.0 + v_mean_db * h / 2.0);
-                //                 path2.line_to(x + (chunk as f32), y + h / 2.0 - v_mean_db * h / 2.0);
-                //             }
+            let (r, g, b) = marker.color.rgb();
+            let selected = Some(marker.id) == self.selected_marker;
 
-                //             UnitsMode::Linear => {
-                //                 path1.line_to(x + (chunk as f32), y + h / 2.0 - v_min * h / 2.0);
-                //                 path1.line_to(x + (chunk as f32), y + h / 2.0 - v_max * h / 2.0);
+            if marker.is_region() {
+                let mut path = Path::new();
+                path.rect(start_x, y, end_x - start_x, h);
+                canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(r, g, b, 40)));
 
-                //                 path2.move_to(x + (chunk as f32), y + h / 2.0 + v_mean * h / 2.0);
-                //                 path2.line_to(x + (chunk as f32), y + h / 2.0 - v_mean * h / 2.0);
-                //             }
-                //         }
-                //     }
-                // }
-            //}
+                let mut path = Path::new();
+                path.rect(start_x, time_axis_posy + time_axis_height - 4.0, end_x - start_x, 4.0);
+                canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(r, g, b, 255)));
+            }
 
-            // Draw min/max paths
-            let mut paint = Paint::color(femtovg::Color::rgba(50, 50, 255, 255));
+            let mut path = Path::new();
+            path.move_to(start_x, y);
+            path.line_to(start_x, y + h);
+            if marker.is_region() {
+                path.move_to(end_x, y);
+                path.line_to(end_x, y + h);
+            }
+            let mut paint = Paint::color(femtovg::Color::rgba(r, g, b, 200));
             paint.set_line_width(1.0);
             paint.set_anti_alias(false);
-            canvas.stroke_path(&mut path1, paint);
-
-            // Draw rms paths
-            if self.zoom_level < 5 {
-                let mut paint = Paint::color(femtovg::Color::rgba(80, 80, 255, 255));
+            canvas.stroke_path(&mut path, paint);
+
+            let mut flag = Path::new();
+            flag.move_to(start_x, time_axis_posy);
+            flag.line_to(start_x + MARKER_FLAG_SIZE, time_axis_posy);
+            flag.line_to(start_x, time_axis_posy + MARKER_FLAG_SIZE);
+            flag.close();
+            canvas.fill_path(&mut flag, Paint::color(femtovg::Color::rgba(r, g, b, 255)));
+            if selected {
+                let mut paint = Paint::color(femtovg::Color::rgba(255, 255, 255, 255));
                 paint.set_line_width(1.0);
-                paint.set_anti_alias(false);
-                canvas.stroke_path(&mut path2, paint);                
+                canvas.stroke_path(&mut flag, paint);
             }
-        //}
+        }
+
+        canvas.restore();
     }
-}
 
-impl BuildHandler for AppWidget {
-    type Ret = Entity;
-    fn on_build(&mut self, state: &mut State, entity: Entity) -> Self::Ret {
-        entity.set_flex_grow(state, 1.0);
+    // Take the results of loading, saving, exporting, measuring and opening the output
+    fn poll(&mut self, state: &mut State, entity: Entity) {
+        self.poll_loader(state, entity);
+        if self.show_spectrum {
+            if let Some(file) = self.controller.file.clone() {
+                self.poll_spectrum(state, &file);
+            }
+        }
+        if self.show_loudness || self.show_loudness_curve {
+            if let Some(file) = self.controller.file.clone() {
+                self.poll_loudness(state, &file);
+            }
+        }
+        self.poll_issues(state);
+        self.poll_export(state);
+        self.poll_saving(state, entity);
+        self.poll_output(state, entity);
+    }
 
-        state.focused = entity;
-        // TEMP - Animations cause the event loop to run continuously so that the timeline cursor moves smoothly
-        let animation_state = AnimationState::new()
-            .with_duration(std::time::Duration::from_secs(200))
-            .with_keyframe((0.0, Color::rgb(0,0,0)))
-            .with_keyframe((1.0, Color::rgb(255,255,255)))
-            .set_persistent(true);
+    // Show the progress of the issue check, and a summary once it finishes
+    fn poll_issues(&mut self, state: &mut State) {
+        let analysis = match self.issues.as_ref() {
+            Some(analysis) => analysis,
+            None => return,
+        };
 
-        self.random_animation = state
-            .style
-            .border_color
-            .insert_animation(animation_state.clone());
+        let report = match analysis.try_recv() {
+            Some(report) => report,
+            None => {
+                let text = format!("Checking {:.0}%", analysis.progress() * 100.0);
+                self.issues_label.set_text(state, &text);
+                return;
+            }
+        };
 
-        // Header
-        let header = Element::new().build(state, entity, |builder| builder.class("header"));
-        
+        let clipped = report
+            .issues
+            .iter()
+            .filter(|issue| matches!(issue.kind, IssueKind::Clipping(_)))
+            .count();
+        let silent = report.issues.len() - clipped;
 
-        self.navigator = Element::new().build(state, entity, |builder| 
-            builder
-                .class("navigator")
-                .set_height(Length::Pixels(100.0))
-        );
+        let mut summary = format!("{} clipped, {} silent", clipped, silent);
+        if report.truncated {
+            summary.push_str(" (stopped early)");
+        }
+        for (channel, offset) in report.dc_offsets.iter().enumerate() {
+            if offset.abs() > DC_OFFSET_THRESHOLD {
+                let label = channel_label(channel, report.dc_offsets.len());
+                summary.push_str(&format!(", DC {} {:+.2}%", label, offset * 100.0));
+            }
+        }
 
-        self.navigator_window = Element::new().build(state, self.navigator, |builder| 
-            builder
-                .class("navigator_window")
-                .set_position(Position::Absolute)
-                .set_height(Length::Pixels(100.0))
-        );
+        self.issues_label.set_text(state, &summary);
+        self.issues = None;
+        self.issue_report = Some(report);
+    }
+
+    fn poll_export(&mut self, state: &mut State) {
+        let export = match self.export.as_ref() {
+            Some(export) => export,
+            None => return,
+        };
+
+        match export.try_recv() {
+            Some(Ok(())) => {
+                let text = format!("Exported {}", file_name(&self.export_path));
+                self.export_status.set_text(state, &text);
+                self.export = None;
+            }
+            Some(Err(err)) => {
+                self.export_status.set_text(state, "");
+                let text = format!("Could not export {}: {}", file_name(&self.export_path), err);
+                self.show_error(state, &text);
+                self.export = None;
+            }
+            None => {
+                let text = format!("Exporting {:.0}%", export.progress() * 100.0);
+                self.export_status.set_text(state, &text);
+            }
+        }
+    }
+
+    // List the hosts and devices in the audio panel, with the rates and buffer sizes of the device in use
+    fn build_audio_options(&mut self, state: &mut State, entity: Entity) {
+        if self.audio_options != Entity::null() {
+            state.remove(self.audio_options);
+        }
+        self.audio_options = Element::new().build(state, self.audio_panel, |builder| builder.class("audio_options"));
+
+        self.output_devices = match output_devices(self.output_settings.host) {
+            Ok(devices) => devices,
+            Err(err) => {
+                self.show_error(state, &format!("Could not list output devices: {}", err));
+                Vec::new()
+            }
+        };
+
+        let settings = self.output_settings.clone();
+        let with_default = |option: fn(Option<u32>) -> OutputOption, values: &[u32]| {
+            let mut options = vec![("Default".to_string(), AppEvent::SetOutput(option(None)))];
+            options.extend(values.iter().map(|value| (value.to_string(), AppEvent::SetOutput(option(Some(*value))))));
+            options
+        };
+
+        let host_row = Element::new().build(state, self.audio_options, |builder| builder.class("audio_row"));
+        let hosts = output_hosts();
+        let mut host_options = vec![("Default".to_string(), AppEvent::SetOutput(OutputOption::Host(None)))];
+        host_options.extend(
+            hosts
+                .iter()
+                .map(|host| (host.name().to_string(), AppEvent::SetOutput(OutputOption::Host(Some(*host))))),
+        );
+        build_options(state, host_row, entity, "Host", host_options, checked_option(&settings.host, &hosts));
+
+        // Device names are too long for a row, so they are listed one per line
+        let device_row = Element::new().build(state, self.audio_options, |builder| builder.class("audio_row"));
+        Label::new("Device").build(state, device_row, |builder| builder.class("option_title"));
+        let device_list = RadioList::new().build(state, device_row, |builder| builder.class("devices"));
+        let names: Vec<String> = self.output_devices.iter().map(|device| device.name.clone()).collect();
+        let checked = checked_option(&settings.device, &names);
+        let device_options = std::iter::once(("Default".to_string(), None))
+            .chain(names.iter().map(|name| (name.clone(), Some(name.clone()))));
+        for (idx, (text, device)) in device_options.enumerate() {
+            let button = RadioButton::new()
+                .on_checked(Event::new(AppEvent::SetOutput(OutputOption::Device(device))).target(entity))
+                .build(state, device_list, |builder| builder.set_text(&text).class("device"));
+            if Some(idx) == checked {
+                button.set_checked(state, true);
+            }
+        }
+
+        let device = match settings.device.as_ref() {
+            Some(name) => self.output_devices.iter().find(|device| device.name == *name),
+            None => self.output_devices.iter().find(|device| device.is_default),
+        };
+        let (sample_rates, buffer_sizes) = device
+            .map(|device| (device.sample_rates.clone(), device.buffer_sizes.clone()))
+            .unwrap_or_default();
+
+        let format_row = Element::new().build(state, self.audio_options, |builder| builder.class("audio_row"));
+        build_options(state, format_row, entity, "Rate",
+            with_default(OutputOption::SampleRate, &sample_rates),
+            checked_option(&settings.sample_rate, &sample_rates));
+        build_options(state, format_row, entity, "Buffer",
+            with_default(OutputOption::BufferSize, &buffer_sizes),
+            checked_option(&settings.buffer_size, &buffer_sizes));
+    }
+
+    // Rebuild the stream with a new choice from the audio panel. Another host or device
+    // goes back to its own rate and buffer size, which may not be offered by the next
+    fn set_output(&mut self, state: &mut State, entity: Entity, option: OutputOption) {
+        let mut settings = self.output_settings.clone();
+        let relist = match option {
+            OutputOption::Host(host) => {
+                settings = OutputSettings {
+                    host,
+                    ..OutputSettings::default()
+                };
+                true
+            }
+            OutputOption::Device(device) => {
+                settings.device = device;
+                settings.sample_rate = None;
+                settings.buffer_size = None;
+                true
+            }
+            OutputOption::SampleRate(sample_rate) => {
+                settings.sample_rate = sample_rate;
+                false
+            }
+            OutputOption::BufferSize(buffer_size) => {
+                settings.buffer_size = buffer_size;
+                false
+            }
+        };
+
+        if settings == self.output_settings {
+            return;
+        }
+        self.output_settings = settings;
+        if relist {
+            self.build_audio_options(state, entity);
+        }
+
+        self.audio_output.configure(self.output_settings.clone());
+        self.audio_status.set_text(state, "Opening output...");
+    }
+
+    // Show the stream opened by the audio output, or why there is no audio
+    fn poll_output(&mut self, state: &mut State, entity: Entity) {
+        while let Some(result) = self.audio_output.try_recv() {
+            match &result {
+                Ok(info) => {
+                    let buffer = info
+                        .buffer_size
+                        .map(|buffer_size| format!(", {} frames", buffer_size))
+                        .unwrap_or_default();
+                    let text = format!(
+                        "Playing to {} ({}) at {} Hz, {} channels{}",
+                        info.device,
+                        info.host.name(),
+                        info.sample_rate,
+                        info.num_channels,
+                        buffer
+                    );
+                    self.audio_status.set_text(state, &text);
+                    self.controller.set_device_rate(info.sample_rate as f64);
+                }
+                Err(err) => {
+                    self.audio_status.set_text(state, "No audio output");
+                    self.show_error(state, &format!("No audio output: {}", err));
+                }
+            }
+            self.output_info = result.ok();
+            self.build_routing_options(state, entity);
+        }
+    }
+
+    // Number of outputs of the device playing, or of a stereo device when there is none
+    fn num_outputs(&self) -> usize {
+        self.output_info.as_ref().map_or(2, |info| info.num_channels)
+    }
+
+    // Choose between the standard mix, a solo channel and a custom routing, with a row of
+    // output toggles for each channel of the file when custom. A routing that no longer
+    // fits the file and device goes back to the standard mix
+    fn build_routing_options(&mut self, state: &mut State, entity: Entity) {
+        let (num_inputs, num_outputs) = (self.num_of_channels, self.num_outputs());
+        if !self.routing.fits(num_inputs, num_outputs) {
+            self.routing = Routing::Standard;
+            self.controller.set_routing(Routing::Standard);
+        }
+
+        if self.routing_options != Entity::null() {
+            state.remove(self.routing_options);
+        }
+        self.routing_options = Element::new().build(state, self.routing_view, |builder| builder.class("routing_options"));
+        if num_inputs == 0 {
+            return;
+        }
+
+        let mode_row = Element::new().build(state, self.routing_options, |builder| builder.class("audio_row"));
+        let mut options = vec![("Mix".to_string(), AppEvent::Route(RouteOption::Standard))];
+        options.extend(
+            (0..num_inputs).map(|channel| (channel_label(channel, num_inputs), AppEvent::Route(RouteOption::Solo(channel)))),
+        );
+        options.push(("Custom".to_string(), AppEvent::Route(RouteOption::Custom)));
+        let checked = match &self.routing {
+            Routing::Standard => 0,
+            Routing::Solo(channel) => channel + 1,
+            Routing::Custom(_) => num_inputs + 1,
+        };
+        build_options(state, mode_row, entity, "Listen", options, Some(checked));
+
+        if let Routing::Custom(matrix) = &self.routing {
+            let matrix = matrix.clone();
+            for input in 0..num_inputs {
+                let row = Element::new().build(state, self.routing_options, |builder| builder.class("audio_row"));
+                Label::new(&channel_label(input, num_inputs)).build(state, row, |builder| builder.class("option_title"));
+                for output in 0..num_outputs {
+                    let label = channel_label(output, num_outputs);
+                    Checkbox::new(matrix.gain(input, output) != 0.0)
+                        .on_unchecked(Event::new(AppEvent::Route(RouteOption::Connect(input, output, false))).target(entity))
+                        .on_checked(Event::new(AppEvent::Route(RouteOption::Connect(input, output, true))).target(entity))
+                        .with_icon_checked(&label)
+                        .with_icon_unchecked(&label)
+                        .build(state, row, |builder| builder.class("route"));
+                }
+            }
+        }
+    }
+
+    // Send a new routing to the player. A custom routing starts from the standard mix
+    fn route(&mut self, state: &mut State, entity: Entity, option: RouteOption) {
+        match option {
+            RouteOption::Standard => self.routing = Routing::Standard,
+            RouteOption::Solo(channel) => self.routing = Routing::Solo(channel),
+            RouteOption::Custom => {
+                if let Routing::Custom(_) = self.routing {
+                    return;
+                }
+                self.routing = Routing::Custom(RoutingMatrix::standard(self.num_of_channels, self.num_outputs()));
+            }
+            RouteOption::Connect(input, output, connected) => {
+                if let Routing::Custom(matrix) = &mut self.routing {
+                    matrix.set_gain(input, output, if connected { 1.0 } else { 0.0 });
+                }
+            }
+        }
+        self.controller.set_routing(self.routing.clone());
+
+        // The toggles only change with the choice of routing, not when one of them is pressed
+        if let RouteOption::Connect(..) = option {
+            return;
+        }
+        self.build_routing_options(state, entity);
+    }
+
+    // One toggle for each channel of the loaded file, all of them exported to begin with
+    fn build_export_channels(&mut self, state: &mut State, entity: Entity) {
+        for button in self.export_channel_buttons.drain(..) {
+            state.remove(button);
+        }
+
+        for channel in 0..self.num_of_channels {
+            let label = channel_label(channel, self.num_of_channels);
+            let button = Checkbox::new(true)
+                .on_unchecked(Event::new(AppEvent::ExportChannel(channel, false)).target(entity))
+                .on_checked(Event::new(AppEvent::ExportChannel(channel, true)).target(entity))
+                .with_icon_checked(&label)
+                .with_icon_unchecked(&label)
+                .build(state, self.export_channels, |builder| builder.class("export_channel"));
+            self.export_channel_buttons.push(button);
+        }
+    }
+
+    // The pixel range of an issue in the waveview, widened so that short issues can be seen and clicked
+    fn issue_pixels(&self, start: usize, end: usize) -> (f32, f32) {
+        let left = (start as f32 - self.start as f32) / self.samples_per_pixel;
+        let right = (end as f32 - self.start as f32) / self.samples_per_pixel;
+        let width = (right - left).max(ISSUE_MARKER_MIN_WIDTH);
+        let centre = (left + right) / 2.0;
+        (centre - width / 2.0, centre + width / 2.0)
+    }
+
+    // Return the region of the issue marker at a position in the waveview, if there is one
+    fn issue_at(&self, state: &State, posx: f32, posy: f32) -> Option<(usize, usize)> {
+        let report = self.issue_report.as_ref()?;
+        let x = state.data.get_posx(self.waveview);
+        let y = state.data.get_posy(self.waveview);
+        if posy < y || posy > y + ISSUE_MARKER_HEIGHT {
+            return None;
+        }
+
+        report
+            .issues
+            .iter()
+            .find(|issue| {
+                let (left, right) = self.issue_pixels(issue.start, issue.end);
+                posx >= x + left && posx <= x + right
+            })
+            .map(|issue| (issue.start, issue.end))
+    }
+
+    // Return the region of the issue closest to a position on the navigator, if one is close enough
+    fn navigator_issue_at(&self, state: &State, posx: f32) -> Option<(usize, usize)> {
+        let report = self.issue_report.as_ref()?;
+        let x = state.data.get_posx(self.navigator);
+        let samples_per_pixel = self.num_of_samples as f32 / state.data.get_width(self.navigator);
+
+        report
+            .issues
+            .iter()
+            .map(|issue| {
+                let issue_x = x + issue.start as f32 / samples_per_pixel;
+                ((issue_x - posx).abs(), issue)
+            })
+            .filter(|(distance, _)| *distance <= NAVIGATOR_PICK_DISTANCE)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+            .map(|(_, issue)| (issue.start, issue.end))
+    }
+
+    // Mark each issue along the top of the waveview and across the navigator
+    fn draw_issue_markers(&self, state: &mut State, canvas: &mut Canvas<OpenGl>) {
+        let report = match self.issue_report.as_ref() {
+            Some(report) => report,
+            None => return,
+        };
+
+        let x = state.data.get_posx(self.waveview);
+        let y = state.data.get_posy(self.waveview);
+        let w = state.data.get_width(self.waveview);
+
+        let navigator_posx = state.data.get_posx(self.navigator);
+        let navigator_posy = state.data.get_posy(self.navigator);
+        let navigator_width = state.data.get_width(self.navigator);
+        let navigator_height = state.data.get_height(self.navigator);
+
+        let mut clipping = Path::new();
+        let mut silence = Path::new();
+        let mut navigator_clipping = Path::new();
+        let mut navigator_silence = Path::new();
+
+        for issue in report.issues.iter() {
+            let (path, navigator_path) = match issue.kind {
+                IssueKind::Clipping(_) => (&mut clipping, &mut navigator_clipping),
+                IssueKind::Silence => (&mut silence, &mut navigator_silence),
+            };
+
+            let (left, right) = self.issue_pixels(issue.start, issue.end);
+            if right >= 0.0 && left <= w {
+                path.rect(x + left.max(0.0), y, right.min(w) - left.max(0.0), ISSUE_MARKER_HEIGHT);
+            }
+
+            let navigator_x = (navigator_posx + navigator_width * issue.start as f32 / self.num_of_samples as f32).floor();
+            navigator_path.move_to(navigator_x, navigator_posy);
+            navigator_path.line_to(navigator_x, navigator_posy + navigator_height);
+        }
+
+        canvas.fill_path(&mut clipping, Paint::color(femtovg::Color::rgba(255, 50, 50, 255)));
+        canvas.fill_path(&mut silence, Paint::color(femtovg::Color::rgba(80, 160, 255, 255)));
+
+        let mut paint = Paint::color(femtovg::Color::rgba(255, 50, 50, 150));
+        paint.set_line_width(1.0);
+        paint.set_anti_alias(false);
+        canvas.stroke_path(&mut navigator_clipping, paint);
+        let mut paint = Paint::color(femtovg::Color::rgba(80, 160, 255, 150));
+        paint.set_line_width(1.0);
+        paint.set_anti_alias(false);
+        canvas.stroke_path(&mut navigator_silence, paint);
+    }
+
+    // Draw a horizontal peak and rms bar for each channel of the output, with the
+    // peak hold as a tick and a clip indicator at the right
+    fn draw_meters(&self, state: &mut State, canvas: &mut Canvas<OpenGl>) {
+        let x = state.data.get_posx(self.meter_view);
+        let y = state.data.get_posy(self.meter_view);
+        let w = state.data.get_width(self.meter_view);
+        let h = state.data.get_height(self.meter_view);
+
+        let mut path = Path::new();
+        path.rect(x, y, w, h);
+        canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(30, 30, 30, 255)));
+
+        if self.meters.channels.is_empty() {
+            return;
+        }
+
+        let clip_width = 6.0;
+        let bar_width = w - clip_width - 1.0;
+        let bar_height = h / self.meters.channels.len() as f32;
+
+        for (channel, meter) in self.meters.channels.iter().enumerate() {
+            let bar_y = y + channel as f32 * bar_height;
+            // Leave a gap between channels when there is room
+            let height = if bar_height > 3.0 { bar_height - 1.0 } else { bar_height };
+
+            let mut path = Path::new();
+            path.rect(x, bar_y, bar_width * meter_position(meter.peak), height);
+            canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(40, 130, 40, 255)));
+
+            let mut path = Path::new();
+            path.rect(x, bar_y, bar_width * meter_position(meter.rms), height);
+            canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(80, 220, 80, 255)));
+
+            let hold_x = (x + bar_width * meter_position(meter.hold)).round();
+            let mut path = Path::new();
+            path.move_to(hold_x, bar_y);
+            path.line_to(hold_x, bar_y + height);
+            let mut paint = Paint::color(femtovg::Color::rgba(240, 240, 240, 255));
+            paint.set_line_width(1.0);
+            paint.set_anti_alias(false);
+            canvas.stroke_path(&mut path, paint);
+
+            let clip_color = if meter.clipped {
+                femtovg::Color::rgba(255, 50, 50, 255)
+            } else {
+                femtovg::Color::rgba(70, 70, 70, 255)
+            };
+            let mut path = Path::new();
+            path.rect(x + w - clip_width, bar_y, clip_width, height);
+            canvas.fill_path(&mut path, Paint::color(clip_color));
+        }
+    }
+
+    // Measure the loudness of the selection, or of the whole file, once the selection
+    // has stopped changing, and show the results when the measurement finishes
+    fn poll_loudness(&mut self, state: &mut State, file: &Shared<AudioFile>) {
+        let (start, end) = if self.cursor != self.select {
+            (self.cursor.min(self.select), self.cursor.max(self.select))
+        } else {
+            (0, file.num_samples)
+        };
+        let end = end.min(file.num_samples);
+        let start = start.min(end);
+
+        let measured = self
+            .loudness
+            .as_ref()
+            .map(|analysis| (analysis.start, analysis.end))
+            .or_else(|| self.loudness_report.as_ref().map(|report| (report.start, report.end)));
+
+        let selecting = state.mouse.left.state == MouseButtonState::Pressed;
+        if !selecting && measured != Some((start, end)) {
+            // Replacing the analysis cancels the previous one
+            self.loudness = Some(Analysis::start(Shared::clone(file), start, end, loudness::analyse));
+        }
+
+        if let Some(analysis) = self.loudness.as_ref() {
+            if let Some(report) = analysis.try_recv() {
+                self.loudness = None;
+                self.loudness_report = Some(report);
+                self.build_loudness_panel(state);
+            } else {
+                let text = format!("Measuring {:.0}%", analysis.progress() * 100.0);
+                self.loudness_status.set_text(state, &text);
+            }
+        }
+    }
+
+    // Fill the loudness panel with the last measurement
+    fn build_loudness_panel(&mut self, state: &mut State) {
+        for label in self.loudness_labels.drain(..) {
+            state.remove(label);
+        }
+
+        let lines = match self.loudness_report.as_ref() {
+            Some(report) => loudness_lines(report, self.sample_rate, self.num_of_samples),
+            None => Vec::new(),
+        };
+
+        for line in lines {
+            let label = Label::new(&line).build(state, self.loudness_panel, |builder| builder.class("file_info"));
+            self.loudness_labels.push(label);
+        }
+
+        self.loudness_status.set_text(state, "");
+    }
+
+    // Draw the short-term loudness over the waveform
+    fn draw_loudness_curve(&self, state: &mut State, canvas: &mut Canvas<OpenGl>) {
+        let report = match self.loudness_report.as_ref() {
+            Some(report) => report,
+            None => return,
+        };
+
+        let x = state.data.get_posx(self.waveview);
+        let y = state.data.get_posy(self.waveview);
+        let w = state.data.get_width(self.waveview);
+        let h = state.data.get_height(self.waveview);
+
+        let loudness_y = |lufs: f32| {
+            let position = (lufs - LOUDNESS_CURVE_MIN) / (LOUDNESS_CURVE_MAX - LOUDNESS_CURVE_MIN);
+            y + h * (1.0 - position.max(0.0).min(1.0))
+        };
+
+        // Each value is drawn at the end of its step, including one either side of the view
+        let step = report.step as f32;
+        let view_start = self.start as f32 - step;
+        let view_end = self.start as f32 + w * self.samples_per_pixel + step;
+        let mut path = Path::new();
+        let mut drawing = false;
+        for (idx, lufs) in report.short_term.iter().enumerate() {
+            let sample = (report.start + (idx + 1) * report.step) as f32;
+            if sample < view_start || sample > view_end {
+                continue;
+            }
+
+            let point_x = x + (sample - self.start as f32) / self.samples_per_pixel;
+            if drawing {
+                path.line_to(point_x, loudness_y(*lufs));
+            } else {
+                path.move_to(point_x, loudness_y(*lufs));
+                drawing = true;
+            }
+        }
+
+        canvas.save();
+        canvas.scissor(x, y, w, h);
+        let mut paint = Paint::color(femtovg::Color::rgba(240, 200, 60, 255));
+        paint.set_line_width(1.5);
+        canvas.stroke_path(&mut path, paint);
+        canvas.restore();
+    }
+
+    // Analyse the selection on a worker once it has stopped changing, and keep the spectra
+    // for drawing when the analysis finishes
+    fn poll_spectrum(&mut self, state: &mut State, file: &Shared<AudioFile>) {
+        let selection = (self.cursor.min(self.select), self.cursor.max(self.select));
+        let analysing = self.spectrum.as_ref().map(|analysis| (analysis.start, analysis.end));
+
+        let selecting = state.mouse.left.state == MouseButtonState::Pressed;
+        if !selecting && analysing.or(self.spectrum_selection) != Some(selection) {
+            if selection.0 == selection.1 {
+                self.spectrum = None;
+                self.selection_spectra.clear();
+                self.spectrum_selection = Some(selection);
+            } else {
+                // Replacing the analysis cancels the previous one
+                self.spectrum = Some(Analysis::start(
+                    Shared::clone(file),
+                    selection.0,
+                    selection.1,
+                    spectrum::analyse,
+                ));
+            }
+        }
+
+        if let Some(analysis) = self.spectrum.as_ref() {
+            if let Some(spectra) = analysis.try_recv() {
+                self.spectrum_selection = Some((analysis.start, analysis.end));
+                self.selection_spectra = spectra;
+                self.spectrum = None;
+            }
+        }
+    }
+
+    // Draw the spectrum of the selection, and of the playhead while playing, for each visible channel
+    fn draw_spectrum(&mut self, state: &mut State, file: &AudioFile, canvas: &mut Canvas<OpenGl>) {
+        let x = state.data.get_posx(self.spectrum_view);
+        let y = state.data.get_posy(self.spectrum_view);
+        let w = state.data.get_width(self.spectrum_view);
+        let h = state.data.get_height(self.spectrum_view);
+
+        let mut path = Path::new();
+        path.rect(x, y, w, h);
+        canvas.fill_path(&mut path, Paint::color(femtovg::Color::rgba(30, 30, 30, 255)));
+
+        // Frequency on a log axis, level in dB
+        let nyquist = (file.sample_rate / 2.0) as f32;
+        let octaves = (nyquist / SPECTRUM_MIN_FREQUENCY).ln();
+        let frequency_x = |frequency: f32| x + w * (frequency / SPECTRUM_MIN_FREQUENCY).ln() / octaves;
+        let db_y = |db: f32| y + h * (db / SPECTRUM_MIN_DB).max(0.0).min(1.0);
+
+        let mut grid = Path::new();
+        for frequency in SPECTRUM_FREQUENCY_LINES.iter().filter(|frequency| **frequency < nyquist) {
+            let line_x = frequency_x(*frequency).round();
+            grid.move_to(line_x, y);
+            grid.line_to(line_x, y + h);
+        }
+        let mut db = -SPECTRUM_DB_STEP;
+        while db > SPECTRUM_MIN_DB {
+            let line_y = db_y(db).round();
+            grid.move_to(x, line_y);
+            grid.line_to(x + w, line_y);
+            db -= SPECTRUM_DB_STEP;
+        }
+        let mut paint = Paint::color(femtovg::Color::rgba(60, 60, 60, 255));
+        paint.set_line_width(1.0);
+        paint.set_anti_alias(false);
+        canvas.stroke_path(&mut grid, paint);
+
+        let bin_x = |bin: usize| frequency_x(spectrum::bin_frequency(bin, file.sample_rate));
+        let first_bin = (SPECTRUM_MIN_FREQUENCY as f64 * SPECTRUM_FFT_SIZE as f64 / file.sample_rate).ceil() as usize;
+        let spectrum_path = |spectrum: &[f32]| {
+            let mut path = Path::new();
+            for (bin, db) in spectrum.iter().enumerate().skip(first_bin) {
+                if bin == first_bin {
+                    path.move_to(bin_x(bin), db_y(*db));
+                } else {
+                    path.line_to(bin_x(bin), db_y(*db));
+                }
+            }
+            path
+        };
+
+        canvas.save();
+        canvas.scissor(x, y, w, h);
+
+        let channels = self.visible_channels();
+        for channel in channels.iter() {
+            if let Some(spectrum) = self.selection_spectra.get(*channel) {
+                let (r, g, b) = SPECTRUM_COLORS[channel % SPECTRUM_COLORS.len()];
+                let mut paint = Paint::color(femtovg::Color::rgba(r, g, b, 255));
+                paint.set_line_width(1.0);
+                canvas.stroke_path(&mut spectrum_path(spectrum), paint);
+            }
+        }
+
+        // The playhead spectrum is drawn fainter, over the selection
+        if self.spectrum_follow_playhead && self.is_playing {
+            for channel in channels.iter() {
+                let spectrum = self.spectrum_analyser.frame(file, *channel, self.playhead);
+                let (r, g, b) = SPECTRUM_COLORS[channel % SPECTRUM_COLORS.len()];
+                let mut paint = Paint::color(femtovg::Color::rgba(r, g, b, 150));
+                paint.set_line_width(1.0);
+                canvas.stroke_path(&mut spectrum_path(&spectrum), paint);
+            }
+        }
+
+        // Readout of the highest peak of each channel near the mouse
+        let mut readout = match self.spectrum.as_ref() {
+            _ if !self.selection_spectra.is_empty() => String::new(),
+            Some(analysis) => format!("Analysing {:.0}%", analysis.progress() * 100.0),
+            None => String::from("Select a region to show its spectrum"),
+        };
+
+        let mouse = self.spectrum_mouse.filter(|_| !self.selection_spectra.is_empty());
+        if let Some(mouse) = mouse {
+            let mut path = Path::new();
+            path.move_to((x + mouse).round(), y);
+            path.line_to((x + mouse).round(), y + h);
+            let mut paint = Paint::color(femtovg::Color::rgba(200, 200, 200, 100));
+            paint.set_line_width(1.0);
+            paint.set_anti_alias(false);
+            canvas.stroke_path(&mut path, paint);
+
+            // The bins between the frequencies either side of the mouse
+            let x_bin = |pixel: f32| {
+                let frequency = SPECTRUM_MIN_FREQUENCY * (pixel / w * octaves).exp();
+                (frequency as f64 * SPECTRUM_FFT_SIZE as f64 / file.sample_rate).round() as usize
+            };
+            let first = x_bin(mouse - SPECTRUM_PEAK_SEARCH).max(first_bin);
+            let last = x_bin(mouse + SPECTRUM_PEAK_SEARCH).max(first);
+
+            for channel in channels.iter() {
+                if let Some(spectrum) = self.selection_spectra.get(*channel) {
+                    if let Some(bin) = spectrum::peak_bin(spectrum, first, last) {
+                        readout.push_str(&format!(
+                            "{}: {:.1} Hz  {:.1} dB    ",
+                            channel_label(*channel, file.num_channels),
+                            spectrum::bin_frequency(bin, file.sample_rate),
+                            spectrum[bin]
+                        ));
+                    }
+                }
+            }
+        }
+
+        canvas.restore();
+
+        self.spectrum_label.set_text(state, readout.trim_end());
+    }
+
+    // Drop all spectrogram tiles, after the file or the spectrogram settings change
+    fn reset_spectrogram(&mut self) {
+        self.spectrogram.invalidate();
+        self.spectrogram_pending.clear();
+        self.spectrogram_discarded
+            .extend(self.spectrogram_tiles.drain().map(|(_, image)| image));
+    }
+
+    // Turn rendered spectrogram tiles into images, and delete discarded ones
+    fn poll_spectrogram(&mut self, canvas: &mut Canvas<OpenGl>) {
+        for image in self.spectrogram_discarded.drain(..) {
+            canvas.delete_image(image);
+        }
+
+        while let Some(tile) = self.spectrogram.try_recv() {
+            self.spectrogram_pending.remove(&tile.key);
+            let source = ImageSource::Rgba(imgref::Img::new(tile.pixels.as_slice(), TILE_WIDTH, TILE_HEIGHT));
+            match canvas.create_image(source, ImageFlags::empty()) {
+                Ok(image) => {
+                    self.spectrogram_tiles.insert(tile.key, image);
+                }
+                Err(err) => eprintln!("Failed to create spectrogram image: {:?}", err),
+            }
+        }
+
+        // Keep the tiles of the current zoom level when there are too many
+        if self.spectrogram_tiles.len() > MAX_SPECTROGRAM_TILES {
+            let level = spectrogram::zoom_level(self.samples_per_pixel);
+            let discarded: Vec<TileKey> = self
+                .spectrogram_tiles
+                .keys()
+                .filter(|key| key.level != level)
+                .cloned()
+                .collect();
+            for key in discarded {
+                if let Some(image) = self.spectrogram_tiles.remove(&key) {
+                    canvas.delete_image(image);
+                }
+            }
+        }
+    }
+
+    // Draw the spectrogram of a channel from its tiles, requesting any that are missing
+    fn draw_spectrogram(
+        &mut self,
+        state: &mut State,
+        file: &Shared<AudioFile>,
+        channel: usize,
+        posy: f32,
+        height: f32,
+        canvas: &mut Canvas<OpenGl>,
+    ) {
+        let x = state.data.get_posx(self.waveview);
+        let y = posy;
+        let w = state.data.get_width(self.waveview);
+        let h = height;
+
+        let samples_per_pixel = self.samples_per_pixel as f64;
+        let level = spectrogram::zoom_level(self.samples_per_pixel);
+        let tile_samples = TILE_WIDTH as f64 * spectrogram::level_samples_per_column(level);
+
+        let view_end = (self.start as f64 + w as f64 * samples_per_pixel).min(file.num_samples as f64);
+        let first_tile = (self.start as f64 / tile_samples) as usize;
+        let last_tile = (view_end / tile_samples) as usize;
+
+        canvas.save();
+        canvas.scissor(x, y, w, h);
+
+        for index in first_tile..=last_tile {
+            let key = TileKey { channel, level, index };
+            let tile_x = x + ((index as f64 * tile_samples - self.start as f64) / samples_per_pixel) as f32;
+            let tile_width = (tile_samples / samples_per_pixel) as f32;
+
+            if let Some(image) = self.spectrogram_tiles.get(&key) {
+                let mut path = Path::new();
+                path.rect(tile_x, y, tile_width, h);
+                canvas.fill_path(&mut path, Paint::image(*image, tile_x, y, tile_width, h, 0.0, 1.0));
+            } else if self.spectrogram_pending.insert(key) {
+                self.spectrogram.request(TileRequest {
+                    file: Shared::clone(file),
+                    key,
+                    settings: self.spectrogram_settings.clone(),
+                    generation: self.spectrogram.generation(),
+                });
+            }
+        }
+
+        canvas.restore();
+    }
+
+    // Drop the loader and hide the progress display
+    fn finish_loading(&mut self, state: &mut State) {
+        self.loader = None;
+        self.progress_label.set_text(state, "");
+        self.progress_label.set_display(state, Display::None);
+        self.cancel_button.set_display(state, Display::None);
+    }
+
+    // Display an error message in the header, or clear it with an empty string
+    fn show_error(&mut self, state: &mut State, message: &str) {
+        self.error_label.set_text(state, message);
+    }
+
+    // Fill the info panel with the format and metadata of the loaded file
+    fn build_info_panel(&mut self, state: &mut State) {
+        for label in self.info_labels.drain(..) {
+            state.remove(label);
+        }
+
+        let lines = match self.controller.file.as_ref() {
+            Some(file) => file_info_lines(file),
+            None => Vec::new(),
+        };
+
+        for line in lines {
+            let label = Label::new(&line).build(state, self.info_panel, |builder| builder.class("file_info"));
+            self.info_labels.push(label);
+        }
+    }
+
+    // Rebuild the channel selector with an entry for each channel of the loaded file
+    fn build_channel_selector(&mut self, state: &mut State, entity: Entity) {
+        for button in self.channel_buttons.drain(..) {
+            state.remove(button);
+        }
+
+        let num_of_channels = self.num_of_channels;
+
+        let all = RadioButton::new()
+            .on_checked(Event::new(AppEvent::SwicthChannel(ChannelMode::All)).target(entity))
+            .build(state, self.channels_list, |builder| {
+                let builder = builder
+                    .set_text("All")
+                    .class("first");
+                if num_of_channels == 0 {
+                    builder.class("last")
+                } else {
+                    builder
+                }
+            });
+        all.set_checked(state, true);
+        self.channel_buttons.push(all);
+
+        for channel in 0..num_of_channels {
+            let label = channel_label(channel, num_of_channels);
+            let button = RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwicthChannel(ChannelMode::Single(channel))).target(entity))
+                .build(state, self.channels_list, |builder| {
+                    let builder = builder.set_text(&label);
+                    if channel == num_of_channels - 1 {
+                        builder.class("last")
+                    } else {
+                        builder
+                    }
+                });
+            self.channel_buttons.push(button);
+        }
+    }
+
+    // Returns the indices of the channels to draw, one lane per channel
+    fn visible_channels(&self) -> Vec<usize> {
+        match self.channel_mode {
+            ChannelMode::All => (0..self.waveforms.len()).collect(),
+            ChannelMode::Single(channel) if channel < self.waveforms.len() => vec![channel],
+            ChannelMode::Single(_) => Vec::new(),
+        }
+    }
+
+    // Set the zoom factor, keeping it between the closest zoom and the furthest of
+    // MIN_ZOOM and fitting the whole file into `width` pixels
+    fn set_zoom_factor(&mut self, zoom: f64, width: f32) {
+        // Samples per pixel at 1X
+        let base = if self.sample_rate > 0.0 { self.sample_rate / 100.0 } else { 441.0 };
+
+        let mut min_zoom = MIN_ZOOM;
+        if self.num_of_samples > 0 && width > 0.0 {
+            min_zoom = min_zoom.min(base * width as f64 / self.num_of_samples as f64);
+        }
+        let max_zoom = base / MIN_SAMPLES_PER_PIXEL;
+
+        self.zoom = zoom.max(min_zoom).min(max_zoom);
+        self.samples_per_pixel = (base / self.zoom) as f32;
+    }
+
+    // Fill `peaks` with one peak per pixel of the waveview for a channel, from the
+    // waveform's levels, or from the samples when zoomed in past its most detailed level
+    fn view_peaks(&self, file: &AudioFile, waveform: &Waveform, channel: usize, width: f32, peaks: &mut Vec<Peak>) {
+        let samples_per_pixel = self.samples_per_pixel as f64;
+        let num_of_pixels = width as usize;
+
+        if samples_per_pixel < SAMPLES_PER_PIXEL[SAMPLES_PER_PIXEL.len() - 1] as f64 {
+            let mut samples = vec![0.0; (width as f64 * samples_per_pixel).ceil() as usize + 1];
+            let len = file.read(channel, self.start, &mut samples);
+            samples.truncate(len);
+            sample_peaks(&samples, samples_per_pixel, peaks);
+            peaks.truncate(num_of_pixels);
+        } else {
+            waveform.peaks(self.start as f64, samples_per_pixel, num_of_pixels, peaks);
+        }
+    }
+
+    // Map a sample value to a vertical position from -1 to 1 using the current units
+    fn scale_value(&self, value: f32) -> f32 {
+        render::scale_value(value, self.units_mode)
+    }
+
+    // Draw the individual samples of a channel, for zoom levels past one sample per pixel
+    fn draw_samples(
+        &self,
+        state: &mut State,
+        file: &AudioFile,
+        channel: usize,
+        posy: f32,
+        height: f32,
+        canvas: &mut Canvas<OpenGl>,
+    ) {
+        let x = state.data.get_posx(self.waveview);
+        let y = posy;
+        let w = state.data.get_width(self.waveview);
+        let h = height;
+
+        let pixels_per_sample = 1.0 / self.samples_per_pixel;
+
+        // Read the visible samples plus enough either side for the sinc interpolation
+        let first = self.start.saturating_sub(SINC_HALF_WIDTH);
+        let num_visible = (w * self.samples_per_pixel).ceil() as usize + 1;
+        let mut samples = vec![0.0; (self.start - first) + num_visible + SINC_HALF_WIDTH];
+        let len = file.read(channel, first, &mut samples);
+        samples.truncate(len);
+
+        let sample_x = |idx: usize| x + ((first + idx) as f32 - self.start as f32) * pixels_per_sample;
+        let sample_y = |value: f32| y + h / 2.0 - self.scale_value(value) * h / 2.0;
+
+        canvas.save();
+        canvas.scissor(x, y, w, h);
+
+        // Draw the curve between samples
+        let mut path = Path::new();
+        match self.interpolation {
+            Interpolation::Line => {
+                for (idx, value) in samples.iter().enumerate() {
+                    if idx == 0 {
+                        path.move_to(sample_x(idx), sample_y(*value));
+                    } else {
+                        path.line_to(sample_x(idx), sample_y(*value));
+                    }
+                }
+            }
+
+            Interpolation::Sinc => {
+                let offset = (self.start - first) as f64;
+                let num_of_pixels = ((samples.len() as f32 - offset as f32) * pixels_per_sample).min(w).max(0.0) as usize;
+                for pixel in 0..=num_of_pixels {
+                    let pos = offset + pixel as f64 * self.samples_per_pixel as f64;
+                    let value = interpolate_sinc(&samples, pos);
+                    if pixel == 0 {
+                        path.move_to(x + pixel as f32, sample_y(value));
+                    } else {
+                        path.line_to(x + pixel as f32, sample_y(value));
+                    }
+                }
+            }
+        }
+        let mut paint = Paint::color(femtovg::Color::rgba(50, 50, 255, 255));
+        paint.set_line_width(1.0);
+        canvas.stroke_path(&mut path, paint);
+
+        // Mark each sample once they are far enough apart to tell apart
+        if pixels_per_sample >= MIN_SAMPLE_MARKER_SPACING {
+            let radius = (pixels_per_sample / 4.0).min(3.0);
+            let mut markers = Path::new();
+            let mut stems = Path::new();
+            for (idx, value) in samples.iter().enumerate() {
+                let px = sample_x(idx);
+                let py = sample_y(*value);
+                if self.sample_style == SampleStyle::Stems {
+                    stems.move_to(px, y + h / 2.0);
+                    stems.line_to(px, py);
+                }
+                markers.circle(px, py, radius);
+            }
+
+            let mut paint = Paint::color(femtovg::Color::rgba(120, 120, 255, 255));
+            paint.set_line_width(1.0);
+            canvas.stroke_path(&mut stems, paint);
+            canvas.fill_path(&mut markers, Paint::color(femtovg::Color::rgba(120, 120, 255, 255)));
+        }
+
+        canvas.restore();
+    }
+
+    // Draw the audio waveforms
+    fn draw_channel(
+        &self,
+        state: &mut State,
+        entity: Entity,
+        waveform_data: &[Peak],
+        posy: f32,
+        height: f32,
+        canvas: &mut Canvas<OpenGl>,
+    ) {
+        let x = state.data.get_posx(self.waveview);
+        let y = posy;
+        let w = state.data.get_width(self.waveview);
+        let h = height;
+
+        //if data.len() > 0 {
+            //let audio = &data[self.start as usize..self.end as usize];
+            //let audio = data;
+
+
+            // // Minimum time
+            // let start_time = self.start as f32 / self.sample_rate as f32;
+            // let end_time = self.end as f32 / self.sample_rate as f32;
+
+            // // Draw grid
+            // let first = round_up(start_time as u32, 1);
+            // let last = round_up(end_time as u32, 1);
+
+            // for n in (first..last+1) {
+            //     let sample = self.sample_rate as f32 * n as f32 - self.start as f32;
+            //     let pixel = ((1.0 / self.samples_per_pixel) * sample).round();
+            //     let mut path = Path::new();
+            //     path.move_to(x + pixel, y + 20.0);
+            //     path.line_to(x + pixel, y + 30.0);
+            //     let mut paint = Paint::color(femtovg::Color::rgba(90, 90, 90, 255));
+            //     paint.set_line_width(1.0);
+            //     paint.set_anti_alias(false);
+            //     canvas.stroke_path(&mut path, paint);
+            // }
+
+            // Create two paths for min/max and rms
+            let mut path1 = Path::new();
+            let mut path2 = Path::new();
+
+            // Move to the center of the drawing region
+            path1.move_to(x, y + h / 2.0);
+            path2.move_to(x, y + h / 2.0);
+
+
+
+
+                //let samples_per_pixel = audio.len() as f32 / w;
+
+                //let mut chunks = audio.chunks(samples_per_pixel as usize);
+                //let spp = self.num_of_samples as f32 / w;
+                //let mut chunks = audio.chunks(spp as usize);
+
+                //println!("Samples per pixel: {}  {}", self.samples_per_pixel, audio.len() as f32 / w);
+
+                for (pixel, column) in render::columns(waveform_data, self.units_mode).take(w as usize).enumerate() {
+                    let px = x + pixel as f32;
+                    path1.line_to(px, y + h / 2.0 - column.min * h / 2.0);
+                    path1.line_to(px, y + h / 2.0 - column.max * h / 2.0);
+
+                    path2.move_to(px, y + h / 2.0 + column.rms * h / 2.0);
+                    path2.line_to(px, y + h / 2.0 - column.rms * h / 2.0);
+                }
+
+            // Draw min/max paths
+            let [r, g, b, a] = PEAK_COLOR;
+            let mut paint = Paint::color(femtovg::Color::rgba(r, g, b, a));
+            paint.set_line_width(1.0);
+            paint.set_anti_alias(false);
+            canvas.stroke_path(&mut path1, paint);
+
+            // Draw rms paths
+            if self.samples_per_pixel >= RMS_MIN_SAMPLES_PER_PIXEL {
+                let [r, g, b, a] = RMS_COLOR;
+                let mut paint = Paint::color(femtovg::Color::rgba(r, g, b, a));
+                paint.set_line_width(1.0);
+                paint.set_anti_alias(false);
+                canvas.stroke_path(&mut path2, paint);
+            }
+        //}
+    }
+}
+
+impl BuildHandler for AppWidget {
+    type Ret = Entity;
+    fn on_build(&mut self, state: &mut State, entity: Entity) -> Self::Ret {
+        entity.set_flex_grow(state, 1.0);
+
+        state.focused = entity;
+        // TEMP - Animations cause the event loop to run continuously so that the timeline cursor moves smoothly
+        let animation_state = AnimationState::new()
+            .with_duration(std::time::Duration::from_secs(200))
+            .with_keyframe((0.0, Color::rgb(0,0,0)))
+            .with_keyframe((1.0, Color::rgb(255,255,255)))
+            .set_persistent(true);
+
+        self.random_animation = state
+            .style
+            .border_color
+            .insert_animation(animation_state.clone());
+
+        // Header
+        let header = Element::new().build(state, entity, |builder| builder.class("header"));
+
+        // Collapsible panel showing the file format and metadata, hidden until toggled
+        self.info_panel = Element::new().build(state, entity, |builder| builder.class("info_panel"));
+        self.info_panel.set_display(state, Display::None);
+
+        // Marker editor, hidden until toggled. Add makes a region when there is a selection
+        self.marker_panel = Element::new().build(state, entity, |builder| builder.class("marker_panel"));
+        self.marker_panel.set_display(state, Display::None);
+
+        let marker_buttons = [
+            ("Add", AppEvent::AddMarker),
+            ("Delete", AppEvent::DeleteMarker),
+            ("Prev", AppEvent::PreviousMarker),
+            ("Next", AppEvent::NextMarker),
+            ("Play", AppEvent::PlayMarker),
+            ("Save", AppEvent::SaveMarkers),
+            ("Import", AppEvent::ImportMarkers),
+        ];
+        for (text, app_event) in marker_buttons.iter() {
+            Button::with_label(text)
+                .on_press(Event::new(app_event.clone()).target(entity))
+                .build(state, self.marker_panel, |builder| builder.class("toggle"));
+        }
+
+        Label::new("Name").build(state, self.marker_panel, |builder| builder.class("option_title"));
+        self.marker_name = Textbox::new("").build(state, self.marker_panel, |builder| builder.class("marker_name"));
+
+        let marker_colors = RadioList::new().build(state, self.marker_panel, |builder| builder.class("checklist"));
+        for (idx, color) in MarkerColor::ALL.iter().enumerate() {
+            let (r, g, b) = color.rgb();
+            let button = RadioButton::new()
+                .on_checked(Event::new(AppEvent::SetMarkerColor(*color)).target(entity))
+                .build(state, marker_colors, |builder| {
+                    let builder = builder.class("marker_color").set_background_color(Color::rgb(r, g, b));
+                    if idx == 0 {
+                        builder.class("first")
+                    } else if idx == MarkerColor::ALL.len() - 1 {
+                        builder.class("last")
+                    } else {
+                        builder
+                    }
+                });
+            self.marker_color_buttons.push((*color, button));
+        }
+
+        // Export of the selection, hidden until toggled. The channel toggles are added when a file is loaded
+        self.export_panel = Element::new().build(state, entity, |builder| builder.class("export_panel"));
+        self.export_panel.set_display(state, Display::None);
+
+        Label::new("Channels").build(state, self.export_panel, |builder| builder.class("option_title"));
+        self.export_channels = Element::new().build(state, self.export_panel, |builder| builder.class("export_channels"));
+
+        Label::new("Depth").build(state, self.export_panel, |builder| builder.class("option_title"));
+        let depths = RadioList::new().build(state, self.export_panel, |builder| builder.class("checklist"));
+        for (idx, depth) in BitDepth::ALL.iter().enumerate() {
+            let button = RadioButton::new()
+                .on_checked(Event::new(AppEvent::SetExportDepth(*depth)).target(entity))
+                .build(state, depths, |builder| {
+                    let builder = builder.set_text(depth.name());
+                    if idx == 0 {
+                        builder.class("first")
+                    } else if idx == BitDepth::ALL.len() - 1 {
+                        builder.class("last")
+                    } else {
+                        builder
+                    }
+                });
+            if *depth == self.export_settings.bit_depth {
+                button.set_checked(state, true);
+            }
+        }
+
+        Label::new("Dither").build(state, self.export_panel, |builder| builder.class("option_title"));
+        Checkbox::new(false)
+            .on_unchecked(Event::new(AppEvent::ExportDither(false)).target(entity))
+            .on_checked(Event::new(AppEvent::ExportDither(true)).target(entity))
+            .with_icon_checked("On")
+            .with_icon_unchecked("Off")
+            .build(state, self.export_panel, |builder| builder.class("curve"));
+
+        Button::with_label("Export...")
+            .on_press(Event::new(AppEvent::ExportSelection).target(entity))
+            .build(state, self.export_panel, |builder| builder.class("toggle").class("wide"));
+
+        self.export_status = Label::new("").build(state, self.export_panel, |builder| builder.class("progress"));
+
+        // Editing of the loaded file, hidden until toggled. Each row of buttons is followed by its value
+        self.edit_panel = Element::new().build(state, entity, |builder| builder.class("edit_panel"));
+        self.edit_panel.set_display(state, Display::None);
+
+        let edit_rows = [
+            vec![
+                ("Undo", EditCommand::Undo),
+                ("Redo", EditCommand::Redo),
+                ("Cut", EditCommand::Cut),
+                ("Copy", EditCommand::Copy),
+                ("Paste", EditCommand::Paste),
+                ("Delete", EditCommand::Delete),
+                ("Crop", EditCommand::Crop),
+                ("Silence", EditCommand::InsertSilence),
+            ],
+            vec![
+                ("Fade In", EditCommand::FadeIn),
+                ("Fade Out", EditCommand::FadeOut),
+                ("Normalize", EditCommand::Normalize),
+                ("Reverse", EditCommand::Reverse),
+                ("Invert", EditCommand::Invert),
+                ("Gain", EditCommand::Gain),
+            ],
+        ];
+        for (row_idx, commands) in edit_rows.iter().enumerate() {
+            let row = Element::new().build(state, self.edit_panel, |builder| builder.class("edit_controls"));
+            for (text, command) in commands.iter() {
+                Button::with_label(text)
+                    .on_press(Event::new(AppEvent::Edit(*command)).target(entity))
+                    .build(state, row, |builder| builder.class("toggle").class("wide"));
+            }
+
+            if row_idx == 0 {
+                self.edit_silence = Textbox::new(&self.silence_seconds.to_string())
+                    .build(state, row, |builder| builder.class("edit_value"));
+                Label::new("s").build(state, row, |builder| builder.class("option_title"));
+            } else {
+                self.edit_gain = Textbox::new(&self.gain_db.to_string())
+                    .build(state, row, |builder| builder.class("edit_value"));
+                Label::new("dB").build(state, row, |builder| builder.class("option_title"));
+            }
+        }
+
+        let save_row = Element::new().build(state, self.edit_panel, |builder| builder.class("edit_controls"));
+        Button::with_label("Save")
+            .on_press(Event::new(AppEvent::Edit(EditCommand::Save)).target(entity))
+            .build(state, save_row, |builder| builder.class("toggle").class("wide"));
+        Button::with_label("Save As...")
+            .on_press(Event::new(AppEvent::Edit(EditCommand::SaveAs)).target(entity))
+            .build(state, save_row, |builder| builder.class("toggle").class("wide"));
+        self.edit_status = Label::new("").build(state, save_row, |builder| builder.class("progress"));
+
+        // Output device and format, hidden until toggled. The options are listed when it is shown
+        self.audio_panel = Element::new().build(state, entity, |builder| builder.class("audio_panel"));
+        self.audio_panel.set_display(state, Display::None);
+
+        let audio_controls = Element::new().build(state, self.audio_panel, |builder| builder.class("audio_row"));
+        build_options(state, audio_controls, entity, "Resample",
+            ResampleQuality::ALL.iter().map(|quality| (quality.name().to_string(), AppEvent::SetResampleQuality(*quality))).collect(),
+            ResampleQuality::ALL.iter().position(|quality| *quality == self.resample_quality));
+        Button::with_label("Refresh")
+            .on_press(Event::new(AppEvent::RefreshDevices).target(entity))
+            .build(state, audio_controls, |builder| builder.class("toggle").class("wide"));
+        self.audio_status = Label::new("").build(state, audio_controls, |builder| builder.class("progress"));
+
+        // Slower or faster playback, for transcription and close listening
+        let speed_controls = Element::new().build(state, self.audio_panel, |builder| builder.class("audio_row"));
+        build_options(state, speed_controls, entity, "Speed",
+            SPEED_PRESETS.iter().map(|rate| (format!("{}x", rate), AppEvent::SetRate(*rate))).collect(),
+            SPEED_PRESETS.iter().position(|rate| *rate == self.playback_rate));
+        let speed_modes = [("Follows", SpeedMode::Varispeed), ("Kept", SpeedMode::KeepPitch)];
+        build_options(state, speed_controls, entity, "Pitch",
+            speed_modes.iter().map(|(name, mode)| (name.to_string(), AppEvent::SetSpeedMode(*mode))).collect(),
+            speed_modes.iter().position(|(_, mode)| *mode == self.speed_mode));
+
+        self.routing_view = Element::new().build(state, self.audio_panel, |builder| builder.class("routing"));
+
+        // Loudness of the selection or file, hidden until toggled
+        self.loudness_panel = Element::new().build(state, entity, |builder| builder.class("loudness_panel"));
+        self.loudness_panel.set_display(state, Display::None);
+
+        let loudness_controls = Element::new().build(state, self.loudness_panel, |builder| builder.class("loudness_controls"));
+
+        Label::new("Curve").build(state, loudness_controls, |builder| builder.class("option_title"));
+
+        // Draws the short-term loudness over the waveform
+        Checkbox::new(false)
+            .on_unchecked(Event::new(AppEvent::ShowLoudnessCurve(false)).target(entity))
+            .on_checked(Event::new(AppEvent::ShowLoudnessCurve(true)).target(entity))
+            .with_icon_checked("On")
+            .with_icon_unchecked("Off")
+            .build(state, loudness_controls, |builder| builder.class("curve"));
+
+        self.loudness_status = Label::new("").build(state, loudness_controls, |builder| builder.class("progress"));
+
+        // Spectrogram settings, shown in the spectrogram view
+        self.spectrogram_panel = Element::new().build(state, entity, |builder| builder.class("spectrogram_panel"));
+        self.spectrogram_panel.set_display(state, Display::None);
+
+        let settings = SpectrogramSettings::default();
+        let panel = self.spectrogram_panel;
+
+        let fft_sizes = [512, 1024, 2048, 4096, 8192];
+        build_options(state, panel, entity, "FFT",
+            fft_sizes.iter().map(|size| (size.to_string(), AppEvent::SetSpectrogram(SpectrogramOption::FftSize(*size)))).collect(),
+            fft_sizes.iter().position(|size| *size == settings.fft_size));
+
+        let overlaps = [2, 4, 8];
+        build_options(state, panel, entity, "Overlap",
+            overlaps.iter().map(|overlap| (format!("{}x", overlap), AppEvent::SetSpectrogram(SpectrogramOption::Overlap(*overlap)))).collect(),
+            overlaps.iter().position(|overlap| *overlap == settings.fft_size / settings.hop));
+
+        let windows = [
+            ("Hann", WindowFunction::Hann),
+            ("Hamming", WindowFunction::Hamming),
+            ("Blackman", WindowFunction::Blackman),
+            ("Rect", WindowFunction::Rectangular),
+        ];
+        build_options(state, panel, entity, "Window",
+            windows.iter().map(|(name, window)| (name.to_string(), AppEvent::SetSpectrogram(SpectrogramOption::Window(*window)))).collect(),
+            windows.iter().position(|(_, window)| *window == settings.window));
+
+        let scales = [
+            ("Lin", FrequencyScale::Linear),
+            ("Log", FrequencyScale::Log),
+            ("Mel", FrequencyScale::Mel),
+        ];
+        build_options(state, panel, entity, "Scale",
+            scales.iter().map(|(name, scale)| (name.to_string(), AppEvent::SetSpectrogram(SpectrogramOption::Scale(*scale)))).collect(),
+            scales.iter().position(|(_, scale)| *scale == settings.scale));
+
+        let color_maps = [
+            ("Magma", ColorMap::Magma),
+            ("Viridis", ColorMap::Viridis),
+            ("Gray", ColorMap::Grayscale),
+        ];
+        build_options(state, panel, entity, "Colours",
+            color_maps.iter().map(|(name, color_map)| (name.to_string(), AppEvent::SetSpectrogram(SpectrogramOption::ColorMap(*color_map)))).collect(),
+            color_maps.iter().position(|(_, color_map)| *color_map == settings.color_map));
+        
+
+        self.navigator = Element::new().build(state, entity, |builder| 
+            builder
+                .class("navigator")
+                .set_height(Length::Pixels(100.0))
+        );
+
+        self.navigator_window = Element::new().build(state, self.navigator, |builder| 
+            builder
+                .class("navigator_window")
+                .set_position(Position::Absolute)
+                .set_height(Length::Pixels(100.0))
+        );
 
 
         self.time_axis = Element::new().build(state, entity, |builder| 
@@ -609,6 +2831,28 @@ impl BuildHandler for AppWidget {
                 .set_visibility(Visibility::Invisible)
         );
 
+        // Spectrum analyser, hidden until toggled
+        self.spectrum_panel = Element::new().build(state, entity, |builder| builder.class("spectrum_panel"));
+        self.spectrum_panel.set_display(state, Display::None);
+
+        let spectrum_controls = Element::new().build(state, self.spectrum_panel, |builder| builder.class("spectrum_controls"));
+
+        // Overlays the spectrum at the playhead during playback
+        Checkbox::new(false)
+            .on_unchecked(Event::new(AppEvent::SpectrumFollowPlayhead(false)).target(entity))
+            .on_checked(Event::new(AppEvent::SpectrumFollowPlayhead(true)).target(entity))
+            .with_icon_checked(ICON_PLAY)
+            .with_icon_unchecked(ICON_PLAY)
+            .build(state, spectrum_controls, |builder| {
+                builder
+                    .set_font("Icons")
+                    .class("live")
+            });
+
+        self.spectrum_label = Label::new("").build(state, spectrum_controls, |builder| builder.class("spectrum_readout"));
+
+        self.spectrum_view = Element::new().build(state, self.spectrum_panel, |builder| builder.class("spectrum_view"));
+
         // Footer
         let footer = Element::new().build(state, entity, |builder| builder.class("footer"));
 
@@ -622,6 +2866,46 @@ impl BuildHandler for AppWidget {
                     .class("open")
             });
 
+        // Toggles the file info panel
+        Button::with_label("Info")
+            .on_press(Event::new(AppEvent::ToggleInfo).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+
+        // Toggles the spectrum analyser below the waveform
+        Button::with_label("Spectrum")
+            .on_press(Event::new(AppEvent::ToggleSpectrum).target(entity))
+            .build(state, header, |builder| builder.class("toggle").class("wide"));
+
+        // Toggles the loudness measurements
+        Button::with_label("Loudness")
+            .on_press(Event::new(AppEvent::ToggleLoudness).target(entity))
+            .build(state, header, |builder| builder.class("toggle").class("wide"));
+
+        // Toggles the marker editor
+        Button::with_label("Markers")
+            .on_press(Event::new(AppEvent::ToggleMarkers).target(entity))
+            .build(state, header, |builder| builder.class("toggle").class("wide"));
+
+        // Toggles the edit panel
+        Button::with_label("Edit")
+            .on_press(Event::new(AppEvent::ToggleEdit).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+
+        // Toggles the export of the selection to a new file
+        Button::with_label("Export")
+            .on_press(Event::new(AppEvent::ToggleExport).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+
+        // Checks the file for clipping, silence and DC offset
+        Button::with_label("Check")
+            .on_press(Event::new(AppEvent::CheckIssues).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+
+        // Toggles the choice of output device
+        Button::with_label("Audio")
+            .on_press(Event::new(AppEvent::ToggleAudio).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+
         // Transpoort controls
         let transport = Element::new().build(state, header, |builder| builder.class("transport"));
 
@@ -680,7 +2964,6 @@ impl BuildHandler for AppWidget {
 
         //Button::new().build(state, header, |builder| builder.set_text(ICON_SOUND).set_font("Icons").class("volume"));
 
-
         Checkbox::new(true)
             .on_unchecked(Event::new(AppEvent::Mute(false)).target(entity))        
             .on_checked(Event::new(AppEvent::Mute(true)).target(entity))
@@ -696,31 +2979,13 @@ impl BuildHandler for AppWidget {
             .on_change(move |value| Event::new(AppEvent::Volume(value)).target(entity))
             .build(state, header, |builder| builder.class("volume"));
 
+        // Output meters, clicking them clears the clip indicators
+        self.meter_view = Element::new().build(state, header, |builder| builder.class("meters"));
 
-        // Channels selector
-        let channels = RadioList::new().build(state, header, |builder| builder.class("checklist"));
-
-            // Left
-            RadioButton::new()
-                .on_checked(Event::new(AppEvent::SwicthChannel(ChannelMode::Left)).target(entity))
-                .build(state, channels, |builder| {
-                    builder.set_text("L").class("first")
-                    }).set_checked(state, true);
 
-            // Right
-            RadioButton::new()
-                .on_checked(Event::new(AppEvent::SwicthChannel(ChannelMode::Right)).target(entity))
-                .build(state, channels, |builder| builder.set_text("R"));
-            
-            // Both
-            RadioButton::new()
-                .on_checked(Event::new(AppEvent::SwicthChannel(ChannelMode::Both)).target(entity))
-                .build(state, channels, |builder| {
-                    builder
-                        .set_text("L + R")
-                        .class("last")
-                        .set_width(Length::Pixels(60.0))
-                });
+        // Channels selector, populated when a file is loaded
+        self.channels_list = RadioList::new().build(state, header, |builder| builder.class("checklist"));
+        self.build_channel_selector(state, entity);
         
         // // Cursor time
         // self.time_label = Label::new("Time: -").build(state, header, |builder| {
@@ -746,11 +3011,65 @@ impl BuildHandler for AppWidget {
             RadioButton::new()
                 .on_checked(Event::new(AppEvent::SwitchUnits(UnitsMode::Decibel)).target(entity))
                 .build(state, units, |builder| builder.set_text("dB").class("last"));
+
+        // Sample marker selector, used when zoomed in past one sample per pixel
+        let sample_styles = RadioList::new().build(state, header, |builder| builder.class("checklist"));
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchSampleStyle(SampleStyle::Dots)).target(entity))
+                .build(state, sample_styles, |builder| {
+                    builder.set_text("Dots").class("first")
+                }).set_checked(state, true);
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchSampleStyle(SampleStyle::Stems)).target(entity))
+                .build(state, sample_styles, |builder| builder.set_text("Stems").class("last"));
+
+        // Interpolation selector, used when zoomed in past one sample per pixel
+        let interpolations = RadioList::new().build(state, header, |builder| builder.class("checklist"));
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchInterpolation(Interpolation::Line)).target(entity))
+                .build(state, interpolations, |builder| builder.set_text("Line").class("first"));
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchInterpolation(Interpolation::Sinc)).target(entity))
+                .build(state, interpolations, |builder| {
+                    builder.set_text("Sinc").class("last")
+                }).set_checked(state, true);
+
+        // View selector
+        let views = RadioList::new().build(state, header, |builder| builder.class("checklist"));
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchView(ViewMode::Waveform)).target(entity))
+                .build(state, views, |builder| {
+                    builder.set_text("Wave").class("first")
+                }).set_checked(state, true);
+
+            RadioButton::new()
+                .on_checked(Event::new(AppEvent::SwitchView(ViewMode::Spectrogram)).target(entity))
+                .build(state, views, |builder| builder.set_text("Spec").class("last"));
+
+        // Shows the progress of a file being loaded, hidden when nothing is loading
+        self.progress_label = Label::new("").build(state, header, |builder| builder.class("progress"));
+        self.progress_label.set_display(state, Display::None);
+
+        self.cancel_button = Button::with_label("Cancel")
+            .on_press(Event::new(AppEvent::CancelLoad).target(entity))
+            .build(state, header, |builder| builder.class("toggle"));
+        self.cancel_button.set_display(state, Display::None);
+
+        // Shows the reason a file failed to load
+        self.error_label = Label::new("").build(state, header, |builder| builder.class("error"));
         
 
 
         // FOOTER
 
+        // Progress and summary of the issue check
+        self.issues_label = Label::new("").build(state, footer, |builder| builder.class("progress"));
+
         self.cursor_label = Label::new("Cursor:  00''00'00.0").build(state, footer, |builder| {
             builder.class("info").set_margin(Length::Pixels(20.0))
         });
@@ -794,63 +3113,24 @@ impl BuildHandler for AppWidget {
                 .set_flex_direction(FlexDirection::Column)
         });
 
-        self.zoom_8 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(8, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("147X").class("zoom")
-            });
-
-        self.zoom_7 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(7, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("49X").class("zoom")
-            });
-
-        self.zoom_6 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(6, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("21X").class("zoom")
-            });
-
-        self.zoom_5 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(5, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("9X").class("zoom")
-            });
-
-        self.zoom_4 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(4, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("3X").class("zoom")
-            });
-
-        self.zoom_3 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(3, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("1X").class("zoom")
-            }).set_checked(state, true);
+        // Closest zoom at the top
+        for zoom in ZOOM_PRESETS.iter().rev() {
+            let button = RadioButton::new()
+                .on_checked(Event::new(AppEvent::SetZoom(*zoom, ZoomMode::Cursor)))
+                .build(state, zoom_levels_list, |builder| {
+                    builder.set_text(&zoom_label(*zoom)).class("zoom")
+                });
 
-        self.zoom_2 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(2, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("0.5X").class("zoom")
-            });
+            if *zoom == 1.0 {
+                button.set_checked(state, true);
+            }
 
-        self.zoom_1 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(1, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("0.25X").class("zoom")
-            });
-        
-        self.zoom_0 = RadioButton::new()
-            .on_checked(Event::new(AppEvent::SetZoomLevel(0, ZoomMode::Cursor)))
-            .build(state, zoom_levels_list, |builder| {
-                builder.set_text("0.1X").class("zoom")
-            });
+            self.zoom_buttons.push((*zoom, button));
+        }
 
         // TODO
         // RadioButton::new()
-        //     .on_checked(Event::new(AppEvent::SetZoomLevel(0)))
+        //     .on_checked(Event::new(AppEvent::SetZoom(0)))
         //     .build(state, zoom_levels_list, |builder| {
         //         builder.set_text("FIT").class("zoom")
         //     });
@@ -870,6 +3150,27 @@ impl BuildHandler for AppWidget {
 
 impl EventHandler for AppWidget {
     fn on_event(&mut self, state: &mut State, entity: Entity, event: &mut Event) {
+
+        // Renaming the selected marker
+        if let Some(TextboxEvent::ValueChanged(name)) = event.message.downcast::<TextboxEvent>() {
+            if event.target == self.marker_name {
+                if let Some(id) = self.selected_marker {
+                    self.markers.rename(id, name);
+                }
+            }
+
+            // The edit values are kept until the text is a number again
+            if event.target == self.edit_silence {
+                if let Ok(seconds) = name.trim().parse::<f64>() {
+                    self.silence_seconds = seconds.max(0.0);
+                }
+            }
+            if event.target == self.edit_gain {
+                if let Ok(gain_db) = name.trim().parse::<f32>() {
+                    self.gain_db = gain_db;
+                }
+            }
+        }
         
         // Handle window events
         if let Some(window_event) = event.message.downcast::<WindowEvent>() {
@@ -879,23 +3180,39 @@ impl EventHandler for AppWidget {
                 WindowEvent::GeometryChanged(_) => {
                     if event.target == entity {
                         let total_samples =
-                        (state.data.get_width(entity) * self.samples_per_pixel as f32) as i32;
+                        (state.data.get_width(entity) * self.samples_per_pixel) as i32;
                         self.end = self.start + (total_samples as usize).min(self.num_of_samples);
-                        if let Some(file) = self.controller.file.as_ref() {
-                            self.waveform_left.set_num_pixels(&file.data[0..self.num_of_samples], state.data.get_width(entity) as usize);
-                            self.waveform_right.set_num_pixels(&file.data[self.num_of_samples..self.num_of_samples*2], state.data.get_width(entity) as usize);
+                        let num_of_pixels = state.data.get_width(entity) as usize;
+                        for waveform in self.waveforms.iter_mut() {
+                            waveform.set_num_pixels(self.num_of_samples, num_of_pixels);
                         }
                     }
                 }
 
                 
                 WindowEvent::MouseDown(button) => {
+                    // Clicking on an issue marker selects the issue
+                    let (mouse_x, mouse_y) = state.mouse.left.pos_down;
+                    let issue = if *button != MouseButton::Left {
+                        None
+                    } else if event.target == entity {
+                        self.issue_at(state, mouse_x, mouse_y)
+                    } else if event.target == self.navigator {
+                        self.navigator_issue_at(state, mouse_x)
+                    } else {
+                        None
+                    };
+
+                    if let Some((start, end)) = issue {
+                        self.go_to(state, entity, start, end);
+                        event.consume();
+                    }
                     // Clicking on the waveform moves the cursor to that position
-                    if event.target == entity {
+                    else if event.target == entity {
                         if *button == MouseButton::Left {
                             // Move cursor to clicked position
                             let cursor_pos_pixel = state.mouse.left.pos_down.0 - state.data.get_posx(entity);
-                            self.cursor = self.start + (self.samples_per_pixel as f32 * cursor_pos_pixel) as usize;
+                            self.cursor = self.start + (self.samples_per_pixel * cursor_pos_pixel) as usize;
                             self.select = self.cursor;
 
                             let time = self.cursor as f32 / self.sample_rate as f32;
@@ -908,6 +3225,21 @@ impl EventHandler for AppWidget {
                             self.extend_selection_right.set_left(state, Length::Pixels(cursor_pos_pixel + 10.0));
                         }
                     }
+                    if event.target == self.meter_view {
+                        self.meters.reset_clips();
+                    }
+                    // Clicking on a marker on the time axis selects it, and dragging moves it
+                    if event.target == self.time_axis && *button == MouseButton::Left {
+                        if let Some(id) = self.marker_at(state, mouse_x) {
+                            if let Some((start, end)) = self.markers.get(id).map(|marker| (marker.position, marker.end())) {
+                                self.select_marker(state, Some(id));
+                                self.set_cursor(state, start, end);
+                                self.dragging_marker = Some(id);
+                                state.capture(entity);
+                                event.consume();
+                            }
+                        }
+                    }
                     // Clicking on the navigator window allows smooth panning of the waveform
                     if event.target == self.navigator_window && !self.follow_playhead {
                         self.panning = true;
@@ -919,6 +3251,7 @@ impl EventHandler for AppWidget {
                 WindowEvent::MouseUp(button) => {
                     if *button == MouseButton::Left {
                         self.panning = false;
+                        self.dragging_marker = None;
                         state.release(entity);
                         //event.consume();
                     }
@@ -926,6 +3259,22 @@ impl EventHandler for AppWidget {
 
                 // Moving the mouse moves the cursor position
                 WindowEvent::MouseMove(x, _) => {
+                    if let Some(id) = self.dragging_marker {
+                        let offset = (*x - state.data.get_posx(self.waveview)) * self.samples_per_pixel;
+                        let position = (self.start as f32 + offset).max(0.0) as usize;
+                        let length = self.markers.get(id).and_then(|marker| marker.length).unwrap_or(0);
+                        let position = position.min(self.num_of_samples.saturating_sub(length));
+                        self.markers.move_to(id, position);
+                        self.set_cursor(state, position, position + length);
+                    }
+
+                    // Track the mouse over the spectrum for the peak readout
+                    if event.target == self.spectrum_view {
+                        self.spectrum_mouse = Some(*x - state.data.get_posx(self.spectrum_view));
+                    } else {
+                        self.spectrum_mouse = None;
+                    }
+
                     if event.target == entity {
 
                         if self.panning {
@@ -960,11 +3309,11 @@ impl EventHandler for AppWidget {
                                 let end_pos = *x - state.data.get_posx(entity);
 
                                 if start_pos > end_pos {
-                                    self.cursor =  self.start + (self.samples_per_pixel as f32 * end_pos) as usize;
-                                    self.select =  self.start + (self.samples_per_pixel as f32 * start_pos) as usize;
+                                    self.cursor =  self.start + (self.samples_per_pixel * end_pos) as usize;
+                                    self.select =  self.start + (self.samples_per_pixel * start_pos) as usize;
                                 } else if end_pos > start_pos {
-                                    self.cursor =  self.start + (self.samples_per_pixel as f32 * start_pos) as usize;
-                                    self.select =  self.start + (self.samples_per_pixel as f32 * end_pos) as usize;
+                                    self.cursor =  self.start + (self.samples_per_pixel * start_pos) as usize;
+                                    self.select =  self.start + (self.samples_per_pixel * end_pos) as usize;
                                 }
 
                                 let time = self.cursor as f32 / self.sample_rate as f32;
@@ -978,7 +3327,7 @@ impl EventHandler for AppWidget {
                                 self.select_label.set_text(state, &time_string);
 
                                 // if (end_pos - start_pos).abs() > 2.0 {
-                                //     self.select =  self.start + (self.samples_per_pixel as f32 * select_end_pos) as usize;
+                                //     self.select =  self.start + (self.samples_per_pixel * select_end_pos) as usize;
                                 // }
 
                                 state.insert_event(Event::new(WindowEvent::Redraw));
@@ -988,7 +3337,7 @@ impl EventHandler for AppWidget {
                                 self.zoom_pos_pixel = *x - state.data.get_posx(entity);
 
                                 self.zoom_pos = self.start
-                                    + (self.samples_per_pixel as f32 * self.zoom_pos_pixel) as usize;
+                                    + (self.samples_per_pixel * self.zoom_pos_pixel) as usize;
 
                                 if self.zoom_pos >= self.num_of_samples {
                                     self.zoom_pos = self.num_of_samples - 1;
@@ -1010,11 +3359,7 @@ impl EventHandler for AppWidget {
                     if *y > 0.0 {
                         if state.modifiers.ctrl {
                             // ZOOM IN
-                            if self.zoom_level != SAMPLES_PER_PIXEL.len() - 1 {
-                                self.zoom_level += 1;
-                            }
-
-                            state.insert_event(Event::new(AppEvent::SetZoomLevel(self.zoom_level, ZoomMode::Mouse)).target(entity));
+                            state.insert_event(Event::new(AppEvent::SetZoom(self.zoom * ZOOM_SCROLL_STEP, ZoomMode::Mouse)).target(entity));
 
                         } else {
                             // PAN
@@ -1023,12 +3368,12 @@ impl EventHandler for AppWidget {
                             let new_start;
                             let new_end ;
 
-                            if samples_to_start < (self.samples_per_pixel as f32 * 30.0) as usize {
+                            if samples_to_start < ((self.samples_per_pixel * 30.0) as usize).max(1) {
                                 new_start = self.start - samples_to_start;
                                 new_end = self.end - samples_to_start;
                             } else {
-                                new_start = self.start - (self.samples_per_pixel as f32 * 30.0) as usize;
-                                new_end =  self.end - (self.samples_per_pixel as f32 * 30.0) as usize;
+                                new_start = self.start - ((self.samples_per_pixel * 30.0) as usize).max(1);
+                                new_end =  self.end - ((self.samples_per_pixel * 30.0) as usize).max(1);
                             }
 
                             self.end = new_end.min(self.num_of_samples - 1);
@@ -1041,11 +3386,7 @@ impl EventHandler for AppWidget {
                     } else if *y < 0.0 {
                         if state.modifiers.ctrl {
                             // ZOOM OUT
-                            if self.zoom_level != 0 {
-                                self.zoom_level -= 1;
-                            }
-
-                            state.insert_event(Event::new(AppEvent::SetZoomLevel(self.zoom_level, ZoomMode::Mouse)).target(entity));
+                            state.insert_event(Event::new(AppEvent::SetZoom(self.zoom / ZOOM_SCROLL_STEP, ZoomMode::Mouse)).target(entity));
 
                         } else {
                             //PAN
@@ -1055,14 +3396,14 @@ impl EventHandler for AppWidget {
                             let new_start;
                             let new_end;
 
-                            if samples_to_end < (self.samples_per_pixel as f32 * 30.0) as usize {
+                            if samples_to_end < ((self.samples_per_pixel * 30.0) as usize).max(1) {
                                 new_start = self.start + samples_to_end;
                                 new_end = self.end + samples_to_end;
                             } else {
-                                new_start = self.start + (self.samples_per_pixel as f32 * 30.0) as usize;
+                                new_start = self.start + ((self.samples_per_pixel * 30.0) as usize).max(1);
                                 //let sample_end = self.start + state.data.get_width(entity) * self.samples_per_pixel;
                                 
-                                new_end =  self.end + (self.samples_per_pixel as f32 * 30.0) as usize;
+                                new_end =  self.end + ((self.samples_per_pixel * 30.0) as usize).max(1);
                                 // if samle_end < new_end {
                                 //     new_end 
                                 // }
@@ -1112,7 +3453,7 @@ impl EventHandler for AppWidget {
                                     }
                                     
                                 } else {
-                                    self.playhead -= self.samples_per_pixel;
+                                    self.playhead = self.playhead.saturating_sub((self.samples_per_pixel as usize).max(1));
                                     //println!("playhead: {}", self.playhead);
                                     let current_time = (self.playhead as f64 / self.sample_rate).max(0.0);
                                     if current_time <= cursor_time {
@@ -1139,7 +3480,7 @@ impl EventHandler for AppWidget {
                                     
                                     self.controller.seek(new_time);
                                 } else {
-                                    self.playhead += self.samples_per_pixel;
+                                    self.playhead += (self.samples_per_pixel as usize).max(1);
                                     //println!("playhead: {}", self.playhead);
                                     let current_time = (self.playhead as f64 / self.sample_rate).max(0.0);
                                     self.controller.seek(current_time);
@@ -1162,6 +3503,11 @@ impl EventHandler for AppWidget {
                             state.insert_event(Event::new(AppEvent::SeekRight).target(entity));
                         }
 
+                        // Not while typing in one of the text boxes
+                        Some(Key::Delete) if state.focused == entity => {
+                            state.insert_event(Event::new(AppEvent::Edit(EditCommand::Delete)).target(entity));
+                        }
+
                         _=> {}
                     }
                 }
@@ -1176,50 +3522,27 @@ impl EventHandler for AppWidget {
 
                 // Load an audio file specified on the command line
                 AppEvent::LoadAudioFile(file_path) => {
-                    //self.read_audio(file_path);
-                    self.controller.load_file(file_path);
-
-
-                    if let Some(file) = self.controller.file.as_ref() {
-                        self.num_of_channels = file.num_channels;
-                        self.sample_rate = file.sample_rate;
-                        self.num_of_samples = file.num_samples;
-                        println!("Length: {} ", self.num_of_samples);
-                    }
-
+                    self.load_audio_file(state, entity, file_path);
+                }
 
-                    state.insert_event(Event::new(AppEvent::SetZoomLevel(3, ZoomMode::Cursor)).target(entity));
+                // Stop loading a file. If it has already been opened, the waveforms built so far are kept
+                AppEvent::CancelLoad => {
+                    // Dropping the loader stops its thread
+                    self.finish_loading(state);
+                    event.consume();
                 }
 
                 // Load an audio file using a file dialog
                 AppEvent::OpenFileDialog => {
 
                     let result = FileDialog::new()
-                        .show_open_single_file()
-                        .expect("Failed to open file dialog");
+                        .show_open_single_file();
 
                     match result {
-                        Some(file_path) => {
+                        Ok(Some(file_path)) => {
                             println!("File path = {:?}", file_path);
 
-                            //self.read_audio(file_path.as_os_str().to_str().unwrap());
-
-                            self.controller.load_file(file_path.as_os_str().to_str().unwrap());
-                            self.controller.seek(0.0);
-                            self.is_playing = false;
-
-                            if let Some(file) = self.controller.file.as_ref() {
-                                self.num_of_channels = file.num_channels;
-                                self.sample_rate = file.sample_rate;
-                                self.num_of_samples = file.num_samples;
-                                println!("Length: {} ", file.num_samples);
-
-                                self.waveform_left.load(&file.data[0..self.num_of_samples], state.data.get_width(entity) as usize);
-                                self.waveform_right.load(&file.data[self.num_of_samples..self.num_of_samples*2], state.data.get_width(entity) as usize);
-                            }
-
-
-                            state.insert_event(Event::new(AppEvent::SetZoomLevel(3, ZoomMode::Cursor)).target(entity));
+                            self.load_audio_file(state, entity, &file_path.to_string_lossy());
 
                             //let samples_per_pixel = 441.0;
 
@@ -1236,7 +3559,11 @@ impl EventHandler for AppWidget {
                             // self.end = self.end.min(self.num_of_samples - 1).max(0);
                         }
 
-                        None => {}
+                        Ok(None) => {}
+
+                        Err(err) => {
+                            self.show_error(state, &format!("Could not open file dialog: {}", err));
+                        }
                     }
 
                     event.consume();
@@ -1250,13 +3577,54 @@ impl EventHandler for AppWidget {
 
                 // Change the display units 
                 AppEvent::SwitchUnits(units_mode) => {
-                    self.units_mode = units_mode.clone();
+                    self.units_mode = *units_mode;
+                    state.insert_event(Event::new(WindowEvent::Redraw));
+                }
+
+                AppEvent::SwitchSampleStyle(sample_style) => {
+                    self.sample_style = sample_style.clone();
+                    state.insert_event(Event::new(WindowEvent::Redraw));
+                }
+
+                AppEvent::SwitchView(view_mode) => {
+                    self.view_mode = view_mode.clone();
+                    let display = if self.view_mode == ViewMode::Spectrogram {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.spectrogram_panel.set_display(state, display);
+                    state.insert_event(Event::new(WindowEvent::Redraw));
+                }
+
+                AppEvent::SetSpectrogram(option) => {
+                    let settings = &mut self.spectrogram_settings;
+                    match option {
+                        SpectrogramOption::FftSize(fft_size) => {
+                            // Keep the same overlap
+                            let overlap = (settings.fft_size / settings.hop.max(1)).max(1);
+                            settings.fft_size = *fft_size;
+                            settings.hop = (*fft_size / overlap).max(1);
+                        }
+                        SpectrogramOption::Overlap(overlap) => {
+                            settings.hop = (settings.fft_size / overlap).max(1);
+                        }
+                        SpectrogramOption::Window(window) => settings.window = *window,
+                        SpectrogramOption::Scale(scale) => settings.scale = *scale,
+                        SpectrogramOption::ColorMap(color_map) => settings.color_map = *color_map,
+                    }
+                    self.reset_spectrogram();
+                    state.insert_event(Event::new(WindowEvent::Redraw));
+                }
+
+                AppEvent::SwitchInterpolation(interpolation) => {
+                    self.interpolation = interpolation.clone();
                     state.insert_event(Event::new(WindowEvent::Redraw));
                 }
 
                 // Change the current zoom level
                 // TODO - zoom at cursor/playhead position
-                AppEvent::SetZoomLevel(val, zoom_mode) => {
+                AppEvent::SetZoom(val, zoom_mode) => {
                     
                     
                     
@@ -1269,23 +3637,22 @@ impl EventHandler for AppWidget {
                             self.cursor + (self.select - self.cursor) / 2
                         };
                         
-                        (zoom, (zoom - self.start) as f32 / self.samples_per_pixel as f32)
+                        (zoom, (zoom as f32 - self.start as f32) / self.samples_per_pixel)
                     } else {
-                        (self.cursor, (self.cursor - self.start) as f32 / self.samples_per_pixel as f32)
+                        (self.cursor, (self.cursor as f32 - self.start as f32) / self.samples_per_pixel)
                     };
                     
-                    self.zoom_level = *val;
-                    self.samples_per_pixel = SAMPLES_PER_PIXEL[self.zoom_level];
+                    self.set_zoom_factor(*val, state.data.get_width(entity));
 
                     let total_samples =
-                        (state.data.get_width(entity) * self.samples_per_pixel as f32) as i32;
+                        (state.data.get_width(entity) * self.samples_per_pixel) as i32;
 
                     let mut new_start = 0;
                     let mut new_end = total_samples as usize;
 
                     
                     let offset = zoom as i32
-                        - (zoom_pos * self.samples_per_pixel as f32) as i32;
+                        - (zoom_pos * self.samples_per_pixel) as i32;
                     
                     if offset > 0 {
                         new_start = offset as usize;
@@ -1295,61 +3662,51 @@ impl EventHandler for AppWidget {
                     self.end = new_end.min(self.num_of_samples - 1);
                     self.start = new_start.max(0).min(self.end);
 
-                    // This is terrible and I hate myself
-                    let zoom_entity = match self.zoom_level {
-                        0 => self.zoom_0,
-                        1 => self.zoom_1,
-                        2 => self.zoom_2,
-                        3 => self.zoom_3,
-                        4 => self.zoom_4,
-                        5 => self.zoom_5,
-                        6 => self.zoom_6,
-                        7 => self.zoom_7,
-                        8 => self.zoom_8,
-                        _=> Entity::new(0),
-                    };
-
-                    // Send an event that will be intercepted by the radio list to change the zoom selection
-                    state.insert_event(Event::new(CheckboxEvent::Check).target(zoom_entity));
+                    // Check the matching preset in the dropdown, if there is one
+                    for (zoom, button) in self.zoom_buttons.iter() {
+                        let checkbox_event = if (zoom - self.zoom).abs() < 1e-6 * zoom {
+                            CheckboxEvent::Check
+                        } else {
+                            CheckboxEvent::Uncheck
+                        };
+                        state.insert_event(Event::new(checkbox_event).target(*button));
+                    }
                     // Let the dropdown know it should change
-                    state.insert_event(Event::new(AppEvent::SetZoomLevel(self.zoom_level, ZoomMode::Cursor)).target(self.zoom_levels_dropdown).propagate(Propagation::Direct));
+                    state.insert_event(Event::new(AppEvent::SetZoom(self.zoom, ZoomMode::Cursor)).target(self.zoom_levels_dropdown).propagate(Propagation::Direct));
                     state.insert_event(Event::new(WindowEvent::Redraw));
                 }
 
                 AppEvent::IncZoom => {
-                    if self.zoom_level != SAMPLES_PER_PIXEL.len() - 1 {
-                        self.zoom_level += 1;
-                    }
-
-                    state.insert_event(Event::new(AppEvent::SetZoomLevel(self.zoom_level, ZoomMode::Cursor)).target(entity));
+                    state.insert_event(Event::new(AppEvent::SetZoom(self.zoom * ZOOM_BUTTON_STEP, ZoomMode::Cursor)).target(entity));
                 }
 
                 AppEvent::DecZoom => {
-                    if self.zoom_level != 0 {
-                        self.zoom_level -= 1;
-                    }
-
-                    state.insert_event(Event::new(AppEvent::SetZoomLevel(self.zoom_level,  ZoomMode::Cursor)).target(entity));
+                    state.insert_event(Event::new(AppEvent::SetZoom(self.zoom / ZOOM_BUTTON_STEP, ZoomMode::Cursor)).target(entity));
                 }
                 
                 // Initiate playback
                 AppEvent::Play => {
-                    
-                    state.insert_event(Event::new(CheckboxEvent::Uncheck).target(self.play_button));
-
-                    // If stopped, play from cursor
-                    if self.play_state == PlayState::Stopped {
-                        if self.cursor != self.start {
-                            let cursor_time = self.cursor as f64 / self.sample_rate;
-                            self.controller.seek(cursor_time);
-                        }      
-                    }
 
+                    // Nothing moves the playhead without a stream, so the button goes back to play
+                    if self.output_info.is_none() {
+                        self.show_error(state, "No audio output, choose a device in the Audio panel");
+                        state.insert_event(Event::new(CheckboxEvent::Check).target(self.play_button));
+                    } else {
+                        state.insert_event(Event::new(CheckboxEvent::Uncheck).target(self.play_button));
+
+                        // If stopped, play from cursor
+                        if self.play_state == PlayState::Stopped {
+                            if self.cursor != self.start {
+                                let cursor_time = self.cursor as f64 / self.sample_rate;
+                                self.controller.seek(cursor_time);
+                            }
+                        }
 
-                    self.controller.play();
-                    state.style.border_color.play_animation(entity, self.random_animation);
-                    self.is_playing = true;
-                    self.play_state = PlayState::Playing;
+                        self.controller.play();
+                        state.style.border_color.play_animation(entity, self.random_animation);
+                        self.is_playing = true;
+                        self.play_state = PlayState::Playing;
+                    }
                 }
 
                 // Pause playback
@@ -1364,6 +3721,7 @@ impl EventHandler for AppWidget {
                 AppEvent::Stop => {
 
                     self.play_state = PlayState::Stopped;
+                    self.play_until = None;
 
                     self.controller.stop();
 
@@ -1386,9 +3744,9 @@ impl EventHandler for AppWidget {
 
                     if self.follow_playhead {
                         let playhead_pos_pixel = state.data.get_width(self.waveview) / 2.0;
-                        let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel as f32) as i32;
+                        let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel) as i32;
                         let total_samples = (state.data.get_width(entity)
-                                            * self.samples_per_pixel as f32) as usize;
+                                            * self.samples_per_pixel) as usize;
 
                         let mut new_start = 0;
                         let mut new_end = total_samples;
@@ -1408,7 +3766,7 @@ impl EventHandler for AppWidget {
                 AppEvent::SeekLeft => {
                     self.controller.seek(0.0);
                     let total_samples =
-                        (state.data.get_width(entity) * self.samples_per_pixel as f32) as i32;
+                        (state.data.get_width(entity) * self.samples_per_pixel) as i32;
                     self.start = 0;
                     self.end = (total_samples as usize).min(self.num_of_samples);
                     self.start = self.start.max(0);
@@ -1433,9 +3791,9 @@ impl EventHandler for AppWidget {
                     self.follow_playhead = *val;
 
                     let playhead_pos_pixel = state.data.get_width(self.waveview) / 2.0;
-                    let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel as f32) as i32;
+                    let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel) as i32;
                     let total_samples = (state.data.get_width(entity)
-                                        * self.samples_per_pixel as f32) as usize;
+                                        * self.samples_per_pixel) as usize;
 
                     let mut new_start = 0;
                     let mut new_end = total_samples;
@@ -1449,6 +3807,308 @@ impl EventHandler for AppWidget {
                     self.start = new_start.max(0).min(self.end);
                 }
 
+                AppEvent::ToggleInfo => {
+                    self.show_info = !self.show_info;
+                    let display = if self.show_info {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.info_panel.set_display(state, display);
+                }
+
+                AppEvent::ToggleMarkers => {
+                    self.show_markers = !self.show_markers;
+                    let display = if self.show_markers {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.marker_panel.set_display(state, display);
+                }
+
+                AppEvent::AddMarker => {
+                    if self.controller.file.is_some() {
+                        let id = if self.select != self.cursor {
+                            let start = self.cursor.min(self.select);
+                            let end = self.cursor.max(self.select);
+                            self.markers.add(start, Some(end - start))
+                        } else {
+                            self.markers.add(self.cursor, None)
+                        };
+                        self.select_marker(state, Some(id));
+                        state.insert_event(Event::new(WindowEvent::Redraw));
+                    }
+                }
+
+                AppEvent::DeleteMarker => {
+                    if let Some(id) = self.selected_marker {
+                        self.markers.remove(id);
+                        self.select_marker(state, None);
+                        state.insert_event(Event::new(WindowEvent::Redraw));
+                    }
+                }
+
+                AppEvent::NextMarker => {
+                    if let Some(id) = self.markers.next(self.cursor).map(|marker| marker.id) {
+                        self.go_to_marker(state, entity, id);
+                    }
+                }
+
+                AppEvent::PreviousMarker => {
+                    if let Some(id) = self.markers.previous(self.cursor).map(|marker| marker.id) {
+                        self.go_to_marker(state, entity, id);
+                    }
+                }
+
+                // Play the selected region, or from the selected marker
+                AppEvent::PlayMarker => {
+                    let region = self
+                        .selected_marker
+                        .and_then(|id| self.markers.get(id))
+                        .map(|marker| (marker.position, marker.end(), marker.is_region()));
+
+                    if let Some((start, end, is_region)) = region {
+                        self.set_cursor(state, start, end);
+                        self.play_until = if is_region && !self.should_loop {
+                            Some(end)
+                        } else {
+                            None
+                        };
+                        self.controller.seek(start as f64 / self.sample_rate);
+                        self.playhead = start;
+                        state.insert_event(Event::new(AppEvent::Play).target(entity));
+                    }
+                }
+
+                AppEvent::SetMarkerColor(color) => {
+                    if let Some(id) = self.selected_marker {
+                        self.markers.set_color(id, *color);
+                        state.insert_event(Event::new(WindowEvent::Redraw));
+                    }
+                }
+
+                // Save to a copy of the WAV or a sidecar, by the extension chosen
+                AppEvent::SaveMarkers => {
+                    if let Some(file) = self.controller.file.as_ref() {
+                        let is_wav = file.info.format == "WAV";
+                        match marker_file_dialog().show_save_single_file() {
+                            Ok(Some(path)) => {
+                                let path = path.to_string_lossy().to_string();
+                                let result = match MarkerFormat::from_path(&path) {
+                                    Some(MarkerFormat::Wav) if !is_wav => {
+                                        Err("markers can only be saved into a copy of a WAV file".to_string())
+                                    }
+                                    Some(format) => {
+                                        marker_files::save(&path, format, &self.markers, &self.file_path, self.sample_rate)
+                                            .map_err(|err| err.to_string())
+                                    }
+                                    None => Err("unknown file type, use .wav, .json, .csv or .txt".to_string()),
+                                };
+
+                                match result {
+                                    Ok(()) => self.show_error(state, ""),
+                                    Err(err) => {
+                                        self.show_error(state, &format!("Could not save markers to {}: {}", file_name(&path), err))
+                                    }
+                                }
+                            }
+
+                            Ok(None) => {}
+
+                            Err(err) => {
+                                self.show_error(state, &format!("Could not open file dialog: {}", err));
+                            }
+                        }
+                    }
+                }
+
+                // Add the markers of a file to those already placed
+                AppEvent::ImportMarkers => {
+                    if self.controller.file.is_some() {
+                        match marker_file_dialog().show_open_single_file() {
+                            Ok(Some(path)) => {
+                                let path = path.to_string_lossy().to_string();
+                                let result = match MarkerFormat::from_path(&path) {
+                                    Some(format) => {
+                                        marker_files::read(&path, format, self.sample_rate).map_err(|err| err.to_string())
+                                    }
+                                    None => Err("unknown file type, use .wav, .json, .csv or .txt".to_string()),
+                                };
+
+                                match result {
+                                    Ok(imported) => {
+                                        self.show_error(state, "");
+                                        for marker in imported {
+                                            self.markers.insert(marker);
+                                        }
+                                        state.insert_event(Event::new(WindowEvent::Redraw));
+                                    }
+                                    Err(err) => {
+                                        self.show_error(state, &format!("Could not import markers from {}: {}", file_name(&path), err))
+                                    }
+                                }
+                            }
+
+                            Ok(None) => {}
+
+                            Err(err) => {
+                                self.show_error(state, &format!("Could not open file dialog: {}", err));
+                            }
+                        }
+                    }
+                }
+
+                AppEvent::ToggleExport => {
+                    self.show_export = !self.show_export;
+                    let display = if self.show_export {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.export_panel.set_display(state, display);
+                }
+
+                AppEvent::ExportChannel(channel, export) => {
+                    let channels = &mut self.export_settings.channels;
+                    channels.retain(|exported| exported != channel);
+                    if *export {
+                        channels.push(*channel);
+                        channels.sort();
+                    }
+                }
+
+                AppEvent::SetExportDepth(bit_depth) => {
+                    self.export_settings.bit_depth = *bit_depth;
+                }
+
+                AppEvent::ExportDither(dither) => {
+                    self.export_settings.dither = *dither;
+                }
+
+                // Write the selection, or the whole file when nothing is selected, on a worker thread.
+                // Starting another export cancels the last one
+                AppEvent::ExportSelection => {
+                    if let Some(file) = self.controller.file.as_ref().map(Shared::clone) {
+                        if self.export_settings.channels.is_empty() {
+                            self.show_error(state, "Choose at least one channel to export");
+                        } else {
+                            match FileDialog::new().add_filter("WAV file", &["wav"]).show_save_single_file() {
+                                Ok(Some(path)) => {
+                                    let (start, end) = if self.cursor != self.select {
+                                        (self.cursor.min(self.select), self.cursor.max(self.select))
+                                    } else {
+                                        (0, file.num_samples)
+                                    };
+
+                                    self.show_error(state, "");
+                                    self.export_path = path.to_string_lossy().to_string();
+                                    let source_path = self.file_path.clone();
+                                    let export_path = self.export_path.clone();
+                                    let settings = self.export_settings.clone();
+                                    self.export = Some(Analysis::start(file, start, end, move |file, start, end, progress| {
+                                        export::export(file, start, end, &source_path, &export_path, &settings, progress)
+                                    }));
+                                }
+
+                                Ok(None) => {}
+
+                                Err(err) => {
+                                    self.show_error(state, &format!("Could not open file dialog: {}", err));
+                                }
+                            }
+                        }
+                    }
+                }
+
+                AppEvent::ToggleEdit => {
+                    self.show_edit = !self.show_edit;
+                    let display = if self.show_edit {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.edit_panel.set_display(state, display);
+                }
+
+                AppEvent::Edit(command) => {
+                    self.edit(state, entity, *command);
+                }
+
+                AppEvent::ToggleAudio => {
+                    self.show_audio = !self.show_audio;
+                    let display = if self.show_audio {
+                        self.build_audio_options(state, entity);
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.audio_panel.set_display(state, display);
+                }
+
+                AppEvent::RefreshDevices => {
+                    self.build_audio_options(state, entity);
+                }
+
+                AppEvent::SetOutput(option) => {
+                    self.set_output(state, entity, option.clone());
+                }
+
+                AppEvent::Route(option) => {
+                    self.route(state, entity, option.clone());
+                }
+
+                AppEvent::SetRate(rate) => {
+                    self.playback_rate = *rate;
+                    self.controller.set_rate(*rate);
+                }
+
+                AppEvent::SetSpeedMode(speed_mode) => {
+                    self.speed_mode = *speed_mode;
+                    self.controller.set_speed_mode(*speed_mode);
+                }
+
+                AppEvent::SetResampleQuality(quality) => {
+                    self.resample_quality = *quality;
+                    self.controller.set_resample_quality(*quality);
+                }
+
+                AppEvent::CheckIssues => {
+                    if let Some(file) = self.controller.file.as_ref() {
+                        self.issues = Some(Analysis::start(Shared::clone(file), 0, file.num_samples, issues::analyse));
+                    }
+                }
+
+                AppEvent::Poll => self.poll(state, entity),
+
+                AppEvent::ToggleLoudness => {
+                    self.show_loudness = !self.show_loudness;
+                    let display = if self.show_loudness {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.loudness_panel.set_display(state, display);
+                }
+
+                AppEvent::ShowLoudnessCurve(val) => {
+                    self.show_loudness_curve = *val;
+                }
+
+                AppEvent::ToggleSpectrum => {
+                    self.show_spectrum = !self.show_spectrum;
+                    let display = if self.show_spectrum {
+                        Display::Flexbox
+                    } else {
+                        Display::None
+                    };
+                    self.spectrum_panel.set_display(state, display);
+                }
+
+                AppEvent::SpectrumFollowPlayhead(val) => {
+                    self.spectrum_follow_playhead = *val;
+                }
+
                 AppEvent::Loop(val) => {
                     self.should_loop = *val;
                 }
@@ -1473,6 +4133,17 @@ impl EventHandler for AppWidget {
 
     // Draw the waveform
     fn on_draw(&mut self, state: &mut State, entity: Entity, canvas: &mut Canvas<OpenGl>) {
+        // The work done on other threads is taken, and the widgets showing it changed, by
+        // an event rather than while drawing
+        state.insert_event(Event::new(AppEvent::Poll).target(entity));
+        self.poll_spectrogram(canvas);
+
+        while let Some(frame) = self.controller.pop_meter_frame() {
+            self.meters.push(&frame);
+        }
+        self.meters.update(std::time::Instant::now());
+        self.draw_meters(state, canvas);
+
         let x = state.data.get_posx(self.waveview);
         let y = state.data.get_posy(self.waveview);
         let h = state.data.get_height(self.waveview);
@@ -1500,7 +4171,7 @@ impl EventHandler for AppWidget {
 
         let cursor_pos = if self.cursor > self.start && self.cursor < self.end {
 
-            (self.cursor - self.start) as f32  / self.samples_per_pixel as f32
+            (self.cursor - self.start) as f32  / self.samples_per_pixel
 
         } else if self.cursor > self.end {
             x + w
@@ -1510,7 +4181,7 @@ impl EventHandler for AppWidget {
 
         let select_pos = if self.select > self.start && self.select < self.end {
 
-            (self.select - self.start) as f32  / self.samples_per_pixel as f32
+            (self.select - self.start) as f32  / self.samples_per_pixel
 
         } else if self.select > self.end {
             x + w
@@ -1533,7 +4204,7 @@ impl EventHandler for AppWidget {
         );  
         
 
-        if let Some(file) = self.controller.file.as_ref() {
+        if let Some(file) = self.controller.file.clone() {
 
             let time_axis_posx = state.data.get_posx(self.time_axis);
             let time_axis_posy = state.data.get_posy(self.time_axis);
@@ -1550,7 +4221,7 @@ impl EventHandler for AppWidget {
 
             for n in (first..last+1) {
                 let sample = self.sample_rate as f32 * n as f32 - self.start as f32;
-                let pixel = ((1.0 / self.samples_per_pixel as f32) * sample).round();
+                let pixel = ((1.0 / self.samples_per_pixel) * sample).round();
                 let mut path = Path::new();
                 path.move_to(time_axis_posx + pixel, time_axis_posy);
                 path.line_to(time_axis_posx + pixel, time_axis_posy + time_axis_height);
@@ -1561,38 +4232,48 @@ impl EventHandler for AppWidget {
             }
 
 
-            let start = round_up(self.start as u32, self.samples_per_pixel as u32) as usize / self.samples_per_pixel;
-
-            match self.channel_mode {
-                ChannelMode::Left => {
-                    self.draw_channel(state, entity, &self.waveform_left, SAMPLES_PER_PIXEL.len(), 0, navigator_posy, navigator_height, canvas);
-                    self.draw_channel(state, entity, &self.waveform_left,self.zoom_level, start, y, h, canvas);
-                }
-
-                ChannelMode::Right => {
-                    self.draw_channel(state, entity, &self.waveform_right, SAMPLES_PER_PIXEL.len(), 0, navigator_posy, navigator_height, canvas);
-                    self.draw_channel(state, entity, &self.waveform_right, self.zoom_level, start, y, h, canvas);
+            // Stack the visible channels vertically, one lane per channel
+            let channels = self.visible_channels();
+            let num_lanes = channels.len().max(1) as f32;
+            let lane_height = h / num_lanes;
+            let navigator_lane_height = navigator_height / num_lanes;
+
+            let mut peaks = Vec::new();
+            for (lane, channel) in channels.iter().enumerate() {
+                let waveform = &self.waveforms[*channel];
+                let lane = lane as f32;
+                self.draw_channel(state, entity, waveform.get_data(SAMPLES_PER_PIXEL.len()), navigator_posy + lane * navigator_lane_height, navigator_lane_height, canvas);
+                if self.view_mode == ViewMode::Spectrogram {
+                    self.draw_spectrogram(state, &file, *channel, y + lane * lane_height, lane_height, canvas);
+                } else if self.samples_per_pixel >= 1.0 {
+                    self.view_peaks(&file, waveform, *channel, w, &mut peaks);
+                    self.draw_channel(state, entity, &peaks, y + lane * lane_height, lane_height, canvas);
+                } else {
+                    self.draw_samples(state, &file, *channel, y + lane * lane_height, lane_height, canvas);
                 }
 
-                ChannelMode::Both => {
-                    self.draw_channel(state, entity, &self.waveform_left, SAMPLES_PER_PIXEL.len(), 0, navigator_posy, navigator_height/2.0, canvas);
-                    self.draw_channel(state, entity, &self.waveform_right, SAMPLES_PER_PIXEL.len(), 0, navigator_posy + navigator_height/2.0, navigator_height/2.0, canvas);
-                    self.draw_channel(state, entity, &self.waveform_left, self.zoom_level, start, y, h / 2.0, canvas);
-                    self.draw_channel(
-                        state,
-                        entity,
-                        &self.waveform_right,
-                        self.zoom_level,
-                        start,
-                        y + h / 2.0,
-                        h / 2.0,
-                        canvas,
-                    );
-
+                // Separate each lane from the one above
+                if lane > 0.0 {
+                    let mut path = Path::new();
+                    path.move_to(x, (y + lane * lane_height).floor());
+                    path.line_to(x + w, (y + lane * lane_height).floor());
+                    path.move_to(navigator_posx, (navigator_posy + lane * navigator_lane_height).floor());
+                    path.line_to(navigator_posx + navigator_width, (navigator_posy + lane * navigator_lane_height).floor());
+                    let mut paint = Paint::color(femtovg::Color::rgba(60, 60, 60, 255));
+                    paint.set_line_width(1.0);
+                    paint.set_anti_alias(false);
+                    canvas.stroke_path(&mut path, paint);
                 }
             }
 
         }
+
+        if self.show_loudness_curve {
+            self.draw_loudness_curve(state, canvas);
+        }
+
+        self.draw_issue_markers(state, canvas);
+        self.draw_markers(state, canvas);
         
         // Draw Navigator Window
         let window_posx = (self.start as f32 / self.num_of_samples as f32) * navigator_width;
@@ -1614,7 +4295,7 @@ impl EventHandler for AppWidget {
         // Draw playhead
         let playhead = self.controller.playhead() as f64;
 
-        let pixels_per_sample = 1.0 / self.samples_per_pixel as f32;
+        let pixels_per_sample = 1.0 / self.samples_per_pixel;
         let playheadx = x + pixels_per_sample * (playhead as f32 - self.start as f32);
 
         let mut path = Path::new();
@@ -1699,6 +4380,12 @@ impl EventHandler for AppWidget {
         //     }
         // }
 
+        if let Some(end) = self.play_until {
+            if self.is_playing && self.playhead >= end {
+                state.insert_event(Event::new(AppEvent::Stop).target(entity));
+            }
+        }
+
         if self.should_loop {
             if self.playhead > loop_end 
             {
@@ -1732,9 +4419,9 @@ impl EventHandler for AppWidget {
             //if self.playhead % self.samples_per_pixel == 0 {
                 //println!("playhead: {}", playhead);
                 let playhead_pos_pixel = w / 2.0;
-                let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel as f32) as i32;
+                let offset = self.playhead as i32 - (playhead_pos_pixel * self.samples_per_pixel) as i32;
                 let total_samples = (state.data.get_width(entity)
-                                    * self.samples_per_pixel as f32) as usize;
+                                    * self.samples_per_pixel) as usize;
 
                 let mut new_start = 0;
                 let mut new_end = total_samples;
@@ -1750,8 +4437,13 @@ impl EventHandler for AppWidget {
 
         }
 
+        if self.show_spectrum {
+            if let Some(file) = self.controller.file.clone() {
+                self.draw_spectrum(state, &file, canvas);
+            }
+        }
+
 
-        //println!("amount: {}", self.waveform_left.data.len() * 2);
     }
 }
 
@@ -1781,8 +4473,8 @@ impl EventHandler for ZoomDropdown {
 
         if let Some(app_event) = event.message.downcast::<AppEvent>() {
             match app_event {
-                AppEvent::SetZoomLevel(val,_) => {
-                    self.dropdown.label.set_text(state, &(((441.0 / SAMPLES_PER_PIXEL[*val] as f32)).to_string() + "X"));
+                AppEvent::SetZoom(val,_) => {
+                    self.dropdown.label.set_text(state, &zoom_label(*val));
                 }
 
                 _=> {}
@@ -0,0 +1,612 @@
+//! Saving and importing markers. Markers and regions are written into a copy of a
+//! WAV file as `cue ` and `LIST`/`adtl` chunks, or to a sidecar file in one of these
+//! formats:
+//!
+//! - JSON: `{"sample_rate": 48000, "markers": [{"name": "Intro", "position": 0,
+//!   "length": 96000, "color": "red"}]}`, positions and lengths in samples, with a
+//!   `null` length for a marker. Positions are rescaled when imported into a file
+//!   with a different sample rate
+//! - CSV: a `name,start,end,color` header and a row for each marker, times in
+//!   seconds, with an empty end for a marker
+//! - Audacity label track (`.txt`): a line for each label with its start and end in
+//!   seconds and its text, separated by tabs. A marker starts and ends at the same time
+//!
+//! Marker colours aren't stored in WAV files or Audacity labels, so imported markers
+//! from those are given the default colours.
+
+use crate::decoder::wav;
+use crate::error::AppError;
+use crate::file_info::{self, FileInfo};
+use crate::markers::{Marker, MarkerColor, Markers};
+use crate::utils::json_string;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkerFormat {
+    Wav,
+    Json,
+    Csv,
+    Audacity,
+}
+
+impl MarkerFormat {
+    pub const ALL: [MarkerFormat; 4] = [
+        MarkerFormat::Wav,
+        MarkerFormat::Json,
+        MarkerFormat::Csv,
+        MarkerFormat::Audacity,
+    ];
+
+    /// the format of a file from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|format| format.extensions().contains(&extension.as_str()))
+    }
+
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            MarkerFormat::Wav => &["wav", "wave"],
+            MarkerFormat::Json => &["json"],
+            MarkerFormat::Csv => &["csv"],
+            MarkerFormat::Audacity => &["txt"],
+        }
+    }
+
+    /// a description for file dialogs
+    pub fn description(&self) -> &'static str {
+        match self {
+            MarkerFormat::Wav => "WAV file with markers",
+            MarkerFormat::Json => "JSON markers",
+            MarkerFormat::Csv => "CSV markers",
+            MarkerFormat::Audacity => "Audacity labels",
+        }
+    }
+}
+
+/// save markers to `path`. A WAV is saved as a copy of the WAV file `source` with the
+/// markers replacing any it had, and `source` can't be overwritten
+pub fn save(path: &str, format: MarkerFormat, markers: &Markers, source: &str, sample_rate: f64) -> io::Result<()> {
+    if format == MarkerFormat::Wav {
+        return write_wav(source, path, markers);
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        MarkerFormat::Json => write_json(&mut writer, markers, sample_rate)?,
+        MarkerFormat::Csv => write_csv(&mut writer, markers, sample_rate)?,
+        MarkerFormat::Audacity => write_audacity(&mut writer, markers, sample_rate)?,
+        MarkerFormat::Wav => unreachable!(),
+    }
+    writer.flush()
+}
+
+/// read the markers of a file, with positions in samples at `sample_rate`
+pub fn read(path: &str, format: MarkerFormat, sample_rate: f64) -> io::Result<Vec<Marker>> {
+    if format == MarkerFormat::Wav {
+        return read_wav(path, sample_rate);
+    }
+
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    match format {
+        MarkerFormat::Json => read_json(&text, sample_rate),
+        MarkerFormat::Csv => read_csv(&text, sample_rate),
+        MarkerFormat::Audacity => read_audacity(&text, sample_rate),
+        MarkerFormat::Wav => unreachable!(),
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn from_app_error(err: AppError) -> io::Error {
+    invalid_data(err.to_string())
+}
+
+// A marker read from a file, with the default name and colour of its position in the file
+// when it doesn't have them
+fn new_marker(idx: usize, name: &str, position: usize, length: Option<usize>, color: Option<MarkerColor>) -> Marker {
+    let length = length.filter(|length| *length > 0);
+    let name = if name.trim().is_empty() {
+        let kind = if length.is_some() { "Region" } else { "Marker" };
+        format!("{} {}", kind, idx + 1)
+    } else {
+        name.trim().to_string()
+    };
+
+    Marker {
+        id: 0,
+        name,
+        position,
+        length,
+        color: color.unwrap_or(MarkerColor::ALL[idx % MarkerColor::ALL.len()]),
+    }
+}
+
+// A position or length in samples from a value read from a file
+fn to_samples(value: f64) -> io::Result<usize> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(value.round() as usize)
+    } else {
+        Err(invalid_data(format!("invalid marker position {}", value)))
+    }
+}
+
+fn parse_seconds(text: &str) -> io::Result<f64> {
+    text.trim()
+        .parse()
+        .map_err(|_| invalid_data(format!("invalid time '{}'", text.trim())))
+}
+
+// WAV
+
+fn write_wav(source: &str, dest: &str, markers: &Markers) -> io::Result<()> {
+    // The source is read while the copy is written, and may be memory-mapped
+    if let (Ok(source_path), Ok(dest_path)) = (fs::canonicalize(source), fs::canonicalize(dest)) {
+        if source_path == dest_path {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "markers can't be saved over the open file, choose a new file name",
+            ));
+        }
+    }
+
+    let mut reader = BufReader::new(File::open(source)?);
+    let chunks = wav::read_chunks(&mut reader).map_err(from_app_error)?;
+
+    let mut writer = BufWriter::new(File::create(dest)?);
+    writer.write_all(b"RIFF\0\0\0\0WAVE")?;
+    let mut riff_size = 4;
+
+    // Copy everything but the old markers
+    for chunk in chunks.iter() {
+        if &chunk.id == b"cue " || (&chunk.id == b"LIST" && is_adtl(&mut reader, chunk)?) {
+            continue;
+        }
+
+        writer.write_all(&chunk.id)?;
+        writer.write_all(&(chunk.size as u32).to_le_bytes())?;
+        reader.seek(SeekFrom::Start(chunk.offset))?;
+        if io::copy(&mut Read::take(&mut reader, chunk.size), &mut writer)? < chunk.size {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "source file is truncated"));
+        }
+        if chunk.size & 1 == 1 {
+            writer.write_all(&[0])?;
+        }
+        riff_size += 8 + chunk.size + (chunk.size & 1);
+    }
+
+    if !markers.is_empty() {
+        let (cue, adtl) = marker_chunks(markers)?;
+        riff_size += write_chunk(&mut writer, b"cue ", &cue)?;
+        riff_size += write_chunk(&mut writer, b"LIST", &adtl)?;
+    }
+
+    let riff_size = u32::try_from(riff_size)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "file is too large for WAV"))?;
+    writer.seek(SeekFrom::Start(4))?;
+    writer.write_all(&riff_size.to_le_bytes())?;
+    writer.flush()
+}
+
+/// add markers to the end of a WAV file that has none
+pub fn append_markers(path: &str, markers: &Markers) -> io::Result<()> {
+    if markers.is_empty() {
+        return Ok(());
+    }
+    let (cue, adtl) = marker_chunks(markers)?;
+    wav::append_chunk(path, b"cue ", &cue)?;
+    wav::append_chunk(path, b"LIST", &adtl)
+}
+
+/// true for a `LIST` chunk holding `adtl` labels
+pub fn is_adtl<R: Read + Seek>(reader: &mut R, chunk: &wav::ChunkInfo) -> io::Result<bool> {
+    if chunk.size < 4 {
+        return Ok(false);
+    }
+    let mut list_type = [0u8; 4];
+    reader.seek(SeekFrom::Start(chunk.offset))?;
+    reader.read_exact(&mut list_type)?;
+    Ok(&list_type == b"adtl")
+}
+
+// Write a chunk padded to an even length, returning the number of bytes written
+fn write_chunk<W: Write>(writer: &mut W, id: &[u8; 4], body: &[u8]) -> io::Result<u64> {
+    let size = u32::try_from(body.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "chunk is too large for WAV"))?;
+    writer.write_all(id)?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.write_all(body)?;
+    if body.len() & 1 == 1 {
+        writer.write_all(&[0])?;
+    }
+    Ok(8 + body.len() as u64 + (body.len() as u64 & 1))
+}
+
+// The bodies of the `cue ` chunk and the `LIST`/`adtl` chunk holding the names of the
+// markers and the lengths of the regions. Cue points are numbered from 1
+fn marker_chunks(markers: &Markers) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let to_u32 = |value: usize| {
+        u32::try_from(value).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "marker is past the end of a WAV file"))
+    };
+
+    let mut cue = (markers.len() as u32).to_le_bytes().to_vec();
+    let mut adtl = b"adtl".to_vec();
+    for (idx, marker) in markers.iter().enumerate() {
+        let cue_id = (idx as u32 + 1).to_le_bytes();
+        let position = to_u32(marker.position)?.to_le_bytes();
+
+        // id, position, data chunk id, chunk start, block start, sample offset
+        cue.extend_from_slice(&cue_id);
+        cue.extend_from_slice(&position);
+        cue.extend_from_slice(b"data");
+        cue.extend_from_slice(&[0; 8]);
+        cue.extend_from_slice(&position);
+
+        let mut label = cue_id.to_vec();
+        label.extend_from_slice(marker.name.as_bytes());
+        label.push(0);
+        write_chunk(&mut adtl, b"labl", &label)?;
+
+        if let Some(length) = marker.length {
+            // id, length, purpose, then country, language, dialect and code page left unset
+            let mut text = cue_id.to_vec();
+            text.extend_from_slice(&to_u32(length)?.to_le_bytes());
+            text.extend_from_slice(b"rgn ");
+            text.extend_from_slice(&[0; 8]);
+            write_chunk(&mut adtl, b"ltxt", &text)?;
+        }
+    }
+
+    Ok((cue, adtl))
+}
+
+fn read_wav(path: &str, sample_rate: f64) -> io::Result<Vec<Marker>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let chunks = wav::read_chunks(&mut reader).map_err(from_app_error)?;
+    let read_chunk = |reader: &mut BufReader<File>, chunk| wav::read_chunk(reader, chunk).map_err(from_app_error);
+
+    let file_rate = match wav::find_chunk(&chunks, b"fmt ") {
+        Some(chunk) => wav::WavFormat::parse(&read_chunk(&mut reader, chunk)?).map_err(from_app_error)?.sample_rate,
+        None => return Err(invalid_data("missing fmt chunk".to_string())),
+    };
+    let scale = sample_rate / file_rate.max(1) as f64;
+
+    let mut info = FileInfo::default();
+    if let Some(chunk) = wav::find_chunk(&chunks, b"cue ") {
+        info.cues = file_info::parse_cue(&read_chunk(&mut reader, chunk)?);
+    }
+    for chunk in chunks.iter().filter(|chunk| &chunk.id == b"LIST") {
+        file_info::parse_list(&read_chunk(&mut reader, chunk)?, &mut info);
+    }
+
+    info.cues
+        .iter()
+        .enumerate()
+        .map(|(idx, cue)| {
+            let position = to_samples(cue.position as f64 * scale)?;
+            let length = cue.length.map(|length| to_samples(length as f64 * scale)).transpose()?;
+            Ok(new_marker(idx, cue.label.as_deref().unwrap_or(""), position, length, None))
+        })
+        .collect()
+}
+
+// JSON
+
+fn write_json<W: Write>(writer: &mut W, markers: &Markers, sample_rate: f64) -> io::Result<()> {
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"sample_rate\": {},", sample_rate)?;
+    writeln!(writer, "  \"markers\": [")?;
+    for (idx, marker) in markers.iter().enumerate() {
+        let length = marker.length.map_or("null".to_string(), |length| length.to_string());
+        let separator = if idx + 1 < markers.len() { "," } else { "" };
+        writeln!(
+            writer,
+            "    {{\"name\": {}, \"position\": {}, \"length\": {}, \"color\": \"{}\"}}{}",
+            json_string(&marker.name),
+            marker.position,
+            length,
+            marker.color.name(),
+            separator
+        )?;
+    }
+    writeln!(writer, "  ]")?;
+    writeln!(writer, "}}")
+}
+
+// Markers are read from an object with a `markers` array, or from a bare array
+fn read_json(text: &str, sample_rate: f64) -> io::Result<Vec<Marker>> {
+    let json: Value = serde_json::from_str(text)?;
+    let (file_rate, items) = match &json {
+        Value::Object(_) => (json.get("sample_rate").and_then(Value::as_f64), json.get("markers")),
+        Value::Array(_) => (None, Some(&json)),
+        _ => (None, None),
+    };
+    let items = match items {
+        Some(Value::Array(items)) => items,
+        _ => return Err(invalid_data("no markers array".to_string())),
+    };
+    let scale = file_rate.filter(|rate| *rate > 0.0).map_or(1.0, |rate| sample_rate / rate);
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(idx, item)| {
+            let position = item
+                .get("position")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| invalid_data(format!("marker {} has no position", idx + 1)))?;
+            let length = item.get("length").and_then(Value::as_f64);
+            let name = item.get("name").and_then(Value::as_str).unwrap_or("");
+            let color = item.get("color").and_then(Value::as_str).and_then(MarkerColor::from_name);
+
+            Ok(new_marker(
+                idx,
+                name,
+                to_samples(position * scale)?,
+                length.map(|length| to_samples(length * scale)).transpose()?,
+                color,
+            ))
+        })
+        .collect()
+}
+
+// CSV
+
+fn write_csv<W: Write>(writer: &mut W, markers: &Markers, sample_rate: f64) -> io::Result<()> {
+    writeln!(writer, "name,start,end,color")?;
+    for marker in markers.iter() {
+        let end = match marker.length {
+            Some(_) => format!("{:.6}", marker.end() as f64 / sample_rate),
+            None => String::new(),
+        };
+        writeln!(
+            writer,
+            "{},{:.6},{},{}",
+            csv_field(&marker.name),
+            marker.position as f64 / sample_rate,
+            end,
+            marker.color.name()
+        )?;
+    }
+    Ok(())
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// The records of a CSV file. Quoted fields may hold commas, doubled quotes and line breaks
+fn csv_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    // Skip blank lines
+    records.retain(|record| record.len() > 1 || !record[0].trim().is_empty());
+    records
+}
+
+// Columns are found by name from the header, so they can be in any order
+fn read_csv(text: &str, sample_rate: f64) -> io::Result<Vec<Marker>> {
+    let mut records = csv_records(text).into_iter();
+    let header = records.next().unwrap_or_default();
+    let column = |name: &str| header.iter().position(|title| title.trim().eq_ignore_ascii_case(name));
+
+    let start_column = column("start").ok_or_else(|| invalid_data("no start column".to_string()))?;
+    let (name_column, end_column, color_column) = (column("name"), column("end"), column("color"));
+
+    records
+        .enumerate()
+        .map(|(idx, record)| {
+            let field = |column: Option<usize>| column.and_then(|column| record.get(column)).map_or("", |field| field.trim());
+
+            let start = parse_seconds(field(Some(start_column)))?;
+            let end = match field(end_column) {
+                "" => None,
+                end => Some(parse_seconds(end)?),
+            };
+            if end.map_or(false, |end| end < start) {
+                return Err(invalid_data(format!("marker {} ends before it starts", idx + 1)));
+            }
+
+            let position = to_samples(start * sample_rate)?;
+            let length = end.map(|end| to_samples((end - start) * sample_rate)).transpose()?;
+            Ok(new_marker(idx, field(name_column), position, length, MarkerColor::from_name(field(color_column))))
+        })
+        .collect()
+}
+
+// Audacity labels
+
+fn write_audacity<W: Write>(writer: &mut W, markers: &Markers, sample_rate: f64) -> io::Result<()> {
+    for marker in markers.iter() {
+        // Tabs and line breaks would split the label
+        let label: String = marker
+            .name
+            .chars()
+            .map(|c| if c == '\t' || c == '\n' || c == '\r' { ' ' } else { c })
+            .collect();
+        writeln!(
+            writer,
+            "{:.6}\t{:.6}\t{}",
+            marker.position as f64 / sample_rate,
+            marker.end() as f64 / sample_rate,
+            label
+        )?;
+    }
+    Ok(())
+}
+
+// Lines starting with a backslash hold the frequency range of spectral selection labels,
+// which is ignored
+fn read_audacity(text: &str, sample_rate: f64) -> io::Result<Vec<Marker>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('\\'))
+        .enumerate()
+        .map(|(idx, line)| {
+            let mut fields = line.splitn(3, '\t');
+            let start = parse_seconds(fields.next().unwrap_or(""))?;
+            let end = match fields.next() {
+                Some(end) => parse_seconds(end)?.max(start),
+                None => start,
+            };
+            let label = fields.next().unwrap_or("");
+
+            let position = to_samples(start * sample_rate)?;
+            let length = to_samples((end - start) * sample_rate)?;
+            Ok(new_marker(idx, label, position, Some(length), None))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, write_wav};
+    use hound::{SampleFormat, WavSpec};
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// Markers with names that need quoting in CSV, and a region
+    fn markers() -> Markers {
+        let mut markers = Markers::new();
+        for (name, position, length, color) in [
+            ("Intro, part 1", 0, None, MarkerColor::Red),
+            ("Say \"hi\"", 48000, Some(96000), MarkerColor::Blue),
+            ("Outro", 12345, None, MarkerColor::Green),
+        ]
+        .iter()
+        {
+            markers.insert(Marker {
+                id: 0,
+                name: name.to_string(),
+                position: *position,
+                length: *length,
+                color: *color,
+            });
+        }
+        markers
+    }
+
+    /// Save markers in a format and read them back
+    fn round_trip(name: &str, format: MarkerFormat, source: &str) -> Vec<Marker> {
+        let path = temp_path(&format!("markers_{}", name));
+        let path = path.to_str().unwrap();
+        save(path, format, &markers(), source, SAMPLE_RATE).unwrap();
+        let read = read(path, format, SAMPLE_RATE);
+        fs::remove_file(path).unwrap();
+        read.unwrap()
+    }
+
+    fn assert_same(read: &[Marker], with_colors: bool) {
+        let markers = markers();
+        assert_eq!(read.len(), markers.len());
+        for (read, marker) in read.iter().zip(markers.iter()) {
+            assert_eq!(read.name, marker.name);
+            assert_eq!(read.position, marker.position);
+            assert_eq!(read.length, marker.length);
+            if with_colors {
+                assert_eq!(read.color, marker.color);
+            }
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        assert_same(&round_trip("round_trip.json", MarkerFormat::Json, ""), true);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        assert_same(&round_trip("round_trip.csv", MarkerFormat::Csv, ""), true);
+    }
+
+    #[test]
+    fn audacity_round_trip() {
+        assert_same(&round_trip("round_trip.txt", MarkerFormat::Audacity, ""), false);
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let source = temp_path("markers_source.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE as u32,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        write_wav(&source, spec, &[0i16; 200000]);
+
+        let read = round_trip("round_trip.wav", MarkerFormat::Wav, source.to_str().unwrap());
+        fs::remove_file(&source).unwrap();
+        assert_same(&read, false);
+    }
+
+    #[test]
+    fn json_rescaled_to_sample_rate() {
+        let text = r#"{"sample_rate": 96000, "markers": [{"name": "A", "position": 96000, "length": null}]}"#;
+        let read = read_json(text, SAMPLE_RATE).unwrap();
+        assert_eq!(read[0].position, 48000);
+        assert_eq!(read[0].length, None);
+    }
+
+    #[test]
+    fn json_nesting_limit() {
+        // Nested values the markers don't use are skipped over
+        let nested = format!("{}{}", "[".repeat(32), "]".repeat(32));
+        let text = format!(r#"{{"extra": {}, "markers": [{{"position": 1}}]}}"#, nested);
+        assert_eq!(read_json(&text, SAMPLE_RATE).unwrap()[0].position, 1);
+        // Deep enough to overflow the stack without a limit
+        assert!(read_json(&"[".repeat(1_000_000), SAMPLE_RATE).is_err());
+        assert!(read_json(&"{\"a\":".repeat(1_000_000), SAMPLE_RATE).is_err());
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let records = csv_records("name,start\r\n\"a, \"\"b\"\"\",1.5\n\"two\nlines\",2\n\n");
+        assert_eq!(
+            records,
+            vec![
+                vec!["name".to_string(), "start".to_string()],
+                vec!["a, \"b\"".to_string(), "1.5".to_string()],
+                vec!["two\nlines".to_string(), "2".to_string()],
+            ]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Named markers and regions placed on the timeline of a file. Markers are
+//! loaded from the WAV `cue ` and `LIST`/`adtl` chunks when a file has them, and
+//! can be saved and imported with `marker_files`.
+
+use crate::file_info::CuePoint;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkerColor {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl MarkerColor {
+    pub const ALL: [MarkerColor; 6] = [
+        MarkerColor::Red,
+        MarkerColor::Orange,
+        MarkerColor::Yellow,
+        MarkerColor::Green,
+        MarkerColor::Blue,
+        MarkerColor::Purple,
+    ];
+
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match self {
+            MarkerColor::Red => (230, 60, 60),
+            MarkerColor::Orange => (255, 140, 40),
+            MarkerColor::Yellow => (240, 210, 60),
+            MarkerColor::Green => (70, 200, 90),
+            MarkerColor::Blue => (70, 140, 255),
+            MarkerColor::Purple => (190, 90, 230),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarkerColor::Red => "red",
+            MarkerColor::Orange => "orange",
+            MarkerColor::Yellow => "yellow",
+            MarkerColor::Green => "green",
+            MarkerColor::Blue => "blue",
+            MarkerColor::Purple => "purple",
+        }
+    }
+
+    /// the colour with a name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|color| color.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// A marker at a position, or a region when it has a length
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub id: u32,
+    pub name: String,
+    /// position in samples from the start of the file
+    pub position: usize,
+    /// length in samples, for regions
+    pub length: Option<usize>,
+    pub color: MarkerColor,
+}
+
+impl Marker {
+    pub fn is_region(&self) -> bool {
+        self.length.is_some()
+    }
+
+    /// the end of a region, or the position of a marker
+    pub fn end(&self) -> usize {
+        self.position + self.length.unwrap_or(0)
+    }
+}
+
+/// The markers of a file, kept in order of position
+#[derive(Debug, Clone, Default)]
+pub struct Markers {
+    markers: Vec<Marker>,
+    next_id: u32,
+}
+
+impl Markers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// create markers from the cue points of a WAV file, with regions for those with a length
+    pub fn from_cues(cues: &[CuePoint]) -> Self {
+        let mut markers = Self::new();
+        for cue in cues {
+            let length = cue.length.filter(|length| *length > 0).map(|length| length as usize);
+            let id = markers.add(cue.position as usize, length);
+            if let Some(label) = cue.label.as_ref().filter(|label| !label.is_empty()) {
+                markers.rename(id, label);
+            }
+        }
+        markers
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Marker> {
+        self.markers.iter()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Marker> {
+        self.markers.iter().find(|marker| marker.id == id)
+    }
+
+    /// add a marker, or a region if it has a length, with a default name and colour.
+    /// Returns its id
+    pub fn add(&mut self, position: usize, length: Option<usize>) -> u32 {
+        let id = self.next_id;
+        let kind = if length.is_some() { "Region" } else { "Marker" };
+        self.insert(Marker {
+            id,
+            name: format!("{} {}", kind, id + 1),
+            position,
+            length,
+            color: MarkerColor::ALL[id as usize % MarkerColor::ALL.len()],
+        })
+    }
+
+    /// add a marker read from a file, giving it a new id. Returns the id
+    pub fn insert(&mut self, mut marker: Marker) -> u32 {
+        marker.id = self.next_id;
+        self.next_id += 1;
+
+        let id = marker.id;
+        self.markers.push(marker);
+        self.sort();
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    pub fn rename(&mut self, id: u32, name: &str) {
+        if let Some(marker) = self.get_mut(id) {
+            marker.name = name.to_string();
+        }
+    }
+
+    /// move a marker, or the start of a region keeping its length
+    pub fn move_to(&mut self, id: u32, position: usize) {
+        if let Some(marker) = self.get_mut(id) {
+            marker.position = position;
+        }
+        self.sort();
+    }
+
+    pub fn set_color(&mut self, id: u32, color: MarkerColor) {
+        if let Some(marker) = self.get_mut(id) {
+            marker.color = color;
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.markers.retain(|marker| marker.id != id);
+    }
+
+    /// move the markers to follow an edit that replaced the samples from `start` to `end`
+    /// with `len` samples. When the length changes, markers inside the range are removed,
+    /// those after it move, and regions that overlap it keep the parts outside it
+    pub fn replace_range(&mut self, start: usize, end: usize, len: usize) {
+        if end - start == len {
+            return;
+        }
+
+        self.markers
+            .retain(|marker| marker.position < start || marker.position >= end || marker.end() > end);
+        let new_end = start + len;
+        for marker in self.markers.iter_mut() {
+            let marker_end = marker.end();
+            if marker.position >= end {
+                marker.position = marker.position - end + new_end;
+            } else if marker.position >= start {
+                // A region starting inside the range keeps the part after it
+                marker.position = new_end;
+                marker.length = Some(marker_end - end);
+            } else if marker.length.is_some() && marker_end > start {
+                let after = if marker_end > end { len + marker_end - end } else { 0 };
+                marker.length = Some(start - marker.position + after).filter(|length| *length > 0);
+            }
+        }
+        self.sort();
+    }
+
+    /// the first marker after a position
+    pub fn next(&self, position: usize) -> Option<&Marker> {
+        self.markers.iter().find(|marker| marker.position > position)
+    }
+
+    /// the last marker before a position
+    pub fn previous(&self, position: usize) -> Option<&Marker> {
+        self.markers.iter().rev().find(|marker| marker.position < position)
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Marker> {
+        self.markers.iter_mut().find(|marker| marker.id == id)
+    }
+
+    // Markers at the same position stay in the order they were added
+    fn sort(&mut self) {
+        self.markers.sort_by_key(|marker| marker.position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Markers from (position, length) pairs
+    fn markers(spans: &[(usize, Option<usize>)]) -> Markers {
+        let mut markers = Markers::new();
+        for (position, length) in spans {
+            markers.add(*position, *length);
+        }
+        markers
+    }
+
+    /// The (name, position, length) of each marker, in order
+    fn spans(markers: &Markers) -> Vec<(String, usize, Option<usize>)> {
+        markers
+            .iter()
+            .map(|marker| (marker.name.clone(), marker.position, marker.length))
+            .collect()
+    }
+
+    fn span(name: &str, position: usize, length: Option<usize>) -> (String, usize, Option<usize>) {
+        (name.to_string(), position, length)
+    }
+
+    #[test]
+    fn cut() {
+        let mut markers = markers(&[
+            (50, None),
+            (150, None),
+            (300, None),
+            (40, Some(20)),
+            (80, Some(100)),
+            (120, Some(30)),
+            (150, Some(100)),
+        ]);
+        // Cut from 100 to 200
+        markers.replace_range(100, 200, 0);
+        assert_eq!(
+            spans(&markers),
+            [
+                span("Region 4", 40, Some(20)),
+                span("Marker 1", 50, None),
+                span("Region 5", 80, Some(20)),
+                span("Region 7", 100, Some(50)),
+                span("Marker 3", 200, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let mut markers = markers(&[(50, None), (100, None), (80, Some(40)), (100, Some(10))]);
+        // Insert 30 samples at 100
+        markers.replace_range(100, 100, 30);
+        assert_eq!(
+            spans(&markers),
+            [
+                span("Marker 1", 50, None),
+                span("Region 3", 80, Some(70)),
+                span("Marker 2", 130, None),
+                span("Region 4", 130, Some(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_with_the_same_length() {
+        let mut markers = markers(&[(150, None), (80, Some(100))]);
+        markers.replace_range(100, 200, 100);
+        assert_eq!(
+            spans(&markers),
+            [span("Region 2", 80, Some(100)), span("Marker 1", 150, None)]
+        );
+    }
+
+    #[test]
+    fn crop() {
+        let mut markers = markers(&[(50, None), (150, None), (350, None), (50, Some(100)), (250, Some(100))]);
+        // Keep 100 to 300, as cropping does, cutting the end first
+        let num_samples = 400;
+        markers.replace_range(300, num_samples, 0);
+        markers.replace_range(0, 100, 0);
+        assert_eq!(
+            spans(&markers),
+            [
+                span("Region 4", 0, Some(50)),
+                span("Marker 2", 50, None),
+                span("Region 5", 150, Some(50)),
+            ]
+        );
+    }
+}
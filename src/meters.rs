@@ -0,0 +1,169 @@
+//! Output level meters. The audio thread measures each buffer it plays and sends
+//! the levels through a ring buffer to the UI, which smooths them for display.
+
+use std::time::Instant;
+
+/// Number of output channels that can be metered. Any further outputs are played but not shown
+pub const MAX_METER_CHANNELS: usize = 32;
+
+/// Level at the bottom of the meter scale, in dB
+pub const METER_MIN_DB: f32 = -60.0;
+
+// How quickly the peak bar falls, and how long the peak hold stays before falling
+const PEAK_FALL_DB_PER_SECOND: f32 = 20.0;
+const PEAK_HOLD_SECONDS: f32 = 2.0;
+
+// Time constant of the rms average
+const RMS_SECONDS: f32 = 0.3;
+
+// Samples at or above this level light the clip indicator
+const CLIP_LEVEL: f32 = 1.0;
+
+/// The levels of one buffer of output, measured on the audio thread.
+/// Fixed size so that sending it doesn't allocate
+#[derive(Clone, Copy)]
+pub struct MeterFrame {
+    pub num_channels: usize,
+    pub num_samples: usize,
+    pub peaks: [f32; MAX_METER_CHANNELS],
+    pub sums_of_squares: [f32; MAX_METER_CHANNELS],
+}
+
+impl MeterFrame {
+    pub fn new(num_channels: usize, num_samples: usize) -> Self {
+        Self {
+            num_channels: num_channels.min(MAX_METER_CHANNELS),
+            num_samples,
+            peaks: [0.0; MAX_METER_CHANNELS],
+            sums_of_squares: [0.0; MAX_METER_CHANNELS],
+        }
+    }
+
+    /// measure the output samples of a channel
+    pub fn measure(&mut self, channel: usize, samples: &[f32]) {
+        if channel >= self.num_channels {
+            return;
+        }
+        for sample in samples {
+            self.peaks[channel] = self.peaks[channel].max(sample.abs());
+            self.sums_of_squares[channel] += sample * sample;
+        }
+    }
+}
+
+/// The displayed levels of a channel, in dB
+pub struct ChannelMeter {
+    pub peak: f32,
+    pub rms: f32,
+    pub hold: f32,
+    pub clipped: bool,
+    // Seconds left before the peak hold starts to fall
+    hold_time: f32,
+    mean_square: f32,
+    // Measurements received since the last update
+    pending_peak: f32,
+    pending_sum: f32,
+    pending_samples: usize,
+}
+
+impl ChannelMeter {
+    fn new() -> Self {
+        Self {
+            peak: METER_MIN_DB,
+            rms: METER_MIN_DB,
+            hold: METER_MIN_DB,
+            clipped: false,
+            hold_time: 0.0,
+            mean_square: 0.0,
+            pending_peak: 0.0,
+            pending_sum: 0.0,
+            pending_samples: 0,
+        }
+    }
+}
+
+/// Smooths the measurements from the audio thread into peak, rms, peak hold and
+/// clip indicators for each channel
+pub struct Meters {
+    pub channels: Vec<ChannelMeter>,
+    last_update: Instant,
+}
+
+impl Meters {
+    pub fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            last_update: Instant::now(),
+        }
+    }
+
+    /// add the levels of a buffer played since the last update
+    pub fn push(&mut self, frame: &MeterFrame) {
+        if self.channels.len() != frame.num_channels {
+            self.channels.resize_with(frame.num_channels, ChannelMeter::new);
+        }
+
+        for (channel, meter) in self.channels.iter_mut().enumerate() {
+            let peak = frame.peaks[channel];
+            meter.pending_peak = meter.pending_peak.max(peak);
+            meter.pending_sum += frame.sums_of_squares[channel];
+            meter.pending_samples += frame.num_samples;
+            if peak >= CLIP_LEVEL {
+                meter.clipped = true;
+            }
+        }
+    }
+
+    /// move the meters on to the current time, letting them fall when nothing is playing
+    pub fn update(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        let rms_decay = (-elapsed / RMS_SECONDS).exp();
+        for meter in self.channels.iter_mut() {
+            let pending_peak = to_db(meter.pending_peak);
+            meter.peak = (meter.peak - PEAK_FALL_DB_PER_SECOND * elapsed).max(pending_peak).max(METER_MIN_DB);
+
+            let mean_square = if meter.pending_samples > 0 {
+                meter.pending_sum / meter.pending_samples as f32
+            } else {
+                0.0
+            };
+            meter.mean_square = meter.mean_square * rms_decay + mean_square * (1.0 - rms_decay);
+            meter.rms = to_db(meter.mean_square.sqrt());
+
+            if pending_peak >= meter.hold {
+                meter.hold = pending_peak;
+                meter.hold_time = PEAK_HOLD_SECONDS;
+            } else if meter.hold_time > 0.0 {
+                meter.hold_time -= elapsed;
+            } else {
+                meter.hold = (meter.hold - PEAK_FALL_DB_PER_SECOND * elapsed).max(METER_MIN_DB);
+            }
+
+            meter.pending_peak = 0.0;
+            meter.pending_sum = 0.0;
+            meter.pending_samples = 0;
+        }
+    }
+
+    /// clear the clip indicators
+    pub fn reset_clips(&mut self) {
+        for meter in self.channels.iter_mut() {
+            meter.clipped = false;
+        }
+    }
+}
+
+/// the position of a level on the meter scale, from 0 at `METER_MIN_DB` to 1 at 0 dB
+pub fn meter_position(db: f32) -> f32 {
+    (1.0 - db / METER_MIN_DB).max(0.0).min(1.0)
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(METER_MIN_DB)
+    } else {
+        METER_MIN_DB
+    }
+}
@@ -0,0 +1,506 @@
+//! Sidecar files holding the waveform peaks of an audio file, so that they don't
+//! have to be rebuilt each time the file is opened. The cache for `take.wav` is
+//! `take.wav.peak`.
+//!
+//! Layout, all integers little-endian:
+//! - header: `MAGIC`, `VERSION` (u32), `BLOCK_SAMPLES` (u32), the number of levels (u32)
+//!   and `SAMPLES_PER_PIXEL` (u32 each), the source file size (u64) and modification
+//!   time (u64 seconds, u32 nanoseconds), the number of channels (u32) and samples (u64)
+//! - one entry per block of `BLOCK_SAMPLES` samples, holding for each channel and level
+//!   the number of peaks (u32) followed by the peaks (min, max, rms as u16)
+//! - an FNV-1a hash (u64) of everything before it
+//!
+//! A cache that doesn't match the source file or the current layout, or that fails
+//! the hash check, is ignored and rebuilt.
+
+use crate::waveform::{Peak, Waveform, BLOCK_SAMPLES, SAMPLES_PER_PIXEL};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 8] = b"WAVPEAKS";
+const VERSION: u32 = 1;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// return the path of the cache file for an audio file
+pub fn cache_path(source_path: &str) -> String {
+    format!("{}.peak", source_path)
+}
+
+// The fields of the header that tie a cache to its source file
+#[derive(PartialEq)]
+struct Header {
+    source_size: u64,
+    source_secs: u64,
+    source_nanos: u32,
+    num_channels: u32,
+    num_samples: u64,
+}
+
+impl Header {
+    fn new(source_path: &str, num_channels: usize, num_samples: usize) -> io::Result<Self> {
+        let metadata = fs::metadata(source_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| io::Error::new(ErrorKind::Other, err))?;
+
+        Ok(Self {
+            source_size: metadata.len(),
+            source_secs: modified.as_secs(),
+            source_nanos: modified.subsec_nanos(),
+            num_channels: num_channels as u32,
+            num_samples: num_samples as u64,
+        })
+    }
+
+    fn write<W: Write>(&self, writer: &mut HashWriter<W>) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u32(VERSION)?;
+        writer.write_u32(BLOCK_SAMPLES as u32)?;
+        writer.write_u32(SAMPLES_PER_PIXEL.len() as u32)?;
+        for samples_per_pixel in SAMPLES_PER_PIXEL.iter() {
+            writer.write_u32(*samples_per_pixel as u32)?;
+        }
+        writer.write_u64(self.source_size)?;
+        writer.write_u64(self.source_secs)?;
+        writer.write_u32(self.source_nanos)?;
+        writer.write_u32(self.num_channels)?;
+        writer.write_u64(self.num_samples)
+    }
+
+    // Returns an InvalidData error if the cache was written with a different layout
+    fn read<R: Read>(reader: &mut HashReader<R>) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || reader.read_u32()? != VERSION {
+            return Err(invalid("not a peak file of this version"));
+        }
+
+        if reader.read_u32()? != BLOCK_SAMPLES as u32
+            || reader.read_u32()? != SAMPLES_PER_PIXEL.len() as u32
+        {
+            return Err(invalid("different zoom levels"));
+        }
+        for samples_per_pixel in SAMPLES_PER_PIXEL.iter() {
+            if reader.read_u32()? != *samples_per_pixel as u32 {
+                return Err(invalid("different zoom levels"));
+            }
+        }
+
+        Ok(Self {
+            source_size: reader.read_u64()?,
+            source_secs: reader.read_u64()?,
+            source_nanos: reader.read_u32()?,
+            num_channels: reader.read_u32()?,
+            num_samples: reader.read_u64()?,
+        })
+    }
+}
+
+/// Writes a cache file one block at a time, as the waveforms are built.
+/// The file is written under a temporary name and only moved into place by
+/// `finish`, so an unfinished cache is never read. Dropping the writer
+/// without finishing deletes the temporary file.
+pub struct PeakCacheWriter {
+    writer: Option<HashWriter<BufWriter<File>>>,
+    temp_path: String,
+    path: String,
+}
+
+impl PeakCacheWriter {
+    /// create the cache file for an audio file and write its header
+    pub fn create(source_path: &str, num_channels: usize, num_samples: usize) -> io::Result<Self> {
+        let header = Header::new(source_path, num_channels, num_samples)?;
+        let path = cache_path(source_path);
+        let temp_path = format!("{}.tmp", path);
+
+        let mut cache = Self {
+            writer: Some(HashWriter::new(BufWriter::new(File::create(&temp_path)?))),
+            temp_path,
+            path,
+        };
+        // If this fails the temporary file is removed when `cache` is dropped
+        if let Some(writer) = cache.writer.as_mut() {
+            header.write(writer)?;
+        }
+        Ok(cache)
+    }
+
+    /// append the peaks of the next block, one waveform per channel
+    pub fn write_block(&mut self, waveforms: &[Waveform]) -> io::Result<()> {
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+
+        for waveform in waveforms {
+            for level in 0..SAMPLES_PER_PIXEL.len() {
+                let peaks = waveform.get_data(level);
+                writer.write_u32(peaks.len() as u32)?;
+                for peak in peaks {
+                    writer.write_u16(peak.0)?;
+                    writer.write_u16(peak.1)?;
+                    writer.write_u16(peak.2)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// write the hash and move the cache file into place
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            let hash = writer.hash;
+            writer.write_u64(hash)?;
+            writer.inner.flush()?;
+            // The file must be closed before it is renamed
+            drop(writer);
+            fs::rename(&self.temp_path, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PeakCacheWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Read the cached waveforms for an audio file, one per channel. Returns `None`
+/// if there is no cache, or if it is stale or corrupt.
+pub fn read(source_path: &str, num_channels: usize, num_samples: usize) -> Option<Vec<Waveform>> {
+    let file = File::open(cache_path(source_path)).ok()?;
+    match read_waveforms(file, source_path, num_channels, num_samples) {
+        Ok(waveforms) => Some(waveforms),
+        Err(err) => {
+            eprintln!("Ignoring peak cache for {}: {}", source_path, err);
+            None
+        }
+    }
+}
+
+fn read_waveforms(
+    file: File,
+    source_path: &str,
+    num_channels: usize,
+    num_samples: usize,
+) -> io::Result<Vec<Waveform>> {
+    let mut reader = HashReader::new(BufReader::new(file));
+
+    let header = Header::read(&mut reader)?;
+    if header != Header::new(source_path, num_channels, num_samples)? {
+        return Err(invalid("the audio file has changed"));
+    }
+
+    let mut waveforms: Vec<Waveform> = (0..num_channels).map(|_| Waveform::new()).collect();
+    let mut position = 0;
+    while position < num_samples {
+        let block_samples = BLOCK_SAMPLES.min(num_samples - position);
+        for waveform in waveforms.iter_mut() {
+            let mut levels = Vec::with_capacity(SAMPLES_PER_PIXEL.len());
+            for samples_per_pixel in SAMPLES_PER_PIXEL.iter() {
+                // Check the count before allocating, so a corrupt count can't exhaust memory
+                let num_peaks = reader.read_u32()? as usize;
+                if num_peaks != (block_samples + samples_per_pixel - 1) / samples_per_pixel {
+                    return Err(invalid("wrong number of peaks"));
+                }
+
+                let mut peaks: Vec<Peak> = Vec::with_capacity(num_peaks);
+                for _ in 0..num_peaks {
+                    peaks.push((reader.read_u16()?, reader.read_u16()?, reader.read_u16()?));
+                }
+                levels.push(peaks);
+            }
+
+            let block = Waveform::from_levels(block_samples, levels)
+                .ok_or_else(|| invalid("wrong number of peaks"))?;
+            waveform.append(&block);
+        }
+        position += block_samples;
+    }
+
+    let hash = reader.hash;
+    if reader.read_u64()? != hash {
+        return Err(invalid("hash mismatch"));
+    }
+
+    Ok(waveforms)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Writes little-endian integers while hashing everything written
+struct HashWriter<W: Write> {
+    inner: W,
+    hash: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hash = fnv1a(self.hash, bytes);
+        self.inner.write_all(bytes)
+    }
+
+    fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+}
+
+// Reads little-endian integers while hashing everything read
+struct HashReader<R: Read> {
+    inner: R,
+    hash: u64,
+}
+
+impl<R: Read> HashReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    fn read_exact(&mut self, bytes: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(bytes)?;
+        self.hash = fnv1a(self.hash, bytes);
+        Ok(())
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0u8; 2];
+        self.read_exact(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        self.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    // Offsets of fields in the header
+    const VERSION_OFFSET: u64 = 8;
+    const BLOCK_SAMPLES_OFFSET: u64 = 12;
+    const HEADER_LEN: u64 = 8 + 4 + 4 + 4 + 4 * SAMPLES_PER_PIXEL.len() as u64 + 8 + 8 + 4 + 4 + 8;
+
+    const NUM_CHANNELS: usize = 2;
+    // One full block and part of another
+    const NUM_SAMPLES: usize = BLOCK_SAMPLES + 1000;
+
+    /// A source file standing in for the audio file, removed with its cache when dropped
+    struct Source {
+        path: PathBuf,
+    }
+
+    impl Source {
+        fn new(name: &str) -> Self {
+            let path = temp_path(&format!("peaks_{}.wav", name));
+            fs::write(&path, b"not really audio").unwrap();
+            Self { path }
+        }
+
+        fn path(&self) -> &str {
+            self.path.to_str().unwrap()
+        }
+
+        fn cache(&self) -> String {
+            cache_path(self.path())
+        }
+    }
+
+    impl Drop for Source {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+            let _ = fs::remove_file(self.cache());
+        }
+    }
+
+    /// Build the waveforms of a ramp on each channel, writing `num_blocks` blocks of them
+    /// to the cache, and return the whole waveforms
+    fn write_cache(source: &Source, num_blocks: usize) -> Vec<Waveform> {
+        let mut cache = PeakCacheWriter::create(source.path(), NUM_CHANNELS, NUM_SAMPLES).unwrap();
+        let mut waveforms: Vec<Waveform> = (0..NUM_CHANNELS).map(|_| Waveform::new()).collect();
+        let mut position = 0;
+        let mut block = 0;
+        while position < NUM_SAMPLES {
+            let len = BLOCK_SAMPLES.min(NUM_SAMPLES - position);
+            let blocks: Vec<Waveform> = (0..NUM_CHANNELS)
+                .map(|channel| {
+                    let samples: Vec<f32> = (position..position + len)
+                        .map(|idx| ((idx * (channel + 1)) % 2000) as f32 / 1000.0 - 1.0)
+                        .collect();
+                    let mut waveform = Waveform::new();
+                    waveform.push(&samples);
+                    waveform.finish();
+                    waveform
+                })
+                .collect();
+            if block < num_blocks {
+                cache.write_block(&blocks).unwrap();
+            }
+            for (waveform, block) in waveforms.iter_mut().zip(blocks.iter()) {
+                waveform.append(block);
+            }
+            position += len;
+            block += 1;
+        }
+        cache.finish().unwrap();
+        waveforms
+    }
+
+    /// The reason a cache is rejected
+    fn read_error(source: &Source) -> String {
+        let file = File::open(source.cache()).unwrap();
+        read_waveforms(file, source.path(), NUM_CHANNELS, NUM_SAMPLES).err().unwrap().to_string()
+    }
+
+    fn patch(path: &str, offset: u64, bytes: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = Source::new("round_trip");
+        let written = write_cache(&source, 2);
+        let read = read(source.path(), NUM_CHANNELS, NUM_SAMPLES).unwrap();
+        assert_eq!(read.len(), NUM_CHANNELS);
+        for (read, written) in read.iter().zip(written.iter()) {
+            assert_eq!(read.num_samples, NUM_SAMPLES);
+            for level in 0..SAMPLES_PER_PIXEL.len() {
+                assert_eq!(read.get_data(level), written.get_data(level));
+            }
+        }
+    }
+
+    #[test]
+    fn changed_source_size() {
+        let source = Source::new("size");
+        write_cache(&source, 2);
+        fs::write(&source.path, b"not really audio, and longer").unwrap();
+        assert_eq!(read_error(&source), "the audio file has changed");
+        assert!(read(source.path(), NUM_CHANNELS, NUM_SAMPLES).is_none());
+    }
+
+    #[test]
+    fn changed_source_time() {
+        let source = Source::new("time");
+        write_cache(&source, 2);
+        let modified = fs::metadata(&source.path).unwrap().modified().unwrap();
+        let file = OpenOptions::new().write(true).open(&source.path).unwrap();
+        file.set_modified(modified + Duration::from_secs(1)).unwrap();
+        assert_eq!(read_error(&source), "the audio file has changed");
+    }
+
+    #[test]
+    fn changed_channels_or_length() {
+        let source = Source::new("length");
+        write_cache(&source, 2);
+        assert!(read(source.path(), NUM_CHANNELS, NUM_SAMPLES - 1).is_none());
+        assert!(read(source.path(), NUM_CHANNELS + 1, NUM_SAMPLES).is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        let source = Source::new("truncated");
+        write_cache(&source, 2);
+        let len = fs::metadata(source.cache()).unwrap().len();
+        OpenOptions::new().write(true).open(source.cache()).unwrap().set_len(len / 2).unwrap();
+        assert!(read(source.path(), NUM_CHANNELS, NUM_SAMPLES).is_none());
+    }
+
+    #[test]
+    fn missing_block() {
+        let source = Source::new("missing_block");
+        write_cache(&source, 1);
+        assert!(read(source.path(), NUM_CHANNELS, NUM_SAMPLES).is_none());
+    }
+
+    #[test]
+    fn flipped_peak_byte() {
+        let source = Source::new("flipped");
+        write_cache(&source, 2);
+        // A byte of the last peak, just before the hash
+        let offset = fs::metadata(source.cache()).unwrap().len() - 9;
+        let mut byte = [0u8];
+        let mut file = File::open(source.cache()).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        patch(&source.cache(), offset, &[byte[0] ^ 0x40]);
+        assert_eq!(read_error(&source), "hash mismatch");
+    }
+
+    #[test]
+    fn wrong_peak_count() {
+        let source = Source::new("count");
+        write_cache(&source, 2);
+        // The count of the first level of the first block
+        let count = ((BLOCK_SAMPLES / SAMPLES_PER_PIXEL[0]) as u32 + 1).to_le_bytes();
+        patch(&source.cache(), HEADER_LEN, &count);
+        assert_eq!(read_error(&source), "wrong number of peaks");
+    }
+
+    #[test]
+    fn wrong_version() {
+        let source = Source::new("version");
+        write_cache(&source, 2);
+        patch(&source.cache(), VERSION_OFFSET, &(VERSION + 1).to_le_bytes());
+        assert_eq!(read_error(&source), "not a peak file of this version");
+    }
+
+    #[test]
+    fn wrong_layout() {
+        let source = Source::new("layout");
+        write_cache(&source, 2);
+        patch(&source.cache(), BLOCK_SAMPLES_OFFSET, &(BLOCK_SAMPLES as u32 / 2).to_le_bytes());
+        assert_eq!(read_error(&source), "different zoom levels");
+    }
+}
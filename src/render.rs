@@ -0,0 +1,328 @@
+//! Waveform drawing that doesn't depend on a graphics backend. Each pixel column of a
+//! channel is a vertical line from its min to its max, joined to the column before,
+//! with a shorter line either side of the centre for the rms. The window strokes these
+//! lines on its canvas, and `render_png` and `render_svg` draw them in software so that
+//! thumbnails can be made on machines without a GPU.
+
+use crate::audio_file::AudioFile;
+use crate::waveform::{level_for, merge_level, sample_peaks, to_f32, Peak, SAMPLES_PER_PIXEL};
+use image::{Rgba, RgbaImage};
+use std::fmt::Write;
+
+/// The rms is only drawn when zoomed out far enough for it to differ from the min/max
+pub const RMS_MIN_SAMPLES_PER_PIXEL: f32 = 100.0;
+
+/// Colours of the min/max and rms lines, as red, green, blue and alpha
+pub const PEAK_COLOR: Color = [50, 50, 255, 255];
+pub const RMS_COLOR: Color = [80, 80, 255, 255];
+pub const BACKGROUND_COLOR: Color = [30, 30, 30, 255];
+
+// Level at the centre line in decibel units
+const MIN_DB: f32 = -60.0;
+
+// Number of samples read from each channel at a time
+const CHUNK_SAMPLES: usize = 65536;
+
+pub type Color = [u8; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitsMode {
+    Linear,
+    Decibel,
+}
+
+/// How the channels are placed in a rendered image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelLayout {
+    /// each channel in its own lane, from the top down
+    Stacked,
+    /// every channel across the full height, drawn over each other
+    Overlaid,
+    /// one channel, by index, across the full height
+    Single(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub width: usize,
+    pub height: usize,
+    pub units: UnitsMode,
+    pub layout: ChannelLayout,
+    pub background: Color,
+    pub peak_color: Color,
+    /// `None` to leave out the rms
+    pub rms_color: Option<Color>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 200,
+            units: UnitsMode::Linear,
+            layout: ChannelLayout::Stacked,
+            background: BACKGROUND_COLOR,
+            peak_color: PEAK_COLOR,
+            rms_color: Some(RMS_COLOR),
+        }
+    }
+}
+
+/// map a sample value to a vertical position from -1 to 1. Decibel units keep the sign
+/// and put `MIN_DB` and below on the centre line
+pub fn scale_value(value: f32, units: UnitsMode) -> f32 {
+    match units {
+        UnitsMode::Linear => value,
+        UnitsMode::Decibel => {
+            let value_db = 1.0 + (20.0 * value.abs().log10()).max(MIN_DB) / -MIN_DB;
+            if value < 0.0 {
+                -value_db
+            } else {
+                value_db
+            }
+        }
+    }
+}
+
+/// The lines of one pixel column, as positions from -1 at the bottom to 1 at the top
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+/// the columns of a channel, one for each peak
+pub fn columns(peaks: &[Peak], units: UnitsMode) -> impl Iterator<Item = Column> + '_ {
+    peaks.iter().map(move |(min, max, rms)| Column {
+        min: scale_value(to_f32(*min), units),
+        max: scale_value(to_f32(*max), units),
+        rms: scale_value(to_f32(*rms), units),
+    })
+}
+
+/// the peaks of each channel of a file, with the whole file fitted to `width` pixels
+pub fn fit_peaks(file: &AudioFile, width: usize) -> Vec<Vec<Peak>> {
+    // Only the level the pixels are merged from is built, in chunks of whole pixels
+    let samples_per_pixel = file.num_samples as f64 / width.max(1) as f64;
+    let level_samples_per_pixel = SAMPLES_PER_PIXEL[level_for(samples_per_pixel)];
+    let mut buffer = vec![0.0; CHUNK_SAMPLES / level_samples_per_pixel * level_samples_per_pixel];
+    let (mut level, mut chunk_peaks) = (Vec::new(), Vec::new());
+    (0..file.num_channels)
+        .map(|channel| {
+            level.clear();
+            let mut position = 0;
+            while position < file.num_samples {
+                let len = file.read(channel, position, &mut buffer);
+                if len == 0 {
+                    break;
+                }
+                sample_peaks(&buffer[..len], level_samples_per_pixel as f64, &mut chunk_peaks);
+                level.extend_from_slice(&chunk_peaks);
+                position += len;
+            }
+
+            let mut peaks = Vec::new();
+            if width > 0 && file.num_samples > 0 {
+                merge_level(
+                    &level,
+                    level_samples_per_pixel,
+                    0.0,
+                    samples_per_pixel,
+                    width,
+                    &mut peaks,
+                );
+            }
+            peaks
+        })
+        .collect()
+}
+
+// The channels drawn in each lane, with the top and height of the lane in pixels
+fn lanes(num_channels: usize, options: &RenderOptions) -> Vec<(Vec<usize>, f32, f32)> {
+    let height = options.height as f32;
+    match options.layout {
+        ChannelLayout::Stacked => {
+            let lane_height = height / num_channels.max(1) as f32;
+            (0..num_channels)
+                .map(|channel| (vec![channel], channel as f32 * lane_height, lane_height))
+                .collect()
+        }
+        ChannelLayout::Overlaid => vec![((0..num_channels).collect(), 0.0, height)],
+        ChannelLayout::Single(channel) if channel < num_channels => vec![(vec![channel], 0.0, height)],
+        ChannelLayout::Single(_) => Vec::new(),
+    }
+}
+
+/// draw the peaks of each channel, already fitted to `options.width`, to an image
+pub fn render_png(peaks: &[Vec<Peak>], options: &RenderOptions) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(options.width as u32, options.height as u32, Rgba(options.background));
+
+    for (channels, top, height) in lanes(peaks.len(), options) {
+        let to_y = |value: f32| top + height / 2.0 - value * height / 2.0;
+        for channel in channels {
+            let mut last_max = None;
+            for (x, column) in columns(&peaks[channel], options.units).take(options.width).enumerate() {
+                // Joined to the max of the last column, as the window draws one line through them all
+                let (mut upper, mut lower) = (to_y(column.max), to_y(column.min));
+                if let Some(last_max) = last_max {
+                    upper = upper.min(last_max);
+                    lower = lower.max(last_max);
+                }
+                fill_column(&mut image, x, upper, lower, options.peak_color);
+                last_max = Some(to_y(column.max));
+            }
+
+            if let Some(rms_color) = options.rms_color {
+                for (x, column) in columns(&peaks[channel], options.units).take(options.width).enumerate() {
+                    fill_column(&mut image, x, to_y(column.rms), to_y(-column.rms), rms_color);
+                }
+            }
+        }
+    }
+
+    image
+}
+
+// Blend a colour over the pixels of a column from `upper` to `lower`
+fn fill_column(image: &mut RgbaImage, x: usize, upper: f32, lower: f32, color: Color) {
+    let last_row = image.height() as f32 - 1.0;
+    let first = upper.floor().max(0.0).min(last_row) as u32;
+    let last = lower.floor().max(0.0).min(last_row) as u32;
+    let alpha = color[3] as f32 / 255.0;
+    for y in first..=last {
+        let pixel = image.get_pixel_mut(x as u32, y);
+        for idx in 0..3 {
+            pixel.0[idx] = (color[idx] as f32 * alpha + pixel.0[idx] as f32 * (1.0 - alpha)).round() as u8;
+        }
+        pixel.0[3] = (color[3] as f32 + pixel.0[3] as f32 * (1.0 - alpha)).round() as u8;
+    }
+}
+
+/// draw the same lines as `render_png` to an SVG document, as one path for the min/max
+/// and one for the rms of each channel
+pub fn render_svg(peaks: &[Vec<Peak>], options: &RenderOptions) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" shape-rendering=\"crispEdges\">",
+        options.width, options.height
+    );
+    let _ = writeln!(
+        svg,
+        "<rect width=\"100%\" height=\"100%\" {}/>",
+        svg_paint("fill", options.background)
+    );
+
+    for (channels, top, height) in lanes(peaks.len(), options) {
+        let to_y = |value: f32| top + height / 2.0 - value * height / 2.0;
+        for channel in channels {
+            // Lines run through the middle of each pixel
+            let mut peak_path = format!("M0.5 {:.1}", to_y(0.0));
+            let mut rms_path = String::new();
+            for (x, column) in columns(&peaks[channel], options.units).take(options.width).enumerate() {
+                let x = x as f32 + 0.5;
+                let _ = write!(
+                    peak_path,
+                    "L{} {:.1}L{} {:.1}",
+                    x,
+                    to_y(column.min),
+                    x,
+                    to_y(column.max)
+                );
+                let _ = write!(
+                    rms_path,
+                    "M{} {:.1}L{} {:.1}",
+                    x,
+                    to_y(-column.rms),
+                    x,
+                    to_y(column.rms)
+                );
+            }
+
+            let _ = writeln!(
+                svg,
+                "<path d=\"{}\" fill=\"none\" {}/>",
+                peak_path,
+                svg_paint("stroke", options.peak_color)
+            );
+            if let Some(rms_color) = options.rms_color {
+                let _ = writeln!(
+                    svg,
+                    "<path d=\"{}\" fill=\"none\" {}/>",
+                    rms_path,
+                    svg_paint("stroke", rms_color)
+                );
+            }
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// A fill or stroke attribute, with its opacity when the colour isn't opaque
+fn svg_paint(attribute: &str, color: Color) -> String {
+    let paint = format!("{}=\"#{:02x}{:02x}{:02x}\"", attribute, color[0], color[1], color[2]);
+    if color[3] == 255 {
+        paint
+    } else {
+        format!("{} {}-opacity=\"{:.3}\"", paint, attribute, color[3] as f32 / 255.0)
+    }
+}
+
+/// read a colour written as `#rrggbb` or `#rrggbbaa`, with or without the `#`
+pub fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.trim_start_matches('#');
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut color = [255; 4];
+    for (idx, component) in color.iter_mut().enumerate().take(hex.len() / 2) {
+        *component = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::open_wav;
+    use crate::waveform::Waveform;
+
+    #[test]
+    fn fitted_peaks_match_the_full_waveform() {
+        let samples: Vec<f32> = (0..200_001)
+            .flat_map(|idx| {
+                let t = idx as f32 / 44100.0;
+                vec![(t * 440.0).sin() * t / 5.0, (t * 97.0).cos() * 0.5]
+            })
+            .collect();
+        let file = open_wav("render_fit.wav", 2, 44100, &samples);
+
+        // As the window does it, with every level built and the fitted level taken from them
+        let mut buffer = vec![0.0; file.num_samples];
+        let mut waveforms: Vec<Waveform> = (0..file.num_channels)
+            .map(|channel| {
+                file.read(channel, 0, &mut buffer);
+                let mut waveform = Waveform::new();
+                waveform.push(&buffer);
+                waveform.finish();
+                waveform
+            })
+            .collect();
+
+        for width in [1, 7, 800, 100_000, 300_000].iter() {
+            let expected: Vec<Vec<Peak>> = waveforms
+                .iter_mut()
+                .map(|waveform| {
+                    waveform.set_num_pixels(file.num_samples, *width);
+                    waveform.get_data(SAMPLES_PER_PIXEL.len()).to_vec()
+                })
+                .collect();
+            assert_eq!(fit_peaks(&file, *width), expected, "width {}", width);
+        }
+        assert!(fit_peaks(&file, 0).iter().all(|peaks| peaks.is_empty()));
+    }
+}
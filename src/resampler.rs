@@ -0,0 +1,332 @@
+//! Sample-rate conversion for playback. Each output sample is read from a fractional
+//! position in the file, as a weighted sum of the samples around it. The weights come
+//! from a kernel chosen by the quality: a straight line between two samples, a cubic
+//! through four, or a Blackman-windowed sinc over many. When the file is played faster
+//! than the device rate the sinc is widened to filter out what the device can't play.
+
+use crate::audio_file::AudioFile;
+use basedrop::Shared;
+
+// Kernel values stored per sample of distance, between which the kernel is interpolated
+const TABLE_STEPS: usize = 256;
+
+// Limit on the taps either side once the sinc is widened for a lower cutoff
+const MAX_HALF_WIDTH: usize = 256;
+
+// File samples read at a time, enough for a block of output at the widest kernel
+const INPUT_LEN: usize = 16384 + 2 * MAX_HALF_WIDTH;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleQuality {
+    Linear,
+    Cubic,
+    Sinc,
+    BestSinc,
+}
+
+impl Default for ResampleQuality {
+    fn default() -> Self {
+        ResampleQuality::Sinc
+    }
+}
+
+impl ResampleQuality {
+    pub const ALL: [ResampleQuality; 4] = [
+        ResampleQuality::Linear,
+        ResampleQuality::Cubic,
+        ResampleQuality::Sinc,
+        ResampleQuality::BestSinc,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResampleQuality::Linear => "Linear",
+            ResampleQuality::Cubic => "Cubic",
+            ResampleQuality::Sinc => "Sinc",
+            ResampleQuality::BestSinc => "Best",
+        }
+    }
+
+    /// number of samples either side of a position that are weighted, at full bandwidth
+    pub fn half_width(&self) -> usize {
+        match self {
+            ResampleQuality::Linear => 1,
+            ResampleQuality::Cubic => 2,
+            ResampleQuality::Sinc => 8,
+            ResampleQuality::BestSinc => 32,
+        }
+    }
+
+    fn is_sinc(&self) -> bool {
+        matches!(self, ResampleQuality::Sinc | ResampleQuality::BestSinc)
+    }
+
+    // Weight of a sample `x` samples away, with the pass band cut to `cutoff` of the file's
+    fn kernel(&self, x: f64, half_width: f64, cutoff: f64) -> f64 {
+        let x = x.abs();
+        if x >= half_width {
+            return 0.0;
+        }
+
+        match self {
+            ResampleQuality::Linear => 1.0 - x,
+            // Catmull-Rom, which passes through the samples
+            ResampleQuality::Cubic => {
+                if x < 1.0 {
+                    1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0
+                } else {
+                    -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0
+                }
+            }
+            ResampleQuality::Sinc | ResampleQuality::BestSinc => {
+                let t = std::f64::consts::PI * x * cutoff;
+                let sinc = if t == 0.0 { 1.0 } else { t.sin() / t };
+                let w = std::f64::consts::PI * x / half_width;
+                let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                cutoff * sinc * window
+            }
+        }
+    }
+}
+
+/// The weights of a quality at one step, as a table, built away from the audio thread
+pub struct Kernel {
+    quality: ResampleQuality,
+    // The step the table was built for, as returned by `table_step`
+    step: f64,
+    half_width: usize,
+    table: Vec<f32>,
+}
+
+impl Kernel {
+    pub fn new(quality: ResampleQuality, step: f64) -> Self {
+        let step = table_step(quality, step);
+        let cutoff = 1.0 / step;
+        let half_width = ((quality.half_width() as f64 * step).ceil() as usize).min(MAX_HALF_WIDTH);
+        let table = (0..=half_width * TABLE_STEPS + 1)
+            .map(|idx| quality.kernel(idx as f64 / TABLE_STEPS as f64, half_width as f64, cutoff) as f32)
+            .collect();
+        Self {
+            quality,
+            step,
+            half_width,
+            table,
+        }
+    }
+
+    // Weight of a sample `x` samples away, from the table
+    fn weight(&self, x: f64) -> f32 {
+        let position = x.abs() * TABLE_STEPS as f64;
+        let idx = position as usize;
+        if idx + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let fract = (position - idx as f64) as f32;
+        self.table[idx] + (self.table[idx + 1] - self.table[idx]) * fract
+    }
+}
+
+/// the step a kernel is built for. The sinc only needs to be cut below the file's band
+/// when samples are skipped, and the other kernels don't change with the step
+pub fn table_step(quality: ResampleQuality, step: f64) -> f64 {
+    if quality.is_sinc() {
+        step.max(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Reads a channel of a file at a step of any number of file samples per output sample.
+/// The kernels are handed to it ready built, and the file samples are read into a buffer
+/// of a fixed size, a block of output at a time, so that it can run on the audio thread.
+/// A step with no kernel is read with linear interpolation until one arrives
+pub struct Resampler {
+    kernels: Option<Shared<Vec<Kernel>>>,
+    linear: Kernel,
+    // The file samples read for one block
+    input: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        Self {
+            kernels: None,
+            linear: Kernel::new(ResampleQuality::Linear, 1.0),
+            input: vec![0.0; INPUT_LEN],
+        }
+    }
+
+    /// use these kernels from now on, each for the step it was built for
+    pub fn set_kernels(&mut self, kernels: Shared<Vec<Kernel>>) {
+        self.kernels = Some(kernels);
+    }
+
+    /// fill `output` with a channel of `file`, starting at the fractional sample `start`
+    /// and moving `step` samples for each output sample. Returns the number of output
+    /// samples written before the end of the file
+    pub fn process(&mut self, file: &AudioFile, channel: usize, start: f64, step: f64, output: &mut [f32]) -> usize {
+        if output.is_empty() || start >= file.num_samples as f64 {
+            return 0;
+        }
+
+        // Samples are copied as they are when no conversion is needed
+        if step == 1.0 && start.fract() == 0.0 {
+            return file.read(channel, start as usize, output);
+        }
+
+        let kernel = self
+            .kernels
+            .as_ref()
+            .and_then(|kernels| {
+                kernels
+                    .iter()
+                    .find(|kernel| kernel.step == table_step(kernel.quality, step))
+            })
+            .unwrap_or(&self.linear);
+        let half_width = kernel.half_width as isize;
+
+        // As many output samples at a time as the input buffer holds the file samples for
+        let block_len = (((INPUT_LEN - 2 * kernel.half_width) as f64 / step) as usize).max(1);
+        let len = (((file.num_samples as f64 - start) / step).ceil() as usize).min(output.len());
+        let mut done = 0;
+        while done < len {
+            let block = &mut output[done..len.min(done + block_len)];
+            let block_start = start + step * done as f64;
+
+            // Read every sample that is weighted, with silence before the start and after the end
+            let first = block_start.floor() as isize - half_width + 1;
+            let last = (block_start + step * (block.len() - 1) as f64).floor() as isize + half_width;
+            let input = &mut self.input[..(last - first + 1) as usize];
+            let skip = (-first).max(0) as usize;
+            let read = file.read(channel, first.max(0) as usize, &mut input[skip..]);
+            input[..skip].iter_mut().for_each(|sample| *sample = 0.0);
+            input[skip + read..].iter_mut().for_each(|sample| *sample = 0.0);
+
+            for (idx, sample) in block.iter_mut().enumerate() {
+                let position = block_start + step * idx as f64 - first as f64;
+                let center = position.floor() as isize;
+                let taps = (center - half_width + 1).max(0)..=(center + half_width).min(input.len() as isize - 1);
+                let mut sum = 0.0;
+                for tap in taps {
+                    sum += input[tap as usize] * kernel.weight(position - tap as f64);
+                }
+                *sample = sum;
+            }
+            done += block.len();
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::open_wav;
+    use basedrop::Collector;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// Write a mono float file of `samples` and open it
+    fn open_file(name: &str, samples: &[f32]) -> AudioFile {
+        open_wav(&format!("resampler_{}.wav", name), 1, SAMPLE_RATE as u32, samples)
+    }
+
+    /// A resampler with the kernel of `quality` for `step`
+    fn with_kernel(collector: &Collector, quality: ResampleQuality, step: f64) -> Resampler {
+        let mut resampler = Resampler::new();
+        resampler.set_kernels(Shared::new(&collector.handle(), vec![Kernel::new(quality, step)]));
+        resampler
+    }
+
+    fn ramp(len: usize) -> Vec<f32> {
+        (0..len).map(|idx| idx as f32 / len as f32).collect()
+    }
+
+    #[test]
+    fn whole_samples_are_copied() {
+        let samples: Vec<f32> = (0..1000).map(|idx| (idx as f32 * 0.37).sin()).collect();
+        let file = open_file("copied", &samples);
+        let collector = Collector::new();
+        let mut resampler = with_kernel(&collector, ResampleQuality::BestSinc, 1.0);
+        let mut output = vec![0.0; 256];
+        assert_eq!(resampler.process(&file, 0, 100.0, 1.0, &mut output), 256);
+        assert_eq!(output, samples[100..356]);
+    }
+
+    #[test]
+    fn fractional_start_on_a_ramp() {
+        let samples = ramp(1000);
+        let file = open_file("ramp", &samples);
+        let collector = Collector::new();
+        for quality in [ResampleQuality::Linear, ResampleQuality::Cubic].iter() {
+            for step in [1.0, 0.5, 0.75].iter() {
+                let mut resampler = with_kernel(&collector, *quality, *step);
+                let mut output = vec![0.0; 256];
+                let start = 100.25;
+                assert_eq!(resampler.process(&file, 0, start, *step, &mut output), 256);
+                for (idx, sample) in output.iter().enumerate() {
+                    let expected = (start + step * idx as f64) / samples.len() as f64;
+                    assert!(
+                        (*sample as f64 - expected).abs() < 1e-5,
+                        "{:?} at step {}, sample {}: {} for {}",
+                        quality,
+                        step,
+                        idx,
+                        sample,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_join_up() {
+        let samples = ramp(40000);
+        let file = open_file("blocks", &samples);
+        let collector = Collector::new();
+        let mut resampler = with_kernel(&collector, ResampleQuality::Linear, 1.0);
+        // More output than one block of input holds at this step
+        let step = 3.0;
+        let mut output = vec![0.0; 10000];
+        assert_eq!(resampler.process(&file, 0, 0.5, step, &mut output), 10000);
+        for (idx, sample) in output.iter().enumerate() {
+            let expected = (0.5 + step * idx as f64) / samples.len() as f64;
+            assert!((*sample as f64 - expected).abs() < 1e-5, "sample {}: {} for {}", idx, sample, expected);
+        }
+    }
+
+    #[test]
+    fn sinc_keeps_the_pass_band() {
+        let amplitude = 0.5;
+        let samples: Vec<f32> = (0..48000)
+            .map(|idx| (amplitude * (2.0 * std::f64::consts::PI * 1000.0 * idx as f64 / SAMPLE_RATE).sin()) as f32)
+            .collect();
+        let file = open_file("sine", &samples);
+        let collector = Collector::new();
+        for step in [0.5, 2.0].iter() {
+            let mut resampler = with_kernel(&collector, ResampleQuality::Sinc, *step);
+            // Away from the ends, over a whole number of cycles of the sine
+            let cycle_len = (SAMPLE_RATE / 1000.0 / step) as usize;
+            let mut output = vec![0.0; cycle_len * 10];
+            resampler.process(&file, 0, 4000.5, *step, &mut output);
+            let rms = (output.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / output.len() as f64).sqrt();
+            let expected = amplitude / 2f64.sqrt();
+            assert!((rms / expected - 1.0).abs() < 0.01, "step {}: rms {} for {}", step, rms, expected);
+        }
+    }
+
+    #[test]
+    fn short_count_at_the_end() {
+        let file = open_file("end", &ramp(1000));
+        let collector = Collector::new();
+        let mut output = vec![0.0; 64];
+        let mut resampler = with_kernel(&collector, ResampleQuality::Cubic, 1.0);
+        assert_eq!(resampler.process(&file, 0, 990.5, 1.0, &mut output), 10);
+        assert_eq!(resampler.process(&file, 0, 990.0, 1.0, &mut output), 10);
+        let mut resampler = with_kernel(&collector, ResampleQuality::Sinc, 2.0);
+        assert_eq!(resampler.process(&file, 0, 900.0, 2.0, &mut output), 50);
+        assert_eq!(resampler.process(&file, 0, 1000.0, 2.0, &mut output), 0);
+    }
+}
@@ -0,0 +1,268 @@
+//! Which channels of the file are heard on which outputs of the device, and at what
+//! gain. Unless something else is chosen the channels are mapped one to one, a mono
+//! file is played on both speakers, and surround files are folded down to stereo with
+//! the ITU-R BS.775 coefficients. A single channel can be soloed on both speakers, or
+//! each channel connected to any of the outputs by hand.
+
+// Gain of the centre and surround channels in a stereo downmix
+const DOWNMIX_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Routing {
+    /// the mapping from `standard_gain`
+    Standard,
+    /// one channel of the file on the first two outputs
+    Solo(usize),
+    Custom(RoutingMatrix),
+}
+
+impl Default for Routing {
+    fn default() -> Self {
+        Routing::Standard
+    }
+}
+
+impl Routing {
+    /// the gain of a channel of the file at an output. A solo or matrix that doesn't fit
+    /// the number of channels, as when another file is opened, falls back to the standard mapping
+    pub fn gain(&self, input: usize, output: usize, num_inputs: usize, num_outputs: usize) -> f32 {
+        match self {
+            Routing::Solo(channel) if *channel < num_inputs => {
+                if input == *channel && output < 2 && output < num_outputs {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Routing::Custom(matrix) if matrix.num_inputs == num_inputs && matrix.num_outputs == num_outputs => {
+                matrix.gain(input, output)
+            }
+            _ => standard_gain(input, output, num_inputs, num_outputs),
+        }
+    }
+
+    /// returns true if the routing still applies to these numbers of channels
+    pub fn fits(&self, num_inputs: usize, num_outputs: usize) -> bool {
+        match self {
+            Routing::Standard => true,
+            Routing::Solo(channel) => *channel < num_inputs,
+            Routing::Custom(matrix) => matrix.num_inputs == num_inputs && matrix.num_outputs == num_outputs,
+        }
+    }
+}
+
+/// Gains from each channel of the file to each output
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingMatrix {
+    num_inputs: usize,
+    num_outputs: usize,
+    // One row of outputs for each input
+    gains: Vec<f32>,
+}
+
+impl RoutingMatrix {
+    /// a matrix holding the standard mapping, to be changed from there
+    pub fn standard(num_inputs: usize, num_outputs: usize) -> Self {
+        let mut gains = Vec::with_capacity(num_inputs * num_outputs);
+        for input in 0..num_inputs {
+            for output in 0..num_outputs {
+                gains.push(standard_gain(input, output, num_inputs, num_outputs));
+            }
+        }
+        Self {
+            num_inputs,
+            num_outputs,
+            gains,
+        }
+    }
+
+    pub fn gain(&self, input: usize, output: usize) -> f32 {
+        if input < self.num_inputs && output < self.num_outputs {
+            self.gains[input * self.num_outputs + output]
+        } else {
+            0.0
+        }
+    }
+
+    pub fn set_gain(&mut self, input: usize, output: usize, gain: f32) {
+        if input < self.num_inputs && output < self.num_outputs {
+            self.gains[input * self.num_outputs + output] = gain;
+        }
+    }
+}
+
+/// the gain of a channel of the file at an output when nothing else is chosen. Channels
+/// are mapped one to one, leaving out any the device has no output for, except that
+/// - a mono file plays on the first two outputs
+/// - every channel is mixed equally into a mono output
+/// - files with more than two channels are mixed down to a stereo output
+pub fn standard_gain(input: usize, output: usize, num_inputs: usize, num_outputs: usize) -> f32 {
+    if input >= num_inputs || output >= num_outputs {
+        return 0.0;
+    }
+
+    if num_inputs == 1 {
+        if output < 2 {
+            1.0
+        } else {
+            0.0
+        }
+    } else if num_outputs == 1 {
+        1.0 / num_inputs as f32
+    } else if num_outputs == 2 && num_inputs > 2 {
+        stereo_downmix_gain(input, output, num_inputs)
+    } else if input == output {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// Where a channel of a surround layout is heard in a stereo downmix
+#[derive(Clone, Copy)]
+enum Position {
+    Left,
+    Right,
+    Centre,
+    Lfe,
+}
+
+// The channels of the common layouts in WAV order: 3.0, quad, 5.0, 5.1, 7.0 and 7.1.
+// Front channels are kept at unity, the centre is split between the sides, the surrounds
+// go to their own side and the LFE is left out. In layouts that aren't known, the channels
+// after the front pair go alternately to the left and right
+fn stereo_downmix_gain(input: usize, output: usize, num_inputs: usize) -> f32 {
+    use Position::*;
+    let layout: &[Position] = match num_inputs {
+        3 => &[Left, Right, Centre],
+        4 => &[Left, Right, Left, Right],
+        5 => &[Left, Right, Centre, Left, Right],
+        6 => &[Left, Right, Centre, Lfe, Left, Right],
+        7 => &[Left, Right, Centre, Left, Right, Left, Right],
+        8 => &[Left, Right, Centre, Lfe, Left, Right, Left, Right],
+        _ => &[],
+    };
+    let position = layout
+        .get(input)
+        .copied()
+        .unwrap_or(if input % 2 == 1 { Right } else { Left });
+    let gain = if input < 2 { 1.0 } else { DOWNMIX_GAIN };
+
+    match (position, output) {
+        (Left, 0) | (Right, 1) | (Centre, _) => gain,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: f32 = DOWNMIX_GAIN;
+
+    /// The gains of every input, one row each, at every output
+    fn gains(routing: &Routing, num_inputs: usize, num_outputs: usize) -> Vec<Vec<f32>> {
+        (0..num_inputs)
+            .map(|input| {
+                (0..num_outputs)
+                    .map(|output| routing.gain(input, output, num_inputs, num_outputs))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn standard_mappings() {
+        let cases: &[(usize, usize, &[&[f32]])] = &[
+            (1, 2, &[&[1.0, 1.0]]),
+            (1, 6, &[&[1.0, 1.0, 0.0, 0.0, 0.0, 0.0]]),
+            (2, 1, &[&[0.5], &[0.5]]),
+            (2, 2, &[&[1.0, 0.0], &[0.0, 1.0]]),
+            (2, 4, &[&[1.0, 0.0, 0.0, 0.0], &[0.0, 1.0, 0.0, 0.0]]),
+            (3, 2, &[&[1.0, 0.0], &[0.0, 1.0], &[G, G]]),
+            (4, 2, &[&[1.0, 0.0], &[0.0, 1.0], &[G, 0.0], &[0.0, G]]),
+            (5, 2, &[&[1.0, 0.0], &[0.0, 1.0], &[G, G], &[G, 0.0], &[0.0, G]]),
+            (
+                6,
+                2,
+                &[&[1.0, 0.0], &[0.0, 1.0], &[G, G], &[0.0, 0.0], &[G, 0.0], &[0.0, G]],
+            ),
+            (
+                7,
+                2,
+                &[
+                    &[1.0, 0.0],
+                    &[0.0, 1.0],
+                    &[G, G],
+                    &[G, 0.0],
+                    &[0.0, G],
+                    &[G, 0.0],
+                    &[0.0, G],
+                ],
+            ),
+            (
+                8,
+                2,
+                &[
+                    &[1.0, 0.0],
+                    &[0.0, 1.0],
+                    &[G, G],
+                    &[0.0, 0.0],
+                    &[G, 0.0],
+                    &[0.0, G],
+                    &[G, 0.0],
+                    &[0.0, G],
+                ],
+            ),
+            (
+                6,
+                6,
+                &[
+                    &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                    &[0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+                    &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+                    &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+                    &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                    &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+                ],
+            ),
+        ];
+        for (num_inputs, num_outputs, expected) in cases.iter() {
+            let expected: Vec<Vec<f32>> = expected.iter().map(|row| row.to_vec()).collect();
+            assert_eq!(
+                gains(&Routing::Standard, *num_inputs, *num_outputs),
+                expected,
+                "{} inputs to {} outputs",
+                num_inputs,
+                num_outputs
+            );
+        }
+    }
+
+    #[test]
+    fn solo() {
+        assert_eq!(gains(&Routing::Solo(1), 2, 2), vec![vec![0.0, 0.0], vec![1.0, 1.0]]);
+        assert_eq!(gains(&Routing::Solo(1), 2, 1), vec![vec![0.0], vec![1.0]]);
+        assert_eq!(gains(&Routing::Solo(0), 1, 1), vec![vec![1.0]]);
+        // A channel the file doesn't have falls back to the standard mapping
+        assert_eq!(gains(&Routing::Solo(2), 2, 2), gains(&Routing::Standard, 2, 2));
+        assert!(!Routing::Solo(2).fits(2, 2));
+    }
+
+    #[test]
+    fn custom_falls_back_when_channels_change() {
+        let mut matrix = RoutingMatrix::standard(2, 2);
+        matrix.set_gain(0, 1, 0.5);
+        let routing = Routing::Custom(matrix);
+        assert_eq!(gains(&routing, 2, 2), vec![vec![1.0, 0.5], vec![0.0, 1.0]]);
+        assert!(routing.fits(2, 2));
+
+        for (num_inputs, num_outputs) in [(6, 2), (2, 6), (1, 2)].iter() {
+            assert!(!routing.fits(*num_inputs, *num_outputs));
+            assert_eq!(
+                gains(&routing, *num_inputs, *num_outputs),
+                gains(&Routing::Standard, *num_inputs, *num_outputs)
+            );
+        }
+    }
+}
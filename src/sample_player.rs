@@ -1,6 +1,10 @@
 use crate::audio_file::AudioFile;
 use crate::audio_stream::PlaybackContext;
-use basedrop::{Collector, Handle, Shared};
+use crate::meters::{MeterFrame, MAX_METER_CHANNELS};
+use crate::resampler::{table_step, Kernel, ResampleQuality, Resampler};
+use crate::routing::Routing;
+use crate::time_stretch::{TimeStretch, MAX_BUFFER_LEN};
+use basedrop::{Collector, Handle, Owned, Shared};
 use ringbuf::{Consumer, Producer, RingBuffer};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -10,56 +14,105 @@ enum PlayerState {
     Stopped,
 }
 
+/// Slowest and fastest playback rates
+pub const MIN_RATE: f64 = 0.25;
+pub const MAX_RATE: f64 = 4.0;
+
+/// How the playback rate changes the sound
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpeedMode {
+    /// the pitch rises and falls with the speed, as with tape
+    Varispeed,
+    /// the pitch stays the same, by time stretching
+    KeepPitch,
+}
+
 enum Message {
     Seek(f64),
     Scrub(f64),
     Play,
     Stop,
-    SetActive(usize, bool),
+    Route(Shared<Routing>),
     NewFile(Shared<AudioFile>),
+    CloseFile,
     Volume(f32),
+    Kernels(Shared<Vec<Kernel>>),
+    Rate(f64),
+    SpeedMode(SpeedMode),
+    TimeStretch(Owned<TimeStretch>),
 }
 
 pub struct SamplePlayer {
     pub file: Option<Shared<AudioFile>>,
-    active: [bool; 32],
+    routing: Shared<Routing>,
     playhead: Arc<AtomicUsize>,
+    // The playhead between samples, when the file and device rates differ
+    position: f64,
     state: PlayerState,
     rx: Consumer<Message>,
     volume: f32,
+    resampler: Resampler,
+    // Playback speed, relative to the file's own
+    rate: f64,
+    speed_mode: SpeedMode,
+    time_stretch: Owned<TimeStretch>,
+    // Whether the last buffer was time stretched, which must start again from the playhead if not
+    stretching: bool,
+    // One channel of the file at the device rate, before it is mixed into the outputs
+    channel_buffer: Vec<f32>,
+    // Levels of each buffer played, for the meters
+    meter_tx: Producer<MeterFrame>,
 }
 
 pub struct SamplePlayerController {
     tx: Producer<Message>,
+    meter_rx: Consumer<MeterFrame>,
     playhead: Arc<AtomicUsize>,
     collector: Handle,
     sample_rate: Option<f64>,
     num_channels: Option<usize>,
     num_samples: Option<usize>,
     pub file: Option<Shared<AudioFile>>,
+    // What the resampling kernels are built for
+    quality: ResampleQuality,
+    rate: f64,
+    device_rate: Option<f64>,
 }
 
 /// create a new sample player and its controller
 pub fn sample_player(c: &Collector) -> (SamplePlayer, SamplePlayerController) {
     let playhead = Arc::new(AtomicUsize::new(0));
     let (tx, rx) = RingBuffer::new(2048).split();
+    let (meter_tx, meter_rx) = RingBuffer::new(64).split();
     (
         SamplePlayer {
             file: None,
-            active: [true; 32],
+            routing: Shared::new(&c.handle(), Routing::default()),
             playhead: playhead.clone(),
+            position: 0.0,
             state: PlayerState::Stopped,
             rx,
             volume: 1.0,
+            resampler: Resampler::new(),
+            rate: 1.0,
+            speed_mode: SpeedMode::Varispeed,
+            time_stretch: Owned::new(&c.handle(), TimeStretch::new(0, 0.0)),
+            stretching: false,
+            channel_buffer: vec![0.0; MAX_BUFFER_LEN],
+            meter_tx,
         },
         SamplePlayerController {
             tx,
+            meter_rx,
             playhead: playhead.clone(),
             collector: c.handle(),
             sample_rate: None,
             num_channels: None,
             num_samples: None,
             file: None,
+            quality: ResampleQuality::default(),
+            rate: 1.0,
+            device_rate: None,
         },
     )
 }
@@ -69,56 +122,114 @@ impl SamplePlayer {
         self.playhead.load(Ordering::SeqCst)
     }
 
-    #[inline]
-    pub fn advance(&mut self, context: &mut PlaybackContext) {
-        
+    /// apply the messages sent by the controller since the last call
+    pub fn receive_messages(&mut self) {
         while let Some(msg) = self.rx.pop() {
             match msg {
                 Message::Seek(pos) => {
                     if let Some(f) = &self.file {
-                        self.playhead.store(
-                            ((f.sample_rate * pos) as usize).min(f.num_samples),
-                            Ordering::SeqCst,
-                        );
+                        self.position = ((f.sample_rate * pos) as usize).min(f.num_samples) as f64;
+                        self.playhead.store(self.position as usize, Ordering::SeqCst);
+                        self.stretching = false;
                     }
                 }
                 Message::NewFile(file) => {
                     self.file = Some(file);
+                    self.stretching = false;
+                }
+                Message::CloseFile => {
+                    self.file = None;
+                    self.state = PlayerState::Stopped;
                 }
                 Message::Scrub(_) => {
                     //todo...
                 }
-                Message::SetActive(channel, active) => {
-                    self.active[channel] = active;
+                Message::Route(routing) => {
+                    self.routing = routing;
                 }
                 Message::Play => self.state = PlayerState::Playing,
                 Message::Stop => self.state = PlayerState::Stopped,
                 Message::Volume(val) => self.volume = val,
+                Message::Kernels(kernels) => self.resampler.set_kernels(kernels),
+                Message::Rate(rate) => self.rate = rate,
+                Message::SpeedMode(speed_mode) => self.speed_mode = speed_mode,
+                Message::TimeStretch(time_stretch) => {
+                    self.time_stretch = time_stretch;
+                    self.stretching = false;
+                }
             }
         }
+    }
+
+    #[inline]
+    pub fn advance(&mut self, context: &mut PlaybackContext) {
+        self.receive_messages();
 
         if let PlayerState::Stopped = self.state {
             return;
         }
 
         if let Some(file) = &self.file {
-            if self.playhead() >= file.num_samples {
+            if self.position >= file.num_samples as f64 {
                 self.state = PlayerState::Stopped;
                 return;
             }
-            for channel in 0..context.num_channels.max(file.num_channels) {
-                if !self.active[channel] {
+            // File samples for each device sample at the file's own speed, and the
+            // distance moved through the file for each device sample at the playback rate
+            let step = file.sample_rate / context.sample_rate;
+            let speed = step * self.rate;
+
+            // Playing at the file's own speed needs no stretching, whatever the mode, and the
+            // pitch follows the speed until the controller sends a stretch made for this file
+            let stretch = self.speed_mode == SpeedMode::KeepPitch
+                && self.rate != 1.0
+                && self.time_stretch.fits(file.num_channels, context.sample_rate);
+            if stretch {
+                if !self.stretching {
+                    self.time_stretch.reset(self.position);
+                }
+                self.time_stretch
+                    .process(file, &mut self.resampler, step, self.rate, context.buffer_size);
+            }
+            self.stretching = stretch;
+
+            let (num_inputs, num_outputs) = (file.num_channels, context.num_channels);
+            let routing = &self.routing;
+            // Only grows, here on the audio thread, for buffers longer than it was made for
+            if self.channel_buffer.len() < context.buffer_size {
+                self.channel_buffer.resize(context.buffer_size, 0.0);
+            }
+            let channel_buffer = &mut self.channel_buffer[..context.buffer_size];
+            for input in 0..num_inputs {
+                let gains = (0..num_outputs).map(|output| routing.gain(input, output, num_inputs, num_outputs));
+                if gains.clone().all(|gain| gain == 0.0) {
                     continue;
                 }
-                let start = channel * file.num_samples + self.playhead().min(file.num_samples);
-                let end = channel * file.num_samples
-                    + (self.playhead() + context.buffer_size).min(file.num_samples);
-                context.get_output(channel)[0..(end - start)]
-                    .copy_from_slice(&file.data[start..end]);
-                context.get_output(channel)[0..(end - start)].iter_mut().for_each(|sample| *sample = *sample * self.volume);
+
+                let len = if stretch {
+                    let stretched = self.time_stretch.output(input);
+                    channel_buffer[..stretched.len()].copy_from_slice(stretched);
+                    stretched.len()
+                } else {
+                    self.resampler.process(file, input, self.position, speed, channel_buffer)
+                };
+                for (output, gain) in gains.enumerate().filter(|(_, gain)| *gain != 0.0) {
+                    let gain = gain * self.volume;
+                    let output = context.get_output(output);
+                    for (sample, input_sample) in output[..len].iter_mut().zip(&channel_buffer[..len]) {
+                        *sample += input_sample * gain;
+                    }
+                }
+            }
+
+            let mut meter_frame = MeterFrame::new(num_outputs, context.buffer_size);
+            for output in 0..num_outputs.min(MAX_METER_CHANNELS) {
+                meter_frame.measure(output, context.get_output(output));
             }
-            self.playhead
-                .fetch_add(context.buffer_size, Ordering::SeqCst);
+            // The meters miss a buffer if the UI falls behind
+            let _ = self.meter_tx.push(meter_frame);
+            self.position += speed * context.buffer_size as f64;
+            self.playhead.store(self.position as usize, Ordering::SeqCst);
         }
     }
 }
@@ -155,28 +266,92 @@ impl SamplePlayerController {
     pub fn scrub(&mut self, seconds: f64) {
         self.send_msg(Message::Scrub(seconds));
     }
-    pub fn set_active(&mut self, channel_index: usize, active: bool) {
-        self.send_msg(Message::SetActive(channel_index, active));
+    /// choose which channels of the file are heard on which outputs
+    pub fn set_routing(&mut self, routing: Routing) {
+        let routing = Shared::new(&self.collector, routing);
+        self.send_msg(Message::Route(routing));
     }
     pub fn volume(&mut self, val: f32) {
         self.send_msg(Message::Volume(val));
     }
-    pub fn load_file(&mut self, s: &str) {
-        let audio_file = Shared::new(
-            &self.collector,
-            AudioFile::open(s).expect("file does not exist"),
-        );
+    /// change the playback speed, from `MIN_RATE` to `MAX_RATE` times the file's own
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate.max(MIN_RATE).min(MAX_RATE);
+        self.send_msg(Message::Rate(self.rate));
+        self.send_kernels();
+    }
+    /// choose whether the pitch follows the playback speed
+    pub fn set_speed_mode(&mut self, speed_mode: SpeedMode) {
+        self.send_msg(Message::SpeedMode(speed_mode));
+    }
+    /// choose how the file is resampled when its rate differs from the device
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.quality = quality;
+        self.send_kernels();
+    }
+    /// set the rate of the device playing, once a stream is opened
+    pub fn set_device_rate(&mut self, sample_rate: f64) {
+        self.device_rate = Some(sample_rate);
+        self.send_kernels();
+        self.send_time_stretch();
+    }
+    // Build the kernels for the steps the player reads the file at, with and without
+    // time stretching, here rather than on the audio thread
+    fn send_kernels(&mut self) {
+        let (file_rate, device_rate) = match (self.sample_rate, self.device_rate) {
+            (Some(file_rate), Some(device_rate)) => (file_rate, device_rate),
+            _ => return,
+        };
+        let step = file_rate / device_rate;
+        let mut kernels = vec![Kernel::new(self.quality, step)];
+        if table_step(self.quality, step * self.rate) != table_step(self.quality, step) {
+            kernels.push(Kernel::new(self.quality, step * self.rate));
+        }
+        let kernels = Shared::new(&self.collector, kernels);
+        self.send_msg(Message::Kernels(kernels));
+    }
+    // Make the time stretch for the file and device here too, as it allocates its buffers
+    fn send_time_stretch(&mut self) {
+        if let (Some(num_channels), Some(device_rate)) = (self.num_channels, self.device_rate) {
+            let time_stretch = Owned::new(&self.collector, TimeStretch::new(num_channels, device_rate));
+            self.send_msg(Message::TimeStretch(time_stretch));
+        }
+    }
+    /// return the levels of the next buffer played, if there is one
+    pub fn pop_meter_frame(&mut self) -> Option<MeterFrame> {
+        self.meter_rx.pop()
+    }
+    /// a handle for allocating shared files on another thread
+    pub fn collector(&self) -> Handle {
+        self.collector.clone()
+    }
+    /// replace the file being played
+    pub fn set_file(&mut self, audio_file: Shared<AudioFile>) {
         self.num_samples = Some(audio_file.num_samples);
         self.num_channels = Some(audio_file.num_channels);
         self.sample_rate = Some(audio_file.sample_rate);
         self.file = Some(Shared::clone(&audio_file));
         self.send_msg(Message::NewFile(audio_file));
+        self.send_kernels();
+        self.send_time_stretch();
+    }
+    /// stop playing and let go of the file, so that it can be written over
+    pub fn close_file(&mut self) {
+        self.num_samples = None;
+        self.num_channels = None;
+        self.sample_rate = None;
+        self.file = None;
+        self.send_msg(Message::CloseFile);
     }
     pub fn get_magnitude(&self, sample_idx: usize) -> f32 {
         if let Some(file) = &self.file {
-            let ldx = sample_idx;
-            let rdx = sample_idx + file.num_samples;
-            (file.data[ldx].abs() + file.data[rdx].abs()) / 2.0
+            if sample_idx >= file.num_samples || file.num_channels == 0 {
+                return 0.0;
+            }
+            let sum: f32 = (0..file.num_channels)
+                .map(|channel| file.sample(channel, sample_idx).abs())
+                .sum();
+            sum / file.num_channels as f32
         } else {
             0.0
         }
@@ -0,0 +1,430 @@
+//! Spectrogram tiles, rendered on a worker thread.
+//!
+//! The spectrogram is split into tiles of `TILE_WIDTH` columns. Like the levels of a
+//! `Waveform`, tiles are made for a fixed set of zoom levels, here powers of two samples
+//! per column, and the view draws the level at or just below its own samples per pixel,
+//! stretched to fit. Each column shows the short-time Fourier transform of the frames,
+//! spaced `hop` samples apart, that are centred within it.
+
+use crate::audio_file::AudioFile;
+use basedrop::Shared;
+use rgb::RGBA8;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// Number of columns in a tile
+pub const TILE_WIDTH: usize = 256;
+/// Number of frequency rows in a tile, stretched to the height of the lane when drawn
+pub const TILE_HEIGHT: usize = 256;
+
+/// The closest zoom level, as a power of two samples per column
+pub const MIN_LEVEL: i32 = -6;
+
+// Columns wider than this many hops only use this many frames, evenly spaced
+const MAX_FRAMES_PER_COLUMN: usize = 4;
+
+// Lowest frequency shown on the log axis
+const MIN_LOG_FREQUENCY: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowFunction {
+    /// the window coefficients for a frame of `size` samples
+    pub fn coefficients(&self, size: usize) -> Vec<f32> {
+        let n = size as f32;
+        (0..size)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / n;
+                match self {
+                    WindowFunction::Rectangular => 1.0,
+                    WindowFunction::Hann => 0.5 - 0.5 * phase.cos(),
+                    WindowFunction::Hamming => 0.54 - 0.46 * phase.cos(),
+                    WindowFunction::Blackman => {
+                        0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrequencyScale {
+    Linear,
+    Log,
+    Mel,
+}
+
+impl FrequencyScale {
+    /// the frequency at `position`, from 0 at the bottom of the lane to 1 at the top
+    pub fn frequency(&self, position: f32, nyquist: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => position * nyquist,
+            FrequencyScale::Log => {
+                MIN_LOG_FREQUENCY * (nyquist / MIN_LOG_FREQUENCY).powf(position)
+            }
+            FrequencyScale::Mel => mel_to_hz(position * hz_to_mel(nyquist)),
+        }
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMap {
+    Magma,
+    Viridis,
+    Grayscale,
+}
+
+impl ColorMap {
+    /// the colour for `value`, from 0 for the quietest to 1 for full scale
+    pub fn color(&self, value: f32) -> RGBA8 {
+        let stops: &[(u8, u8, u8)] = match self {
+            ColorMap::Magma => &[
+                (0, 0, 4),
+                (81, 18, 124),
+                (183, 55, 121),
+                (252, 137, 97),
+                (252, 253, 191),
+            ],
+            ColorMap::Viridis => &[
+                (68, 1, 84),
+                (59, 82, 139),
+                (33, 145, 140),
+                (94, 201, 98),
+                (253, 231, 37),
+            ],
+            ColorMap::Grayscale => &[(0, 0, 0), (255, 255, 255)],
+        };
+
+        let position = value.max(0.0).min(1.0) * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let t = position - index as f32;
+        let (r0, g0, b0) = stops[index];
+        let (r1, g1, b1) = stops[index + 1];
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+        RGBA8::new(mix(r0, r1), mix(g0, g1), mix(b0, b1), 255)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrogramSettings {
+    /// number of samples in each FFT frame, a power of two
+    pub fft_size: usize,
+    /// number of samples between the starts of consecutive frames
+    pub hop: usize,
+    pub window: WindowFunction,
+    pub scale: FrequencyScale,
+    pub color_map: ColorMap,
+    /// level in dB shown as the bottom of the colour map
+    pub min_db: f32,
+}
+
+impl Default for SpectrogramSettings {
+    fn default() -> Self {
+        Self {
+            fft_size: 2048,
+            hop: 512,
+            window: WindowFunction::Hann,
+            scale: FrequencyScale::Log,
+            color_map: ColorMap::Magma,
+            min_db: -100.0,
+        }
+    }
+}
+
+/// Identifies a tile of one channel at one zoom level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub channel: usize,
+    /// zoom level, as a power of two samples per column
+    pub level: i32,
+    /// index of the tile from the start of the file
+    pub index: usize,
+}
+
+/// the zoom level to draw at `samples_per_pixel`
+pub fn zoom_level(samples_per_pixel: f32) -> i32 {
+    (samples_per_pixel.log2().floor() as i32).max(MIN_LEVEL)
+}
+
+/// the number of samples covered by one column at a zoom level
+pub fn level_samples_per_column(level: i32) -> f64 {
+    2f64.powi(level)
+}
+
+/// A request to render a tile
+pub struct TileRequest {
+    pub file: Shared<AudioFile>,
+    pub key: TileKey,
+    pub settings: SpectrogramSettings,
+    pub generation: usize,
+}
+
+/// A rendered tile, `TILE_WIDTH` by `TILE_HEIGHT` pixels with the highest frequency in the top row
+pub struct RenderedTile {
+    pub key: TileKey,
+    pub generation: usize,
+    pub pixels: Vec<RGBA8>,
+}
+
+/// Renders spectrogram tiles on a worker thread. Requests made before the last call
+/// to `invalidate` are skipped by the worker and their results should be discarded.
+pub struct SpectrogramRenderer {
+    tx: Sender<TileRequest>,
+    rx: Receiver<RenderedTile>,
+    generation: Arc<AtomicUsize>,
+}
+
+impl SpectrogramRenderer {
+    /// start the worker thread, which stops when the renderer is dropped
+    pub fn new() -> Self {
+        let (request_tx, request_rx) = channel::<TileRequest>();
+        let (tile_tx, tile_rx) = channel();
+        let generation = Arc::new(AtomicUsize::new(0));
+
+        let thread_generation = generation.clone();
+        thread::spawn(move || {
+            let mut planner = FftPlanner::new();
+            for request in request_rx {
+                if request.generation != thread_generation.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                let pixels = render_tile(&request.file, request.key, &request.settings, &mut planner);
+                let tile = RenderedTile {
+                    key: request.key,
+                    generation: request.generation,
+                    pixels,
+                };
+                if tile_tx.send(tile).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            tx: request_tx,
+            rx: tile_rx,
+            generation,
+        }
+    }
+
+    /// the current generation, to be sent with requests
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// discard all outstanding requests, after the file or settings have changed
+    pub fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn request(&self, request: TileRequest) {
+        let _ = self.tx.send(request);
+    }
+
+    /// return the next rendered tile of the current generation, without blocking
+    pub fn try_recv(&self) -> Option<RenderedTile> {
+        while let Ok(tile) = self.rx.try_recv() {
+            if tile.generation == self.generation() {
+                return Some(tile);
+            }
+        }
+        None
+    }
+}
+
+// Compute the pixels of a tile
+fn render_tile(
+    file: &AudioFile,
+    key: TileKey,
+    settings: &SpectrogramSettings,
+    planner: &mut FftPlanner<f32>,
+) -> Vec<RGBA8> {
+    let fft_size = settings.fft_size;
+    let hop = settings.hop.max(1) as f64;
+    let fft = planner.plan_fft_forward(fft_size);
+    let window = settings.window.coefficients(fft_size);
+
+    // Scale so that a full scale sine reads 0 dB
+    let window_sum: f32 = window.iter().sum();
+    let scale = 2.0 / window_sum.max(std::f32::EPSILON);
+
+    // The range of FFT bins shown in each row
+    let nyquist = (file.sample_rate / 2.0) as f32;
+    let bin_of = |position: f32| {
+        settings.scale.frequency(position, nyquist) / nyquist * (fft_size / 2) as f32
+    };
+    let rows: Vec<(usize, usize)> = (0..TILE_HEIGHT)
+        .map(|row| {
+            let low = bin_of(row as f32 / TILE_HEIGHT as f32);
+            let high = bin_of((row + 1) as f32 / TILE_HEIGHT as f32);
+            let first = (low.round() as usize).min(fft_size / 2);
+            let last = (high.round() as usize).max(first + 1).min(fft_size / 2 + 1);
+            (first, last)
+        })
+        .collect();
+
+    let samples_per_column = level_samples_per_column(key.level);
+    let mut pixels = vec![RGBA8::new(0, 0, 0, 255); TILE_WIDTH * TILE_HEIGHT];
+    let mut frame = vec![0.0; fft_size];
+    let mut buffer = vec![Complex::new(0.0, 0.0); fft_size];
+    let mut magnitudes = vec![0.0f32; fft_size / 2 + 1];
+    let mut column_magnitudes = vec![0.0f32; fft_size / 2 + 1];
+
+    for column in 0..TILE_WIDTH {
+        let start = (key.index * TILE_WIDTH + column) as f64 * samples_per_column;
+        if start >= file.num_samples as f64 {
+            break;
+        }
+        let end = start + samples_per_column;
+
+        // The frames centred in this column, or the nearest one if there are none
+        let first_frame = (start / hop).ceil() as usize;
+        let last_frame = (end / hop).ceil() as usize;
+        let frames: Vec<usize> = if last_frame <= first_frame {
+            vec![((start + end) / 2.0 / hop).round() as usize]
+        } else {
+            let num_frames = last_frame - first_frame;
+            let step = (num_frames + MAX_FRAMES_PER_COLUMN - 1) / MAX_FRAMES_PER_COLUMN;
+            (first_frame..last_frame).step_by(step).collect()
+        };
+
+        column_magnitudes.iter_mut().for_each(|magnitude| *magnitude = 0.0);
+        for frame_index in frames {
+            let centre = (frame_index as f64 * hop) as usize;
+            read_frame(file, key.channel, centre, &mut frame);
+
+            for ((value, sample), coefficient) in buffer.iter_mut().zip(frame.iter()).zip(window.iter()) {
+                *value = Complex::new(sample * coefficient, 0.0);
+            }
+            fft.process(&mut buffer);
+
+            for (magnitude, value) in magnitudes.iter_mut().zip(buffer.iter()) {
+                *magnitude = value.norm() * scale;
+            }
+            for (column_magnitude, magnitude) in column_magnitudes.iter_mut().zip(magnitudes.iter()) {
+                *column_magnitude = column_magnitude.max(*magnitude);
+            }
+        }
+
+        for (row, (first, last)) in rows.iter().enumerate() {
+            let magnitude = column_magnitudes[*first..*last]
+                .iter()
+                .fold(0.0f32, |max, magnitude| max.max(*magnitude));
+            let db = 20.0 * magnitude.max(1e-10).log10();
+            let value = 1.0 - db / settings.min_db;
+            pixels[(TILE_HEIGHT - 1 - row) * TILE_WIDTH + column] = settings.color_map.color(value);
+        }
+    }
+
+    pixels
+}
+
+/// read the frame of samples centred on `centre`, padding with silence outside the file
+pub fn read_frame(file: &AudioFile, channel: usize, centre: usize, frame: &mut [f32]) {
+    frame.iter_mut().for_each(|sample| *sample = 0.0);
+    let half = frame.len() / 2;
+    if centre >= half {
+        file.read(channel, centre - half, frame);
+    } else {
+        file.read(channel, 0, &mut frame[half - centre..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::open_wav;
+
+    const SAMPLE_RATE: u32 = 48000;
+    // The centre of FFT bin 64 of the default 2048 sample frame, so that none of it leaks
+    const FREQUENCY: f32 = 1500.0;
+
+    /// Render the first tile of a mono sine of `amplitude` in grayscale, where the
+    /// brightness of a pixel is proportional to its level above `min_db`
+    fn render(name: &str, amplitude: f32, scale: FrequencyScale) -> Vec<RGBA8> {
+        let samples: Vec<f32> = (0..SAMPLE_RATE as usize / 4)
+            .map(|idx| {
+                let t = idx as f32 / SAMPLE_RATE as f32;
+                amplitude * (2.0 * std::f32::consts::PI * FREQUENCY * t).sin()
+            })
+            .collect();
+        let file = open_wav(&format!("spectrogram_{}.wav", name), 1, SAMPLE_RATE, &samples);
+        let settings = SpectrogramSettings {
+            scale,
+            color_map: ColorMap::Grayscale,
+            ..Default::default()
+        };
+        let key = TileKey {
+            channel: 0,
+            level: 5,
+            index: 0,
+        };
+        render_tile(&file, key, &settings, &mut FftPlanner::new())
+    }
+
+    /// the row counted from the bottom, and the brightness, of the brightest pixel in `column`
+    fn brightest(pixels: &[RGBA8], column: usize) -> (usize, u8) {
+        (0..TILE_HEIGHT)
+            .map(|row| (row, pixels[(TILE_HEIGHT - 1 - row) * TILE_WIDTH + column].r))
+            .fold((0, 0), |best, pixel| if pixel.1 > best.1 { pixel } else { best })
+    }
+
+    #[test]
+    fn sine_lands_in_its_row_on_each_scale() {
+        let nyquist = SAMPLE_RATE as f32 / 2.0;
+        for scale in [FrequencyScale::Linear, FrequencyScale::Log, FrequencyScale::Mel].iter() {
+            let pixels = render(&format!("{:?}", scale), 1.0, *scale);
+            let expected = (0..TILE_HEIGHT)
+                .find(|row| scale.frequency((row + 1) as f32 / TILE_HEIGHT as f32, nyquist) > FREQUENCY)
+                .unwrap();
+            for column in [64, 128, 200].iter() {
+                let (row, _) = brightest(&pixels, *column);
+                assert!(
+                    (row as isize - expected as isize).abs() <= 1,
+                    "{:?}: column {} peaks in row {}, not {}",
+                    scale,
+                    column,
+                    row,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn full_scale_sine_reads_0_db() {
+        let min_db = SpectrogramSettings::default().min_db;
+        for (amplitude, db) in [(1.0, 0.0), (0.1, -20.0), (0.01, -40.0)].iter() {
+            let pixels = render(&format!("level{}", db), *amplitude, FrequencyScale::Linear);
+            let (_, brightness) = brightest(&pixels, 128);
+            let expected = (1.0 - db / min_db) * 255.0;
+            assert!(
+                (brightness as f32 - expected).abs() <= 2.0,
+                "{} dB: brightness {}, not {}",
+                db,
+                brightness,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Magnitude spectra for the spectrum analyser: the average over a region of a
+//! file, and a single frame at the playhead.
+
+use crate::analysis::Progress;
+use crate::audio_file::AudioFile;
+use crate::spectrogram::{read_frame, WindowFunction};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Number of samples in each frame of the spectrum analyser
+pub const SPECTRUM_FFT_SIZE: usize = 4096;
+
+/// Level in dB given to bins with no energy
+pub const SPECTRUM_FLOOR_DB: f32 = -144.0;
+
+// Long regions are averaged over at most this many frames, evenly spaced
+const MAX_FRAMES: usize = 256;
+
+/// Computes magnitude spectra, in dB relative to a full scale sine, with a Hann window
+pub struct SpectrumAnalyser {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    frame: Vec<f32>,
+}
+
+impl SpectrumAnalyser {
+    pub fn new() -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(SPECTRUM_FFT_SIZE);
+        Self {
+            fft,
+            window: WindowFunction::Hann.coefficients(SPECTRUM_FFT_SIZE),
+            buffer: vec![Complex::new(0.0, 0.0); SPECTRUM_FFT_SIZE],
+            frame: vec![0.0; SPECTRUM_FFT_SIZE],
+        }
+    }
+
+    /// the average spectrum of a channel between samples `start` and `end`, one value
+    /// per bin. Frames overlap by half; a region shorter than a frame is zero padded
+    pub fn average(&mut self, file: &AudioFile, channel: usize, start: usize, end: usize) -> Vec<f32> {
+        let end = end.min(file.num_samples);
+        let len = end.saturating_sub(start);
+        let mut power = vec![0.0; SPECTRUM_FFT_SIZE / 2 + 1];
+        if len == 0 {
+            return to_db(&power, 1);
+        }
+
+        if len < SPECTRUM_FFT_SIZE {
+            let window = WindowFunction::Hann.coefficients(len);
+            self.frame.iter_mut().for_each(|sample| *sample = 0.0);
+            file.read(channel, start, &mut self.frame[..len]);
+            accumulate(&*self.fft, &self.frame[..len], &window, &mut self.buffer, &mut power);
+            return to_db(&power, 1);
+        }
+
+        let hop = SPECTRUM_FFT_SIZE / 2;
+        let num_frames = (len - SPECTRUM_FFT_SIZE) / hop + 1;
+        let step = (num_frames + MAX_FRAMES - 1) / MAX_FRAMES;
+        let mut count = 0;
+        for frame_index in (0..num_frames).step_by(step) {
+            file.read(channel, start + frame_index * hop, &mut self.frame);
+            accumulate(&*self.fft, &self.frame, &self.window, &mut self.buffer, &mut power);
+            count += 1;
+        }
+        to_db(&power, count)
+    }
+
+    /// the spectrum of the frame of a channel centred on sample `centre`
+    pub fn frame(&mut self, file: &AudioFile, channel: usize, centre: usize) -> Vec<f32> {
+        let mut power = vec![0.0; SPECTRUM_FFT_SIZE / 2 + 1];
+        read_frame(file, channel, centre, &mut self.frame);
+        accumulate(&*self.fft, &self.frame, &self.window, &mut self.buffer, &mut power);
+        to_db(&power, 1)
+    }
+}
+
+/// the average spectrum of each channel between samples `start` and `end`, for running
+/// as an `Analysis`. Returns `None` if it was cancelled
+pub fn analyse(file: &AudioFile, start: usize, end: usize, progress: &Progress) -> Option<Vec<Vec<f32>>> {
+    let mut analyser = SpectrumAnalyser::new();
+    let mut spectra = Vec::with_capacity(file.num_channels);
+    for channel in 0..file.num_channels {
+        if !progress.update(channel as f32 / file.num_channels as f32) {
+            return None;
+        }
+        spectra.push(analyser.average(file, channel, start, end));
+    }
+    Some(spectra)
+}
+
+/// the centre frequency of a bin
+pub fn bin_frequency(bin: usize, sample_rate: f64) -> f32 {
+    (bin as f64 * sample_rate / SPECTRUM_FFT_SIZE as f64) as f32
+}
+
+/// the bin with the highest level between bins `first` and `last`, inclusive
+pub fn peak_bin(spectrum: &[f32], first: usize, last: usize) -> Option<usize> {
+    let last = last.min(spectrum.len().checked_sub(1)?);
+    (first..=last).max_by(|a, b| spectrum[*a].partial_cmp(&spectrum[*b]).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+// Add the power of each bin of a windowed frame to `power`. The frame may be shorter
+// than the FFT, in which case it is zero padded after windowing
+fn accumulate(
+    fft: &dyn Fft<f32>,
+    frame: &[f32],
+    window: &[f32],
+    buffer: &mut [Complex<f32>],
+    power: &mut [f32],
+) {
+    // Scale so that a full scale sine reads 0 dB
+    let window_sum: f32 = window.iter().sum();
+    let scale = 2.0 / window_sum.max(std::f32::EPSILON);
+
+    for (idx, value) in buffer.iter_mut().enumerate() {
+        let sample = match (frame.get(idx), window.get(idx)) {
+            (Some(sample), Some(coefficient)) => sample * coefficient,
+            _ => 0.0,
+        };
+        *value = Complex::new(sample, 0.0);
+    }
+    fft.process(buffer);
+
+    for (bin_power, value) in power.iter_mut().zip(buffer.iter()) {
+        let magnitude = value.norm() * scale;
+        *bin_power += magnitude * magnitude;
+    }
+}
+
+fn to_db(power: &[f32], count: usize) -> Vec<f32> {
+    power
+        .iter()
+        .map(|bin_power| {
+            let mean = bin_power / count.max(1) as f32;
+            if mean > 0.0 {
+                (10.0 * mean.log10()).max(SPECTRUM_FLOOR_DB)
+            } else {
+                SPECTRUM_FLOOR_DB
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,40 @@
+//! Temporary WAV files for the unit tests. Each test names its files after its
+//! module, so that tests running in parallel never share a file.
+
+use crate::audio_file::AudioFile;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::path::{Path, PathBuf};
+
+/// a path in the temp directory for the file `name`, unique to this test process
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("wav_viewer_{}_{}", std::process::id(), name))
+}
+
+/// the spec of a 32 bit float file
+pub fn float_spec(num_channels: usize, sample_rate: u32) -> WavSpec {
+    WavSpec {
+        channels: num_channels as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    }
+}
+
+/// write interleaved `samples` to a new file at `path`
+pub fn write_wav<S: hound::Sample + Copy>(path: &Path, spec: WavSpec, samples: &[S]) {
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for sample in samples {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// write interleaved float `samples` to a temporary file named `name`, open it and
+/// remove the file again
+pub fn open_wav(name: &str, num_channels: usize, sample_rate: u32, samples: &[f32]) -> AudioFile {
+    let path = temp_path(name);
+    write_wav(&path, float_spec(num_channels, sample_rate), samples);
+    let file = AudioFile::open(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    file
+}
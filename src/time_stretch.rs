@@ -0,0 +1,271 @@
+//! Changing the speed of playback without changing its pitch, by WSOLA (waveform
+//! similarity overlap-add). The output is built from overlapping Hann-windowed frames
+//! of the file, taken at the playback rate but laid down at the device rate. Each frame
+//! is moved, within a small tolerance, to where it best matches the audio that follows
+//! the last frame, so that the overlaps add up without cancelling. Every channel uses
+//! the same frames, so that the stereo image stays in place.
+
+use crate::audio_file::AudioFile;
+use crate::resampler::Resampler;
+
+// Length of a frame, and how far a frame may be moved to match the last one, in seconds
+const FRAME_SECONDS: f64 = 0.04;
+const TOLERANCE_SECONDS: f64 = 0.008;
+
+// Every nth sample is compared when matching frames
+const MATCH_STRIDE: usize = 2;
+
+/// Longest device buffer that playback allocates for up front. Longer ones still play,
+/// but grow the buffers on the audio thread
+pub const MAX_BUFFER_LEN: usize = 8192;
+
+pub struct TimeStretch {
+    // In samples at the device rate, with frames overlapping by half
+    frame_len: usize,
+    tolerance: usize,
+    sample_rate: f64,
+    window: Vec<f32>,
+    // Position in the file of the next frame before it is moved
+    next_position: f64,
+    // Position in the file of the second half of the last frame
+    continuation: Option<f64>,
+    // The part of the file each frame is chosen from, for each channel
+    source: Vec<Vec<f32>>,
+    // The audio following the last frame, and the sums of the channels, for matching
+    target: Vec<f32>,
+    source_mix: Vec<f32>,
+    target_mix: Vec<f32>,
+    // The second half of the last frame, waiting to be added to the next
+    overlap: Vec<Vec<f32>>,
+    // Samples made but not yet played, and the samples of the last call to `process`
+    pending: Vec<Vec<f32>>,
+    output: Vec<Vec<f32>>,
+}
+
+impl TimeStretch {
+    /// allocate everything needed to stretch a file of `num_channels` played at the
+    /// device `sample_rate`, so that none of it is allocated on the audio thread
+    pub fn new(num_channels: usize, sample_rate: f64) -> Self {
+        // An even length, so that frames overlap by exactly half
+        let frame_len = (FRAME_SECONDS * sample_rate / 2.0).round() as usize * 2;
+        let tolerance = (TOLERANCE_SECONDS * sample_rate).round() as usize;
+        let hop = frame_len / 2;
+        let source_len = 2 * tolerance + frame_len;
+        let buffers = |capacity| (0..num_channels).map(|_| Vec::with_capacity(capacity)).collect();
+        Self {
+            frame_len,
+            tolerance,
+            sample_rate,
+            window: (0..frame_len)
+                .map(|idx| (0.5 - 0.5 * (2.0 * std::f64::consts::PI * idx as f64 / frame_len as f64).cos()) as f32)
+                .collect(),
+            next_position: 0.0,
+            continuation: None,
+            source: buffers(source_len),
+            target: Vec::with_capacity(hop),
+            source_mix: Vec::with_capacity(source_len),
+            target_mix: Vec::with_capacity(hop),
+            overlap: vec![vec![0.0; hop]; num_channels],
+            pending: buffers(MAX_BUFFER_LEN + hop),
+            output: buffers(MAX_BUFFER_LEN),
+        }
+    }
+
+    /// whether it was made for a file of `num_channels` played at `sample_rate`
+    pub fn fits(&self, num_channels: usize, sample_rate: f64) -> bool {
+        self.overlap.len() == num_channels && self.sample_rate == sample_rate
+    }
+
+    /// start again from a position in the file, as after a seek
+    pub fn reset(&mut self, position: f64) {
+        self.next_position = position;
+        self.continuation = None;
+        for overlap in self.overlap.iter_mut() {
+            overlap.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        for pending in self.pending.iter_mut() {
+            pending.clear();
+        }
+        for output in self.output.iter_mut() {
+            output.clear();
+        }
+    }
+
+    /// make `len` samples of each channel of `file` at the device rate it was made for,
+    /// reading `step` file samples for each device sample and moving through the file
+    /// `rate` times as fast. They can then be taken from `output`
+    pub fn process(&mut self, file: &AudioFile, resampler: &mut Resampler, step: f64, rate: f64, len: usize) {
+        debug_assert_eq!(file.num_channels, self.overlap.len());
+        while self.pending.first().map_or(false, |pending| pending.len() < len) {
+            self.add_frame(file, resampler, step, rate);
+        }
+
+        for (output, pending) in self.output.iter_mut().zip(self.pending.iter_mut()) {
+            output.clear();
+            output.extend(pending.drain(..len));
+        }
+    }
+
+    /// the samples of a channel made by the last call to `process`
+    pub fn output(&self, channel: usize) -> &[f32] {
+        &self.output[channel]
+    }
+
+    // Choose the next frame, add its first half to the last frame's second half, and keep
+    // its own second half for the frame after
+    fn add_frame(&mut self, file: &AudioFile, resampler: &mut Resampler, step: f64, rate: f64) {
+        let hop = self.frame_len / 2;
+        let num_channels = self.source.len();
+
+        // Frames may start up to `tolerance` samples either side of the position, but not before the file
+        let before = self.tolerance.min((self.next_position / step).floor() as usize);
+        let source_start = self.next_position - before as f64 * step;
+        let source_len = before + self.tolerance + self.frame_len;
+
+        self.source_mix.clear();
+        self.source_mix.resize(source_len, 0.0);
+        for (channel, source) in self.source.iter_mut().enumerate() {
+            source.clear();
+            source.resize(source_len, 0.0);
+            resampler.process(file, channel, source_start, step, source);
+            for (mix, sample) in self.source_mix.iter_mut().zip(source.iter()) {
+                *mix += sample;
+            }
+        }
+
+        // The first frame is taken where it is, and each one after is matched to the
+        // audio that carries on from the last
+        let offset = match self.continuation {
+            Some(continuation) => {
+                self.target_mix.clear();
+                self.target_mix.resize(hop, 0.0);
+                for channel in 0..num_channels {
+                    self.target.clear();
+                    self.target.resize(hop, 0.0);
+                    resampler.process(file, channel, continuation, step, &mut self.target);
+                    for (mix, sample) in self.target_mix.iter_mut().zip(self.target.iter()) {
+                        *mix += sample;
+                    }
+                }
+                self.best_match(hop, before + self.tolerance)
+            }
+            None => before,
+        };
+
+        for channel in 0..num_channels {
+            let frame = &self.source[channel][offset..offset + self.frame_len];
+            let overlap = &mut self.overlap[channel];
+            let pending = &mut self.pending[channel];
+            for idx in 0..hop {
+                pending.push(overlap[idx] + frame[idx] * self.window[idx]);
+                overlap[idx] = frame[hop + idx] * self.window[hop + idx];
+            }
+        }
+
+        let frame_position = source_start + offset as f64 * step;
+        self.continuation = Some(frame_position + hop as f64 * step);
+        self.next_position += hop as f64 * step * rate;
+    }
+
+    // The offset in the source, up to `last`, of the frame whose first `len` samples are most like the target
+    fn best_match(&self, len: usize, last: usize) -> usize {
+        let mut best = (0, f32::MIN);
+        for offset in 0..=last {
+            let candidate = &self.source_mix[offset..offset + len];
+            let (mut correlation, mut energy) = (0.0, 0.0);
+            for idx in (0..len).step_by(MATCH_STRIDE) {
+                correlation += candidate[idx] * self.target_mix[idx];
+                energy += candidate[idx] * candidate[idx];
+            }
+            let similarity = correlation / energy.max(f32::EPSILON).sqrt();
+            if similarity > best.1 {
+                best = (offset, similarity);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resampler::{Kernel, ResampleQuality};
+    use crate::test_util::open_wav;
+    use basedrop::{Collector, Shared};
+
+    const SAMPLE_RATE: f64 = 48000.0;
+    const FREQUENCY: f64 = 440.0;
+
+    /// Write a mono float file of a sine and open it
+    fn sine_file(name: &str, seconds: f64) -> AudioFile {
+        let samples: Vec<f32> = (0..(seconds * SAMPLE_RATE) as usize)
+            .map(|idx| {
+                let t = idx as f64 / SAMPLE_RATE;
+                (0.5 * (2.0 * std::f64::consts::PI * FREQUENCY * t).sin()) as f32
+            })
+            .collect();
+        open_wav(&format!("time_stretch_{}.wav", name), 1, SAMPLE_RATE as u32, &samples)
+    }
+
+    #[test]
+    fn keeps_pitch_and_length() {
+        let file = sine_file("sine", 6.0);
+        let collector = Collector::new();
+        let mut resampler = Resampler::new();
+        resampler.set_kernels(Shared::new(
+            &collector.handle(),
+            vec![Kernel::new(ResampleQuality::Sinc, 1.0)],
+        ));
+        let (step, len, buffers) = (1.0, 512, 150);
+
+        for rate in [0.5, 2.0].iter() {
+            let mut time_stretch = TimeStretch::new(1, SAMPLE_RATE);
+            let start = 1000.0;
+            time_stretch.reset(start);
+            let mut output = Vec::new();
+            for _ in 0..buffers {
+                time_stretch.process(&file, &mut resampler, step, *rate, len);
+                assert_eq!(time_stretch.output(0).len(), len);
+                output.extend_from_slice(time_stretch.output(0));
+            }
+
+            // Frames are read ahead of what is played by less than a frame
+            let played = rate * step * (len * buffers) as f64;
+            let read = time_stretch.next_position - start;
+            assert!(
+                read >= played && read <= played + time_stretch.frame_len as f64 * rate * step,
+                "rate {}: {} samples read for {} played",
+                rate,
+                read,
+                played
+            );
+
+            // The first frame fades in, and isn't counted
+            let counted = &output[time_stretch.frame_len..];
+            let crossings = counted
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count();
+            let frequency = crossings as f64 / 2.0 / (counted.len() as f64 / SAMPLE_RATE);
+            assert!(
+                (frequency / FREQUENCY - 1.0).abs() < 0.03,
+                "rate {}: {} Hz for {} Hz",
+                rate,
+                frequency,
+                FREQUENCY
+            );
+        }
+    }
+
+    #[test]
+    fn reset_clears_the_output() {
+        let file = sine_file("reset", 1.0);
+        let mut resampler = Resampler::new();
+        let mut time_stretch = TimeStretch::new(1, SAMPLE_RATE);
+        time_stretch.reset(0.0);
+        time_stretch.process(&file, &mut resampler, 1.0, 2.0, 256);
+        assert_eq!(time_stretch.output(0).len(), 256);
+        time_stretch.reset(100.0);
+        assert!(time_stretch.output(0).is_empty());
+    }
+}
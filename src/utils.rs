@@ -36,6 +36,22 @@ impl Flag {
     }
 }
 
+/// Return a display name for a channel, using speaker names for the common
+/// mono, stereo, 5.1 and 7.1 layouts and the channel number otherwise
+pub fn channel_label(idx: usize, num_channels: usize) -> String {
+    let names: &[&str] = match num_channels {
+        1 => &["M"],
+        2 => &["L", "R"],
+        6 => &["L", "R", "C", "LFE", "Ls", "Rs"],
+        8 => &["L", "R", "C", "LFE", "Ls", "Rs", "Lrs", "Rrs"],
+        _ => &[],
+    };
+    names
+        .get(idx)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| (idx + 1).to_string())
+}
+
 /// Interleave a buffer of samples into an output buffer.
 pub fn interleave<T: Copy>(input: &[T], output: &mut [T], num_channels: usize) {
     debug_assert_eq!(input.len(), output.len());
@@ -47,13 +63,21 @@ pub fn interleave<T: Copy>(input: &[T], output: &mut [T], num_channels: usize) {
     }
 }
 
-/// Deinterleave a buffer of samples into an output buffer
-pub fn deinterleave<T: Copy>(input: &[T], output: &mut [T], num_channels: usize) {
-    debug_assert_eq!(input.len(), output.len());
-    let num_samples = input.len() / num_channels;
-    for sm in 0..num_samples {
-        for ch in 0..num_channels {
-            output[ch * num_samples + sm] = input[sm * num_channels + ch];
+/// Quote and escape text as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
         }
     }
-}
\ No newline at end of file
+    quoted.push('"');
+    quoted
+}
@@ -1,7 +1,5 @@
 // Eventually this will be a widget dedicated to displaying waveforms
 
-use std::cmp::Ordering;
-
 pub fn to_u8(val: f32) -> u16 {
     (((val + 1.0) / 2.0) * std::u16::MAX as f32) as u16
 }
@@ -15,84 +13,447 @@ pub const SAMPLES_PER_PIXEL: [usize; 9] = [
     4410, 1764, 882, 441, 147, 49, 21, 9, 3
 ];
 
+/// Number of samples in a block of peaks built on its own. 8820 is the lowest
+/// common multiple of `SAMPLES_PER_PIXEL`, so a block always ends on a pixel boundary.
+pub const BLOCK_SAMPLES: usize = 8820 * 32;
+
+/// The min, max and rms of the samples covered by one pixel, encoded with `to_u8`
+pub type Peak = (u16, u16, u16);
+
+/// Running min, max and sum of squares for the pixel currently being built
+#[derive(Clone, Copy)]
+struct Accumulator {
+    min: f32,
+    max: f32,
+    sum_sq: f32,
+    count: usize,
+}
+
+impl Accumulator {
+    fn new() -> Self {
+        Self {
+            min: std::f32::MAX,
+            max: std::f32::MIN,
+            sum_sq: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, sample: f32) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.sum_sq += sample * sample;
+        self.count += 1;
+    }
+
+    fn peak(&self) -> Peak {
+        let v_mean = (self.sum_sq / self.count as f32).sqrt();
+        (to_u8(self.min), to_u8(self.max), to_u8(v_mean))
+    }
+}
+
+/// Min/max/rms peaks of one channel at each zoom level of `SAMPLES_PER_PIXEL`,
+/// plus a final level that fits the whole channel to the window width.
+/// Peaks for consecutive blocks of a file can be built separately and appended,
+/// so they can be drawn while the file loads.
 pub struct Waveform {
-    pub index: Vec<usize>,
-    pub data: Vec<(u16, u16, u16)>,
+    levels: Vec<Vec<Peak>>,
+    accumulators: Vec<Accumulator>,
+    /// number of samples pushed so far
+    pub num_samples: usize,
 }
 
 impl Waveform {
     pub fn new() -> Self {
         Self {
-            index: Vec::new(),
-            data: Vec::new(),
+            levels: vec![Vec::new(); SAMPLES_PER_PIXEL.len() + 1],
+            accumulators: vec![Accumulator::new(); SAMPLES_PER_PIXEL.len()],
+            num_samples: 0,
         }
     }
 
-    pub fn load(&mut self, audio: &[f32], num_of_pixels: usize)  {
-        self.data.clear();
-        self.index.clear();
-        for level in 0..SAMPLES_PER_PIXEL.len() + 1 {
-            self.index.push(self.data.len());
-            let samples_per_pixel = if level == SAMPLES_PER_PIXEL.len() {
-                audio.len() / num_of_pixels
-            } else {
-                SAMPLES_PER_PIXEL[level]
-            };
-
-            let chunks = audio.chunks(samples_per_pixel);
-            for chunk in chunks {
-                let v_min = *chunk
-                    .iter()
-                    .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                    .unwrap();
-                let v_max = *chunk
-                    .iter()
-                    .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                    .unwrap();
-                let v_mean: f32 = (chunk.iter().map(|s| s*s).sum::<f32>() / chunk.len() as f32).sqrt();
-                self.data.push((to_u8(v_min), to_u8(v_max), to_u8(v_mean)));
+    /// Create a waveform from previously built peaks, one `Vec` per entry in `SAMPLES_PER_PIXEL`.
+    /// Returns `None` unless every level has the right number of peaks for `num_samples`
+    pub fn from_levels(num_samples: usize, levels: Vec<Vec<Peak>>) -> Option<Self> {
+        if levels.len() != SAMPLES_PER_PIXEL.len() {
+            return None;
+        }
+
+        for (peaks, samples_per_pixel) in levels.iter().zip(SAMPLES_PER_PIXEL.iter()) {
+            let expected = (num_samples + samples_per_pixel - 1) / samples_per_pixel;
+            if peaks.len() != expected {
+                return None;
+            }
+        }
+
+        let mut waveform = Self::new();
+        for (level, peaks) in levels.into_iter().enumerate() {
+            waveform.levels[level] = peaks;
+        }
+        waveform.num_samples = num_samples;
+        Some(waveform)
+    }
+
+    /// Add the next block of samples, completing any pixels it fills
+    pub fn push(&mut self, audio: &[f32]) {
+        for (level, samples_per_pixel) in SAMPLES_PER_PIXEL.iter().enumerate() {
+            let accumulator = &mut self.accumulators[level];
+            let peaks = &mut self.levels[level];
+            for sample in audio {
+                accumulator.add(*sample);
+                if accumulator.count == *samples_per_pixel {
+                    peaks.push(accumulator.peak());
+                    *accumulator = Accumulator::new();
+                }
+            }
+        }
+        self.num_samples += audio.len();
+    }
+
+    /// Complete the partially filled pixel at the end of each level once all samples are pushed
+    pub fn finish(&mut self) {
+        for (level, accumulator) in self.accumulators.iter_mut().enumerate() {
+            if accumulator.count > 0 {
+                self.levels[level].push(accumulator.peak());
+                *accumulator = Accumulator::new();
             }
         }
     }
 
-    pub fn set_num_pixels(&mut self, audio: &[f32], num_of_pixels: usize) {
-        if num_of_pixels > 0 {
-            if let Some(last) = self.index.last() {
-                let samples_per_pixel = audio.len() / num_of_pixels;
-                let chunks = audio.chunks(samples_per_pixel);
-                for (idx, chunk) in chunks.enumerate() {
-                    let v_min = *chunk
-                        .iter()
-                        .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                        .unwrap();
-                    let v_max = *chunk
-                        .iter()
-                        .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
-                        .unwrap();
-                    let v_mean: f32 = (chunk.iter().map(|s| s*s).sum::<f32>() / chunk.len() as f32).sqrt();
-                    if last + idx < self.data.len() {
-                        self.data[last + idx] = (to_u8(v_min), to_u8(v_max), to_u8(v_mean))
-                    } else {
-                        self.data.push((to_u8(v_min), to_u8(v_max), to_u8(v_mean)));
+    /// Append the peaks of a block built from the samples that follow those already in
+    /// this waveform. Every previous block must have been `BLOCK_SAMPLES` long.
+    pub fn append(&mut self, block: &Waveform) {
+        debug_assert!(self.num_samples % BLOCK_SAMPLES == 0);
+        for level in 0..SAMPLES_PER_PIXEL.len() {
+            self.levels[level].extend_from_slice(&block.levels[level]);
+        }
+        self.num_samples += block.num_samples;
+    }
+
+    /// Drop the peaks of the samples from `num_samples` on, a multiple of `BLOCK_SAMPLES`,
+    /// so that the blocks built from there can be appended again
+    pub fn truncate(&mut self, num_samples: usize) {
+        debug_assert!(num_samples % BLOCK_SAMPLES == 0);
+        let num_samples = num_samples.min(self.num_samples);
+        for (peaks, spp) in self.levels.iter_mut().zip(SAMPLES_PER_PIXEL.iter()) {
+            peaks.truncate(num_samples / spp);
+        }
+        self.accumulators = vec![Accumulator::new(); SAMPLES_PER_PIXEL.len()];
+        self.num_samples = num_samples;
+    }
+
+    /// Rebuild the peaks of the samples from `start` to `end` after they have been edited,
+    /// keeping the peaks of every pixel outside that range. The waveform is resized to
+    /// `num_samples`, and `read` copies the samples from a position into a buffer,
+    /// returning the number copied
+    pub fn rebuild<F>(&mut self, start: usize, end: usize, num_samples: usize, mut read: F)
+    where
+        F: FnMut(usize, &mut [f32]) -> usize,
+    {
+        let end = end.min(num_samples);
+        let start = start.min(end);
+
+        // The samples of the whole pixels of each level that the range touches
+        let ranges: Vec<(usize, usize)> = SAMPLES_PER_PIXEL
+            .iter()
+            .map(|spp| (start / spp * spp, ((end + spp - 1) / spp * spp).min(num_samples)))
+            .collect();
+        for (peaks, spp) in self.levels.iter_mut().zip(SAMPLES_PER_PIXEL.iter()) {
+            peaks.resize((num_samples + spp - 1) / spp, (0, 0, 0));
+        }
+        self.num_samples = num_samples;
+
+        let read_start = ranges.iter().map(|range| range.0).min().unwrap_or(start);
+        let read_end = ranges.iter().map(|range| range.1).max().unwrap_or(end);
+        let mut accumulators = vec![Accumulator::new(); SAMPLES_PER_PIXEL.len()];
+        let mut buffer = vec![0.0; BLOCK_SAMPLES.min(read_end - read_start)];
+        let mut position = read_start;
+        while position < read_end {
+            let len = (read_end - position).min(buffer.len());
+            let len = read(position, &mut buffer[..len]);
+            if len == 0 {
+                break;
+            }
+
+            for (level, spp) in SAMPLES_PER_PIXEL.iter().enumerate() {
+                let (first, last) = ranges[level];
+                let accumulator = &mut accumulators[level];
+                for sample_idx in first.max(position)..last.min(position + len) {
+                    accumulator.add(buffer[sample_idx - position]);
+                    // The last pixel of the file may be short
+                    if accumulator.count == *spp || sample_idx + 1 == last {
+                        self.levels[level][sample_idx / spp] = accumulator.peak();
+                        *accumulator = Accumulator::new();
                     }
-                    
-                }                
-            }         
+                }
+            }
+            position += len;
+        }
+    }
+
+    /// Rebuild the level that fits `total_samples` into `num_of_pixels`
+    pub fn set_num_pixels(&mut self, total_samples: usize, num_of_pixels: usize) {
+        if num_of_pixels == 0 || total_samples == 0 {
+            return;
+        }
+
+        let samples_per_pixel = total_samples as f64 / num_of_pixels as f64;
+        let mut fit = std::mem::take(&mut self.levels[SAMPLES_PER_PIXEL.len()]);
+        self.peaks(0.0, samples_per_pixel, num_of_pixels, &mut fit);
+        self.levels[SAMPLES_PER_PIXEL.len()] = fit;
+    }
+
+    /// Fill `out` with the peaks of `num_of_pixels` pixels, each covering `samples_per_pixel`
+    /// samples from sample `start` on, merged from the level chosen by `level_for`
+    pub fn peaks(&self, start: f64, samples_per_pixel: f64, num_of_pixels: usize, out: &mut Vec<Peak>) {
+        let level = level_for(samples_per_pixel);
+        merge_level(
+            &self.levels[level],
+            SAMPLES_PER_PIXEL[level],
+            start,
+            samples_per_pixel,
+            num_of_pixels,
+            out,
+        );
+    }
+
+    pub fn get_data(&self, level: usize) -> &[Peak] {
+        &self.levels[level]
+    }
+}
+
+/// the level of `SAMPLES_PER_PIXEL` that pixels of `samples_per_pixel` are merged from:
+/// the coarsest that is at least as detailed, or the most detailed when none is
+pub fn level_for(samples_per_pixel: f64) -> usize {
+    SAMPLES_PER_PIXEL
+        .iter()
+        .position(|spp| *spp as f64 <= samples_per_pixel)
+        .unwrap_or(SAMPLES_PER_PIXEL.len() - 1)
+}
+
+/// Fill `out` with the peaks of `num_of_pixels` pixels, each covering `samples_per_pixel`
+/// samples from sample `start` on, by merging `source`, the peaks of a level of
+/// `level_samples_per_pixel` samples each
+pub fn merge_level(
+    source: &[Peak],
+    level_samples_per_pixel: usize,
+    start: f64,
+    samples_per_pixel: f64,
+    num_of_pixels: usize,
+    out: &mut Vec<Peak>,
+) {
+    out.clear();
+
+    let level_samples_per_pixel = level_samples_per_pixel as f64;
+    for pixel in 0..num_of_pixels {
+        let first_sample = start + pixel as f64 * samples_per_pixel;
+        let first = (first_sample / level_samples_per_pixel) as usize;
+        let last = (((first_sample + samples_per_pixel) / level_samples_per_pixel) as usize)
+            .max(first + 1)
+            .min(source.len());
+        if first >= last {
+            break;
         }
+        out.push(merge_peaks(&source[first..last]));
+    }
+}
+
+/// Fill `out` with the peaks of pixels covering `samples_per_pixel` samples each,
+/// computed directly from `samples`. Used when zoomed in past the most detailed level.
+pub fn sample_peaks(samples: &[f32], samples_per_pixel: f64, out: &mut Vec<Peak>) {
+    out.clear();
 
+    let mut pixel = 0;
+    loop {
+        let first = (pixel as f64 * samples_per_pixel) as usize;
+        let last = (((pixel + 1) as f64 * samples_per_pixel) as usize)
+            .max(first + 1)
+            .min(samples.len());
+        if first >= last {
+            break;
+        }
+
+        let mut accumulator = Accumulator::new();
+        for sample in &samples[first..last] {
+            accumulator.add(*sample);
+        }
+        out.push(accumulator.peak());
+        pixel += 1;
     }
+}
+
+/// Combine consecutive peaks into one covering all of them
+fn merge_peaks(peaks: &[Peak]) -> Peak {
+    let v_min = peaks.iter().map(|peak| peak.0).min().unwrap_or(to_u8(0.0));
+    let v_max = peaks.iter().map(|peak| peak.1).max().unwrap_or(to_u8(0.0));
+    let mean_sq = peaks
+        .iter()
+        .map(|peak| to_f32(peak.2).powi(2))
+        .sum::<f32>()
+        / peaks.len() as f32;
+    (v_min, v_max, to_u8(mean_sq.sqrt()))
+}
 
-    pub fn get_data(&self, level: usize) -> &[(u16, u16, u16)] {
-        let index = self.index[level];
-        let next_index = if level < SAMPLES_PER_PIXEL.len() {
-            self.index[level+1]
+/// Number of samples either side of a point used by `interpolate_sinc`
+pub const SINC_HALF_WIDTH: usize = 16;
+
+/// Band-limited value of `samples` at the fractional index `pos`, using a
+/// Hann-windowed sinc kernel. Samples outside the slice are treated as silence.
+/// Unlike joining the samples with straight lines, this shows peaks that fall
+/// between samples.
+pub fn interpolate_sinc(samples: &[f32], pos: f64) -> f32 {
+    let center = pos.floor() as isize;
+    let half_width = SINC_HALF_WIDTH as isize;
+    let mut sum = 0.0;
+
+    for idx in (center - half_width + 1)..=(center + half_width) {
+        if idx < 0 || idx as usize >= samples.len() {
+            continue;
+        }
+
+        let x = pos - idx as f64;
+        let sinc = if x == 0.0 {
+            1.0
         } else {
-            self.data.len()
+            (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
         };
+        let window = 0.5 + 0.5 * (std::f64::consts::PI * x / SINC_HALF_WIDTH as f64).cos();
+        sum += samples[idx as usize] as f64 * sinc * window;
+    }
+
+    sum as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sine of `frequency` cycles per sample, well below the Nyquist frequency
+    fn sine(len: usize, frequency: f64) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f64::consts::PI * frequency * i as f64).sin() as f32)
+            .collect()
+    }
+
+    /// Deterministic noise under a decaying envelope, so that pixels differ
+    fn noise(len: usize) -> Vec<f32> {
+        let mut state = 12345u32;
+        (0..len)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let value = (state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0;
+                value * (-(i as f32) / len as f32 * 3.0).exp()
+            })
+            .collect()
+    }
 
-        //println!("level: {} index: {} next: {} {}", level, index, next_index, self.data[index..next_index-1].len());
+    // Pixel widths at 48 kHz, 96 kHz and 22.05 kHz that fall between the preset levels
+    const ZOOMS: [f64; 6] = [48000.0 / 25.0, 96000.0 / 100.0, 22050.0 / 30.0, 123.7, 10.5, 4.2];
 
-        return &self.data[index..next_index-1];
+    #[test]
+    fn level_for_picks_the_coarsest_detailed_enough_level() {
+        for samples_per_pixel in ZOOMS.iter().chain([2.0, 3.0, 4410.0, 10000.0].iter()) {
+            let level = level_for(*samples_per_pixel);
+            if *samples_per_pixel >= SAMPLES_PER_PIXEL[SAMPLES_PER_PIXEL.len() - 1] as f64 {
+                assert!(SAMPLES_PER_PIXEL[level] as f64 <= *samples_per_pixel);
+            } else {
+                assert_eq!(level, SAMPLES_PER_PIXEL.len() - 1);
+            }
+            if level > 0 {
+                assert!(SAMPLES_PER_PIXEL[level - 1] as f64 > *samples_per_pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn merged_peaks_match_the_samples_they_cover() {
+        let samples = noise(100_000);
+        let mut waveform = Waveform::new();
+        waveform.push(&samples);
+        waveform.finish();
+
+        let mut out = Vec::new();
+        for samples_per_pixel in ZOOMS.iter() {
+            let level = level_for(*samples_per_pixel);
+            let level_spp = SAMPLES_PER_PIXEL[level];
+            let start = 1234.5;
+            // Stop short of the last level pixel, which may be partly filled
+            let num_of_pixels = (((samples.len() - level_spp) as f64 - start) / samples_per_pixel) as usize;
+            let num_of_pixels = num_of_pixels.min(200);
+            waveform.peaks(start, *samples_per_pixel, num_of_pixels, &mut out);
+            assert_eq!(out.len(), num_of_pixels);
 
+            for (pixel, peak) in out.iter().enumerate() {
+                // A merged pixel covers the whole level pixels that its first and last sample fall in
+                let first_sample = start + pixel as f64 * samples_per_pixel;
+                let first = (first_sample / level_spp as f64) as usize * level_spp;
+                let last = ((first_sample + samples_per_pixel) / level_spp as f64) as usize * level_spp;
+                let last = last.max(first + level_spp);
+                assert!(first as f64 <= first_sample && first_sample - (first as f64) < level_spp as f64);
+                assert!((last as f64 - first_sample - samples_per_pixel).abs() <= level_spp as f64);
+
+                let covered = &samples[first..last];
+                let min = covered.iter().cloned().fold(f32::MAX, f32::min);
+                let max = covered.iter().cloned().fold(f32::MIN, f32::max);
+                let rms = (covered.iter().map(|s| s * s).sum::<f32>() / covered.len() as f32).sqrt();
+                let context = format!("{} samples per pixel, pixel {}", samples_per_pixel, pixel);
+                assert_eq!(peak.0, to_u8(min), "min at {}", context);
+                assert_eq!(peak.1, to_u8(max), "max at {}", context);
+                assert!((to_f32(peak.2) - rms).abs() < 1e-3, "rms at {}", context);
+            }
+        }
+    }
+
+    // Build a waveform block by block, as the loader does, from sample `start` of `samples`
+    fn append_blocks(waveform: &mut Waveform, samples: &[f32], start: usize) {
+        for block in samples[start..].chunks(BLOCK_SAMPLES) {
+            let mut peaks = Waveform::new();
+            peaks.push(block);
+            peaks.finish();
+            waveform.append(&peaks);
+        }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn truncated_blocks_are_appended_again() {
+        // An edit that lengthens the file from part way through its second block
+        let before = sine(BLOCK_SAMPLES * 3 + 1000, 0.001);
+        let mut after = before[..BLOCK_SAMPLES + 5000].to_vec();
+        after.extend(noise(BLOCK_SAMPLES * 2 + 7000));
+
+        let mut waveform = Waveform::new();
+        append_blocks(&mut waveform, &before, 0);
+        waveform.truncate(BLOCK_SAMPLES);
+        append_blocks(&mut waveform, &after, BLOCK_SAMPLES);
+
+        let mut expected = Waveform::new();
+        expected.push(&after);
+        expected.finish();
+        assert_eq!(waveform.num_samples, after.len());
+        for level in 0..SAMPLES_PER_PIXEL.len() {
+            assert_eq!(waveform.get_data(level), expected.get_data(level), "level {}", level);
+        }
+    }
+
+    #[test]
+    fn sinc_passes_through_the_samples() {
+        let samples = sine(256, 0.1);
+        for (i, sample) in samples.iter().enumerate() {
+            let value = interpolate_sinc(&samples, i as f64);
+            assert!((value - sample).abs() < 1e-6, "at {}: {} != {}", i, value, sample);
+        }
+    }
+
+    #[test]
+    fn sinc_follows_a_band_limited_sine_between_samples() {
+        let frequency = 0.1;
+        let samples = sine(256, frequency);
+        // Away from the ends, where the kernel runs past the samples
+        for step in 0..400 {
+            let pos = 32.0 + step as f64 * 0.47;
+            let expected = (2.0 * std::f64::consts::PI * frequency * pos).sin() as f32;
+            let value = interpolate_sinc(&samples, pos);
+            assert!((value - expected).abs() < 0.01, "at {}: {} != {}", pos, value, expected);
+        }
+    }
+}